@@ -0,0 +1,66 @@
+/*
+
+    This script writes files atomically: the new contents go to a temporary file next to the file, which is flushed to disk and then renamed over the old file. A crash while writing leaves either the old file or the new one, never half of one, and a file that is memory mapped is never written over.
+
+    It is used by everything that saves to disk: the crawl's graph and history, the compact graph, the url lookup table, the ranks, and the vector indexes.
+
+*/
+
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub fn write_atomic(path: &str, bytes: &[u8]) {
+    /*
+
+        Write a file that is already in memory
+
+    */
+    write_atomic_with(path, |writer| writer.write_all(bytes));
+}
+
+pub fn write_atomic_with<F>(path: &str, write: F)
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+    /*
+
+        Write a file a piece at a time through a buffered writer, so that large files don't have to be built in memory first
+
+    */
+    let temporary_path = format!("{}.tmp", path);
+    let file = File::create(&temporary_path).expect("Unable to create temporary file");
+    let mut writer = BufWriter::new(file);
+    write(&mut writer).expect("Unable to write temporary file");
+    let file = writer.into_inner().expect("Unable to write temporary file");
+    file.sync_all().expect("Unable to flush temporary file to disk");
+    drop(file);
+
+    fs::rename(&temporary_path, path).expect("Unable to replace file with temporary file");
+
+    // The rename itself is only durable once the directory is flushed. Not every platform can open a directory, so this is skipped if it can't be.
+    let directory = Path::new(path).parent().filter(|directory| !directory.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempPath;
+
+    #[test]
+    fn files_are_replaced_and_the_temporary_file_is_gone() {
+        let temp_path = TempPath::new("atomic_file.bin");
+        write_atomic(temp_path.path(), b"old contents");
+        write_atomic_with(temp_path.path(), |writer| {
+            writer.write_all(b"new ")?;
+            writer.write_all(b"contents")
+        });
+
+        assert_eq!(fs::read(temp_path.path()).unwrap(), b"new contents");
+        assert!(!Path::new(&format!("{}.tmp", temp_path.path())).exists());
+    }
+}
@@ -0,0 +1,439 @@
+/*
+
+    This is a local replacement for the remote service that the crawler sends images to, so that crawling and searching can run on one machine.
+
+    It serves the same contract as the remote service:
+
+        POST /upsert_image_url   {"image_url": "...", "page_url": "..."}   adds the image to the vector index, or replaces it if it was already added
+
+    and adds two more:
+
+        GET /search?q=<text>&k=<top_k>&host=<host>          the top_k images closest to the text, optionally only from one host
+        GET /search?image_url=<url>&k=<top_k>&host=<host>   the top_k images closest to another image
+        GET /stats                                          how many points the index has, and how many changes haven't been saved yet
+
+    The points are stored in the image index that the crawler writes when it embeds images itself (IMAGE_VECTOR_INDEX_PATH), unless another index is given when the server is started. Only one process can have an index open at a time, so the server won't start on an index that a crawl with embedding turned on is writing to, and the other way around. The index is loaded when the server starts, saved in the background once SAVE_EVERY upserts have built up, and saved again when the server is stopped with ctrl-c. The index is behind an async read-write lock: searches share it, and upserts and saving take it on their own. Saving runs on a blocking thread, so requests that come in while it is being saved wait for it without holding up the threads that serve requests, and the upsert that filled it up doesn't wait at all. Every upsert is written to the index's log before it is applied (see vector_search/wal.rs), so if the server dies in between saves, the upserts since the last save are replayed when it starts again. How often the log is flushed to disk is set with VECTOR_WAL_FSYNC.
+
+    Each image is downloaded with the crawler's fetch settings (see fetch.rs) and embedded with the embedding provider from the environment (see embedding.rs), the same way as when the crawler embeds images itself. Text queries are embedded as text, so they only find matching images when the model server puts text and images in the same space; the local provider doesn't, so with it, search by an example image instead. An image's id is a hash of its url, so upserting the same image again replaces it, even after the server is restarted.
+
+    Run with: cargo run --bin embedding_server [address] [index path]   (the default address is 127.0.0.1:8000)
+
+*/
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+use balene_search_engine::embedding::{self, EmbeddingError, EmbeddingProvider};
+use balene_search_engine::fetch::{self, FetchConfig, SkipReason};
+use balene_search_engine::url_host::{host_of, normalize_host};
+use balene_search_engine::vector_search::wal::{DurableIndex, FsyncPolicy};
+use balene_search_engine::vector_search::{self, url_point_id, DistanceMetric, FilterValue, PayloadField, PayloadFilter, PointId, PointVector, QueryVector, VectorPayload, VectorSearchClient};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8000";
+
+// how many upserts are kept in memory before the index is saved
+const SAVE_EVERY: usize = 100;
+
+// how often the background task checks whether the index needs to be saved
+const SAVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// how many results a search returns when k isn't given
+const DEFAULT_TOP_K: usize = 10;
+
+struct ServerState {
+    index: DurableIndex,
+    index_path: String,
+}
+
+#[derive(Deserialize)]
+struct UpsertImageRequest {
+    image_url: String,
+    page_url: String,
+}
+
+#[derive(Serialize)]
+struct UpsertImageResponse {
+    status: String,
+    id: PointId,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    id: PointId,
+    score: f32,
+    image_url: String,
+    page_url: String,
+    host: String,
+}
+
+#[derive(Serialize)]
+struct StatsResponse {
+    point_count: usize,
+    tombstone_count: usize,
+    dimension: usize,
+    unsaved_changes: usize,
+    index_path: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let address: SocketAddr = env::args().nth(1).unwrap_or(DEFAULT_ADDRESS.to_string()).parse().expect("Unable to parse server address");
+
+    let provider: Arc<dyn EmbeddingProvider> = Arc::from(embedding::provider_from_env());
+
+    let index_path = env::args().nth(2).unwrap_or(vector_search::IMAGE_VECTOR_INDEX_PATH.to_string());
+    let index = DurableIndex::open(&index_path, provider.dimension(), DistanceMetric::Cosine, FsyncPolicy::from_env());
+    println!("Loaded vector index {} with {} points", index_path, index.point_count());
+
+    let state = Arc::new(RwLock::new(ServerState { index, index_path }));
+    tokio::spawn(save_in_background(state.clone()));
+
+    let service_state = state.clone();
+    let make_service = make_service_fn(move |_| {
+        let state = service_state.clone();
+        let provider = provider.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle_request(request, state.clone(), provider.clone()))) }
+    });
+
+    println!("Embedding server listening on http://{}", address);
+    let server = Server::bind(&address).serve(make_service).with_graceful_shutdown(async {
+        tokio::signal::ctrl_c().await.expect("Unable to listen for ctrl-c");
+    });
+    if let Err(err) = server.await {
+        println!("Server error: {}", err);
+    }
+
+    // save whatever hasn't been saved before exiting
+    let mut state = state.write().await;
+    if state.index.unsaved_changes() > 0 {
+        state.index.checkpoint();
+    }
+}
+
+async fn save_in_background(state: Arc<RwLock<ServerState>>) {
+    /*
+
+        Save the index whenever SAVE_EVERY upserts have built up. Saving writes the whole index, so it runs on a blocking thread instead of in a request handler or on one of the threads that serve requests. Handlers wait for the lock asynchronously, so they don't hold up those threads while it is being saved.
+
+    */
+    let mut interval = tokio::time::interval(SAVE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        if state.read().await.index.unsaved_changes() < SAVE_EVERY {
+            continue;
+        }
+        let saved = tokio::task::spawn_blocking(move || {
+            let mut state = state.blocking_write();
+            if state.index.unsaved_changes() >= SAVE_EVERY {
+                state.index.checkpoint();
+            }
+        }).await;
+        if let Err(err) = saved {
+            println!("Error saving vector index: {}", err);
+        }
+    }
+}
+
+async fn handle_request(request: Request<Body>, state: Arc<RwLock<ServerState>>, provider: Arc<dyn EmbeddingProvider>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::POST, "/upsert_image_url") => {
+            let body = match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, &format!("Unable to read request body: {}", err))),
+            };
+            match serde_json::from_slice::<UpsertImageRequest>(&body) {
+                Ok(upsert) => upsert_image(&state, provider.as_ref(), upsert).await,
+                Err(err) => error_response(StatusCode::BAD_REQUEST, &format!("Invalid upsert request: {}", err)),
+            }
+        }
+        (&Method::GET, "/search") => search(&state, provider.as_ref(), request.uri().query().unwrap_or("")).await,
+        (&Method::GET, "/stats") => stats(&state).await,
+        _ => error_response(StatusCode::NOT_FOUND, "Not found"),
+    };
+    Ok(response)
+}
+
+async fn upsert_image(state: &RwLock<ServerState>, provider: &dyn EmbeddingProvider, upsert: UpsertImageRequest) -> Response<Body> {
+    let id = url_point_id(&upsert.image_url);
+    let (image, content_type) = match fetch_image(&upsert.image_url).await {
+        Ok(image) => image,
+        Err(response) => return response,
+    };
+    let vec = match embed_image(provider, image).await {
+        Ok(vec) => vec,
+        Err(response) => return response,
+    };
+    let point = PointVector {
+        vec,
+        payload: VectorPayload {
+            host: host_of(&upsert.page_url).unwrap_or_default(),
+            content_type,
+            crawl_date: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            page_url: upsert.page_url,
+            image_url: upsert.image_url,
+            ..Default::default()
+        },
+    };
+
+    state.write().await.index.upsert(id, point);
+
+    json_response(&UpsertImageResponse { status: "upserted".to_string(), id })
+}
+
+async fn search(state: &RwLock<ServerState>, provider: &dyn EmbeddingProvider, query_string: &str) -> Response<Body> {
+    // the query string is parsed as part of a url, so that it is percent decoded
+    let url = match reqwest::Url::parse(&format!("http://localhost/?{}", query_string)) {
+        Ok(url) => url,
+        Err(_) => return error_response(StatusCode::BAD_REQUEST, "Unable to parse query string"),
+    };
+    let mut text = String::new();
+    let mut image_url = String::new();
+    let mut top_k = DEFAULT_TOP_K;
+    let mut filter: Option<PayloadFilter> = None;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "q" => text = value.into_owned(),
+            "image_url" => image_url = value.into_owned(),
+            "k" => match value.parse::<usize>() {
+                Ok(k) => top_k = k,
+                Err(_) => return error_response(StatusCode::BAD_REQUEST, "k must be a number"),
+            },
+            "host" => filter = Some(PayloadFilter::Equals(PayloadField::Host, FilterValue::Text(normalize_host(&value)))),
+            _ => {}
+        }
+    }
+    let query = if !text.is_empty() {
+        embed_text(provider, text).await
+    } else if !image_url.is_empty() {
+        match fetch_image(&image_url).await {
+            Ok((image, _)) => embed_image(provider, image).await,
+            Err(response) => Err(response),
+        }
+    } else {
+        return error_response(StatusCode::BAD_REQUEST, "Missing query text q or query image image_url");
+    };
+    let query = match query {
+        Ok(vec) => QueryVector { vec },
+        Err(response) => return response,
+    };
+    let state = state.read().await;
+    let results: Vec<SearchResult> = state.index.search_filtered(&query, top_k, filter.as_ref()).into_iter().map(|result| SearchResult {
+        id: result.id,
+        score: result.score,
+        image_url: result.payload.image_url.clone(),
+        page_url: result.payload.page_url.clone(),
+        host: result.payload.host.clone(),
+    }).collect();
+
+    json_response(&results)
+}
+
+async fn stats(state: &RwLock<ServerState>) -> Response<Body> {
+    let state = state.read().await;
+    json_response(&StatsResponse {
+        point_count: state.index.point_count(),
+        tombstone_count: state.index.tombstone_count(),
+        dimension: state.index.dimension(),
+        unsaved_changes: state.index.unsaved_changes(),
+        index_path: state.index_path.clone(),
+    })
+}
+
+fn json_response<T: Serialize>(value: &T) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(value).unwrap()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let body = serde_json::json!({ "error": message }).to_string();
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn embed_text(provider: &dyn EmbeddingProvider, text: String) -> Result<Vec<f32>, Response<Body>> {
+    // the embedding is made before the index is locked, so that a slow model server doesn't hold up searches
+    let texts = vec![text];
+    match provider.embed_texts(&texts).await {
+        Ok(mut embeddings) => Ok(embeddings.remove(0)),
+        Err(err) => Err(error_response(StatusCode::BAD_GATEWAY, &err.to_string())),
+    }
+}
+
+async fn embed_image(provider: &dyn EmbeddingProvider, image: Vec<u8>) -> Result<Vec<f32>, Response<Body>> {
+    let images = vec![image];
+    match provider.embed_images(&images).await {
+        Ok(mut embeddings) => Ok(embeddings.remove(0)),
+        Err(EmbeddingError::UnsupportedImage(message)) => Err(error_response(StatusCode::UNPROCESSABLE_ENTITY, &format!("Unsupported image: {}", message))),
+        Err(err) => Err(error_response(StatusCode::BAD_GATEWAY, &err.to_string())),
+    }
+}
+
+async fn fetch_image(image_url: &str) -> Result<(Vec<u8>, String), Response<Body>> {
+    // Download an image the same way as the crawler does, and turn the reasons it can't be used into error responses
+    match fetch::fetch_image_bytes(image_url, &FetchConfig::default()).await {
+        Ok(Ok(image)) => Ok(image),
+        Ok(Err(reason @ SkipReason::HttpStatus(_))) => Err(error_response(StatusCode::BAD_GATEWAY, &format!("Unable to fetch image: {}", reason))),
+        Ok(Err(reason @ SkipReason::BodyTooLarge { .. })) => Err(error_response(StatusCode::PAYLOAD_TOO_LARGE, &format!("Image is too large: {}", reason))),
+        Ok(Err(reason @ SkipReason::UnsupportedContent(_))) => Err(error_response(StatusCode::UNPROCESSABLE_ENTITY, &format!("Not an image: {}", reason))),
+        Err(err) => Err(error_response(StatusCode::BAD_GATEWAY, &format!("Unable to fetch image: {}", err))),
+    }
+}
+
+#[cfg(test)]
+#[path = "../test_support.rs"]
+mod test_support;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use balene_search_engine::embedding::local::LocalEmbeddingProvider;
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use std::io::{BufRead, BufReader, Cursor, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use test_support::TempPath;
+
+    const DIMENSION: usize = 64;
+
+    fn png(image: RgbImage) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        DynamicImage::ImageRgb8(image).write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png).expect("Unable to encode test image");
+        bytes
+    }
+
+    fn image_server() -> String {
+        /*
+
+            A web server with two images, /sunset.png and /checkerboard.png, and a page, /page.html. Every other path is not found.
+
+        */
+        let sunset = png(RgbImage::from_fn(200, 120, |x, y| image::Rgb([255 - (y * 2) as u8, (x / 2) as u8, 40])));
+        let checkerboard = png(RgbImage::from_fn(200, 120, |x, y| if (x / 20 + y / 20) % 2 == 0 { image::Rgb([20, 40, 220]) } else { image::Rgb([240, 240, 240]) }));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let (status, content_type, body) = match request_line.split(' ').nth(1).unwrap_or("") {
+                    "/sunset.png" => ("200 OK", "image/png", sunset.clone()),
+                    "/checkerboard.png" => ("200 OK", "image/png", checkerboard.clone()),
+                    "/page.html" => ("200 OK", "text/html", b"<html></html>".to_vec()),
+                    _ => ("404 Not Found", "text/plain", b"not found".to_vec()),
+                };
+                let header = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content_type, body.len());
+                let mut stream = reader.into_inner();
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        base_url
+    }
+
+    fn server_state(index_path: &str) -> (Arc<RwLock<ServerState>>, Arc<dyn EmbeddingProvider>) {
+        let index = DurableIndex::open(index_path, DIMENSION, DistanceMetric::Cosine, FsyncPolicy::Never);
+        let state = Arc::new(RwLock::new(ServerState { index, index_path: index_path.to_string() }));
+        (state, Arc::new(LocalEmbeddingProvider::new(DIMENSION)))
+    }
+
+    async fn request(state: &Arc<RwLock<ServerState>>, provider: &Arc<dyn EmbeddingProvider>, method: Method, uri: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder().method(method).uri(uri).body(Body::from(body.to_string())).unwrap();
+        let response = handle_request(request, state.clone(), provider.clone()).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn upsert_body(image_url: &str, page_url: &str) -> String {
+        serde_json::json!({ "image_url": image_url, "page_url": page_url }).to_string()
+    }
+
+    #[tokio::test]
+    async fn upserted_images_can_be_searched_and_counted() {
+        let base_url = image_server();
+        let temp_path = TempPath::new("embedding_server_index.bin");
+        let (state, provider) = server_state(temp_path.path());
+        let sunset_url = format!("{}/sunset.png", base_url);
+        let checkerboard_url = format!("{}/checkerboard.png", base_url);
+
+        let (status, upserted) = request(&state, &provider, Method::POST, "/upsert_image_url", &upsert_body(&sunset_url, "https://www.example.com/sunsets")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(upserted["status"], "upserted");
+        assert_eq!(upserted["id"], url_point_id(&sunset_url));
+        let (status, _) = request(&state, &provider, Method::POST, "/upsert_image_url", &upsert_body(&checkerboard_url, "https://other.org/patterns")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // searching by an image finds that image first, along with the page it was on and its host
+        let (status, results) = request(&state, &provider, Method::GET, &format!("/search?image_url={}&k=1", sunset_url), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(results[0]["image_url"], sunset_url.as_str());
+        assert_eq!(results[0]["page_url"], "https://www.example.com/sunsets");
+        assert_eq!(results[0]["host"], "example.com");
+
+        // the host filter is normalized the same way as the hosts in the index
+        let (_, results) = request(&state, &provider, Method::GET, &format!("/search?image_url={}&host=WWW.Other.org", sunset_url), "").await;
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(results[0]["image_url"], checkerboard_url.as_str());
+        let (status, results) = request(&state, &provider, Method::GET, "/search?q=sunset&k=5", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results.as_array().unwrap().len(), 2);
+
+        // upserting the same image again replaces it
+        request(&state, &provider, Method::POST, "/upsert_image_url", &upsert_body(&sunset_url, "https://www.example.com/more_sunsets")).await;
+        let (status, stats) = request(&state, &provider, Method::GET, "/stats", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stats["point_count"], 2);
+        assert_eq!(stats["tombstone_count"], 1);
+        assert_eq!(stats["dimension"], DIMENSION);
+        assert_eq!(stats["unsaved_changes"], 3);
+        assert_eq!(stats["index_path"], temp_path.path());
+    }
+
+    #[tokio::test]
+    async fn bad_requests_get_error_responses() {
+        let base_url = image_server();
+        let temp_path = TempPath::new("embedding_server_errors.bin");
+        let (state, provider) = server_state(temp_path.path());
+
+        let (status, error) = request(&state, &provider, Method::GET, "/search?q=sunset&k=ten", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "k must be a number");
+        let (status, _) = request(&state, &provider, Method::GET, "/search?k=10", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = request(&state, &provider, Method::POST, "/upsert_image_url", "{\"image_url\": 5}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // images that can't be downloaded or aren't images
+        let (status, _) = request(&state, &provider, Method::POST, "/upsert_image_url", &upsert_body(&format!("{}/page.html", base_url), "https://example.com/")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = request(&state, &provider, Method::POST, "/upsert_image_url", &upsert_body(&format!("{}/missing.png", base_url), "https://example.com/")).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+        let (status, _) = request(&state, &provider, Method::GET, "/upsert_image_url", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(state.read().await.index.point_count(), 0);
+    }
+}
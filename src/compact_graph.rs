@@ -0,0 +1,462 @@
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::ops::Deref;
+
+use crate::atomic_file::write_atomic_with;
+use crate::web_graph::WebPageGraph;
+
+/*
+
+    Compact Graph:
+    WebPageGraph stores every edge as the full url of the page it links to, which makes a large crawl take up far more memory than the connections themselves need. CompactGraph stores the same graph with every url interned to a u32 id, and the edges kept in compressed sparse row (CSR) arrays, both forwards (outgoing links) and in reverse (incoming links).
+
+    The graph is kept in exactly the same byte layout in memory as it is on disk, so a saved graph can be memory mapped and used immediately, without deserializing anything. All of the numbers are little endian.
+
+        header:           magic (8 bytes), version: u32, node_count: u32, crawled_count: u32, padding: u32, edge_count: u64
+        forward_offsets:  (node_count + 1) x u64   outgoing links of node i are forward_targets[forward_offsets[i]..forward_offsets[i+1]]
+        forward_targets:  edge_count x u32
+        reverse_offsets:  (node_count + 1) x u64   incoming links of node i are reverse_sources[reverse_offsets[i]..reverse_offsets[i+1]]
+        reverse_sources:  edge_count x u32
+        url_offsets:      (node_count + 1) x u64   the url of node i is url_bytes[url_offsets[i]..url_offsets[i+1]]
+        sorted_ids:       node_count x u32         node ids sorted by url, used to look up the id of a url
+        url_bytes:        the utf-8 bytes of every url
+
+    Pages that were crawled get the ids 0..crawled_count. Pages that were only linked to, and never crawled, come after them.
+
+    The ranking commands in main.rs save the compact graph next to the crawl's graph (COMPACT_GRAPH_PATH), and memory map it instead of rebuilding it, until the crawl's graph changes. save_web_graph writes the sections straight into the file, so building it never holds a second copy of the graph in memory.
+
+*/
+
+// place where the compact version of the crawl's graph is stored
+pub const COMPACT_GRAPH_PATH: &str = "crawl_history/compact_graph_1.bin";
+
+const MAGIC: &[u8; 8] = b"BALGRAPH";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 32;
+
+pub struct URLInterner {
+    /*
+
+        Assigns each url a u32 id, in the order that they are first seen
+
+    */
+    ids: HashMap<String, u32>,
+    urls: Vec<String>,
+}
+
+impl URLInterner {
+    pub fn new() -> URLInterner {
+        URLInterner {
+            ids: HashMap::new(),
+            urls: Vec::new(),
+        }
+    }
+
+    pub fn intern(&mut self, url: &str) -> u32 {
+        if let Some(id) = self.ids.get(url) {
+            return *id;
+        }
+        let id = self.urls.len() as u32;
+        self.ids.insert(url.to_string(), id);
+        self.urls.push(url.to_string());
+        id
+    }
+
+    pub fn url(&self, id: u32) -> &str {
+        &self.urls[id as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.urls.len()
+    }
+}
+
+enum GraphBytes {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for GraphBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            GraphBytes::Owned(bytes) => bytes,
+            GraphBytes::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+struct GraphLayout {
+    /*
+
+        The sections of a compact graph before they are written out, either into memory or into a file
+
+    */
+    crawled_count: usize,
+    forward_offsets: Vec<u64>,
+    forward_targets: Vec<u32>,
+    reverse_offsets: Vec<u64>,
+    reverse_sources: Vec<u32>,
+    url_offsets: Vec<u64>,
+    sorted_ids: Vec<u32>,
+    url_bytes: Vec<u8>,
+}
+
+impl GraphLayout {
+    fn from_web_graph(graph: &WebPageGraph) -> GraphLayout {
+        /*
+
+            Intern every url in the web page graph, and lay out its edges as CSR arrays
+
+        */
+
+        // The crawled pages are interned first, in sorted order so that the ids are the same every time
+        let mut crawled_urls: Vec<&String> = graph.node_hashmap.keys().collect();
+        crawled_urls.sort();
+
+        let mut interner = URLInterner::new();
+        for url in crawled_urls.iter() {
+            interner.intern(url);
+        }
+        let crawled_count = interner.len();
+
+        // Then the forward edges, which also interns the pages that were linked to but never crawled
+        let mut forward_offsets: Vec<u64> = vec![0];
+        let mut forward_targets: Vec<u32> = Vec::new();
+        for url in crawled_urls.iter() {
+            for linked_url in graph.node_hashmap[*url].linked_urls.iter() {
+                forward_targets.push(interner.intern(linked_url));
+            }
+            forward_offsets.push(forward_targets.len() as u64);
+        }
+
+        // Pages that were never crawled have no outgoing links
+        let node_count = interner.len();
+        forward_offsets.resize(node_count + 1, forward_targets.len() as u64);
+
+        GraphLayout::from_csr(interner, crawled_count, forward_offsets, forward_targets)
+    }
+
+    fn from_csr(interner: URLInterner, crawled_count: usize, forward_offsets: Vec<u64>, forward_targets: Vec<u32>) -> GraphLayout {
+        /*
+
+            Build the reverse edges and the url table
+
+        */
+        let node_count = interner.len();
+        let edge_count = forward_targets.len();
+
+        // Transposing the CSR arrays: count the incoming edges of each node, turn the counts into offsets, and then place each edge
+        let mut reverse_offsets: Vec<u64> = vec![0; node_count + 1];
+        for target in forward_targets.iter() {
+            reverse_offsets[*target as usize + 1] += 1;
+        }
+        for i in 0..node_count {
+            reverse_offsets[i + 1] += reverse_offsets[i];
+        }
+        let mut next_slot: Vec<u64> = reverse_offsets[..node_count].to_vec();
+        let mut reverse_sources: Vec<u32> = vec![0; edge_count];
+        for source in 0..node_count {
+            for edge in forward_offsets[source]..forward_offsets[source + 1] {
+                let target = forward_targets[edge as usize] as usize;
+                reverse_sources[next_slot[target] as usize] = source as u32;
+                next_slot[target] += 1;
+            }
+        }
+
+        let mut url_offsets: Vec<u64> = vec![0];
+        let mut url_bytes: Vec<u8> = Vec::new();
+        for id in 0..node_count {
+            url_bytes.extend_from_slice(interner.url(id as u32).as_bytes());
+            url_offsets.push(url_bytes.len() as u64);
+        }
+
+        let mut sorted_ids: Vec<u32> = (0..node_count as u32).collect();
+        sorted_ids.sort_by(|a, b| interner.url(*a).cmp(interner.url(*b)));
+
+        GraphLayout {
+            crawled_count,
+            forward_offsets,
+            forward_targets,
+            reverse_offsets,
+            reverse_sources,
+            url_offsets,
+            sorted_ids,
+            url_bytes,
+        }
+    }
+
+    fn byte_len(&self) -> usize {
+        let node_count = self.sorted_ids.len();
+        let edge_count = self.forward_targets.len();
+        HEADER_LEN + 3 * 8 * (node_count + 1) + 2 * 4 * edge_count + 4 * node_count + self.url_bytes.len()
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        /*
+
+            Write the graph out in the on-disk layout
+
+        */
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.sorted_ids.len() as u32).to_le_bytes())?;
+        writer.write_all(&(self.crawled_count as u32).to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&(self.forward_targets.len() as u64).to_le_bytes())?;
+        for offset in self.forward_offsets.iter() { writer.write_all(&offset.to_le_bytes())?; }
+        for target in self.forward_targets.iter() { writer.write_all(&target.to_le_bytes())?; }
+        for offset in self.reverse_offsets.iter() { writer.write_all(&offset.to_le_bytes())?; }
+        for source in self.reverse_sources.iter() { writer.write_all(&source.to_le_bytes())?; }
+        for offset in self.url_offsets.iter() { writer.write_all(&offset.to_le_bytes())?; }
+        for id in self.sorted_ids.iter() { writer.write_all(&id.to_le_bytes())?; }
+        writer.write_all(&self.url_bytes)
+    }
+}
+
+pub struct CompactGraph {
+    bytes: GraphBytes,
+
+    node_count: usize,
+    crawled_count: usize,
+    edge_count: usize,
+
+    // byte positions of each of the sections
+    forward_offsets_start: usize,
+    forward_targets_start: usize,
+    reverse_offsets_start: usize,
+    reverse_sources_start: usize,
+    url_offsets_start: usize,
+    sorted_ids_start: usize,
+    url_bytes_start: usize,
+}
+
+impl CompactGraph {
+    pub fn from_web_graph(graph: &WebPageGraph) -> CompactGraph {
+        /*
+
+            Build the compact graph in memory, in the same layout as on disk
+
+        */
+        let layout = GraphLayout::from_web_graph(graph);
+        let mut bytes: Vec<u8> = Vec::with_capacity(layout.byte_len());
+        layout.write_to(&mut bytes).expect("Unable to lay out compact graph");
+        CompactGraph::from_bytes(GraphBytes::Owned(bytes))
+    }
+
+    pub fn save_web_graph(graph: &WebPageGraph, graph_path: &str) {
+        /*
+
+            Build the compact graph of a web page graph straight into a file, without laying out the whole graph in memory first. Load it with load_mmap.
+
+        */
+        let layout = GraphLayout::from_web_graph(graph);
+        // written atomically, since a graph that is memory mapped must never be written over
+        write_atomic_with(graph_path, |writer| layout.write_to(writer));
+
+        println!("Compact graph written to disk.")
+    }
+
+    fn from_bytes(bytes: GraphBytes) -> CompactGraph {
+        /*
+
+            Read the header, and work out where each of the sections starts
+
+        */
+        assert!(bytes.len() >= HEADER_LEN, "compact graph file is too short to contain a header");
+        assert_eq!(&bytes[0..8], MAGIC, "not a compact graph file");
+        let version = read_u32(&bytes, 8);
+        assert_eq!(version, VERSION, "unsupported compact graph version {}", version);
+
+        let node_count = read_u32(&bytes, 12) as usize;
+        let crawled_count = read_u32(&bytes, 16) as usize;
+        let edge_count = read_u64(&bytes, 24) as usize;
+
+        let forward_offsets_start = HEADER_LEN;
+        let forward_targets_start = forward_offsets_start + 8 * (node_count + 1);
+        let reverse_offsets_start = forward_targets_start + 4 * edge_count;
+        let reverse_sources_start = reverse_offsets_start + 8 * (node_count + 1);
+        let url_offsets_start = reverse_sources_start + 4 * edge_count;
+        let sorted_ids_start = url_offsets_start + 8 * (node_count + 1);
+        let url_bytes_start = sorted_ids_start + 4 * node_count;
+
+        assert!(bytes.len() >= url_bytes_start, "compact graph file is truncated");
+        let url_bytes_len = read_u64(&bytes, url_offsets_start + 8 * node_count) as usize;
+        assert_eq!(bytes.len(), url_bytes_start + url_bytes_len, "compact graph file is truncated");
+
+        CompactGraph {
+            bytes,
+            node_count,
+            crawled_count,
+            edge_count,
+            forward_offsets_start,
+            forward_targets_start,
+            reverse_offsets_start,
+            reverse_sources_start,
+            url_offsets_start,
+            sorted_ids_start,
+            url_bytes_start,
+        }
+    }
+
+    pub fn to_web_graph(&self) -> WebPageGraph {
+        /*
+
+            Convert back into a WebPageGraph. Only the pages that were crawled become nodes, the same as in the graph that this was built from.
+
+        */
+        let mut graph = WebPageGraph::new();
+        for id in 0..self.crawled_count as u32 {
+            let linked_urls: Vec<String> = self.outgoing(id).map(|target| self.url(target).to_string()).collect();
+            graph.insert_page(self.url(id), &linked_urls);
+        }
+        graph
+    }
+
+    pub fn load_mmap(graph_path: &str) -> CompactGraph {
+        /*
+
+            Memory map a saved compact graph. Nothing is read until it is used, so this returns right away, even for very large graphs.
+
+        */
+        let file = File::open(graph_path).expect("Unable to open compact graph file");
+
+        // The file must not be modified while it is mapped. Saving replaces the file instead of writing over it, so this stays valid even if the graph is saved again.
+        let mmap = unsafe { Mmap::map(&file) }.expect("Unable to memory map compact graph file");
+
+        CompactGraph::from_bytes(GraphBytes::Mapped(mmap))
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn crawled_count(&self) -> usize {
+        self.crawled_count
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edge_count
+    }
+
+    pub fn url(&self, id: u32) -> &str {
+        let start = self.url_bytes_start + read_u64(&self.bytes, self.url_offsets_start + 8 * id as usize) as usize;
+        let end = self.url_bytes_start + read_u64(&self.bytes, self.url_offsets_start + 8 * (id as usize + 1)) as usize;
+        std::str::from_utf8(&self.bytes[start..end]).expect("compact graph contains a url that is not valid utf-8")
+    }
+
+    pub fn id(&self, url: &str) -> Option<u32> {
+        /*
+
+            Find the id of a url, with a binary search over the ids sorted by url
+
+        */
+        let mut low = 0;
+        let mut high = self.node_count;
+        while low < high {
+            let middle = (low + high) / 2;
+            let id = read_u32(&self.bytes, self.sorted_ids_start + 4 * middle);
+            match self.url(id).cmp(url) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(id),
+            }
+        }
+        None
+    }
+
+    pub fn out_degree(&self, id: u32) -> usize {
+        let (start, end) = self.edge_range(self.forward_offsets_start, id);
+        end - start
+    }
+
+    pub fn in_degree(&self, id: u32) -> usize {
+        let (start, end) = self.edge_range(self.reverse_offsets_start, id);
+        end - start
+    }
+
+    pub fn outgoing(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        let (start, end) = self.edge_range(self.forward_offsets_start, id);
+        (start..end).map(move |edge| read_u32(&self.bytes, self.forward_targets_start + 4 * edge))
+    }
+
+    pub fn incoming(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        let (start, end) = self.edge_range(self.reverse_offsets_start, id);
+        (start..end).map(move |edge| read_u32(&self.bytes, self.reverse_sources_start + 4 * edge))
+    }
+
+    fn edge_range(&self, offsets_start: usize, id: u32) -> (usize, usize) {
+        let start = read_u64(&self.bytes, offsets_start + 8 * id as usize) as usize;
+        let end = read_u64(&self.bytes, offsets_start + 8 * (id as usize + 1)) as usize;
+        (start, end)
+    }
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(bytes[position..position + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempPath;
+
+    fn small_graph() -> WebPageGraph {
+        // a -> b, a -> c, b -> c, c -> a, and c -> d, where d was never crawled
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("a", &["b".to_string(), "c".to_string()]);
+        graph.insert_page("b", &["c".to_string()]);
+        graph.insert_page("c", &["a".to_string(), "d".to_string()]);
+        graph
+    }
+
+    fn links(graph: &WebPageGraph) -> Vec<(String, Vec<String>)> {
+        let mut links: Vec<(String, Vec<String>)> = graph.node_hashmap.values().map(|node| (node.url.clone(), node.linked_urls.clone())).collect();
+        links.sort();
+        links
+    }
+
+    #[test]
+    fn builds_forward_and_reverse_edges() {
+        let compact = CompactGraph::from_web_graph(&small_graph());
+        assert_eq!(compact.node_count(), 4);
+        assert_eq!(compact.crawled_count(), 3);
+        assert_eq!(compact.edge_count(), 5);
+
+        let id = |url: &str| compact.id(url).unwrap();
+        assert_eq!(compact.id("e"), None);
+        assert_eq!(compact.outgoing(id("a")).map(|target| compact.url(target)).collect::<Vec<&str>>(), vec!["b", "c"]);
+        let mut linking_to_c: Vec<&str> = compact.incoming(id("c")).map(|source| compact.url(source)).collect();
+        linking_to_c.sort();
+        assert_eq!(linking_to_c, vec!["a", "b"]);
+        assert_eq!(compact.out_degree(id("d")), 0);
+        assert_eq!(compact.in_degree(id("d")), 1);
+    }
+
+    #[test]
+    fn memory_mapped_graph_matches_saved_graph() {
+        let graph = small_graph();
+        let compact = CompactGraph::from_web_graph(&graph);
+        let graph_path = TempPath::new("compact_graph.bin");
+        CompactGraph::save_web_graph(&graph, graph_path.path());
+
+        let mapped = CompactGraph::load_mmap(graph_path.path());
+        assert_eq!(&mapped.bytes[..], &compact.bytes[..]);
+        assert_eq!(mapped.node_count(), compact.node_count());
+        assert_eq!(mapped.edge_count(), compact.edge_count());
+        for id in 0..compact.node_count() as u32 {
+            assert_eq!(mapped.url(id), compact.url(id));
+            assert_eq!(mapped.id(compact.url(id)), Some(id));
+            assert_eq!(mapped.outgoing(id).collect::<Vec<u32>>(), compact.outgoing(id).collect::<Vec<u32>>());
+            assert_eq!(mapped.incoming(id).collect::<Vec<u32>>(), compact.incoming(id).collect::<Vec<u32>>());
+        }
+        assert_eq!(links(&mapped.to_web_graph()), links(&graph));
+    }
+}
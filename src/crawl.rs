@@ -1,33 +1,47 @@
 use async_recursion::async_recursion;
-use reqwest;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::env;
 
+pub mod embed;
 pub mod parse;
 
+use embed::EmbeddingWriter;
+
+use crate::atomic_file::write_atomic;
+use crate::embedding;
+use crate::fetch::{self, ContentKind, FetchConfig, FetchResult, SkipReason};
+use crate::host_rank::{self, HostRank};
+use crate::web_graph::WebPageGraph;
+
+// place where the crawl data is stored
+pub const CRAWLER_PATH: &str = "crawl_history/crawl_1.bin";
+
+// place where the web page graph built by the crawler is stored
+pub const GRAPH_PATH: &str = "crawl_history/graph_1.bin";
+
+// how many pages are crawled between saves of the crawl history and the graph. Each save writes the whole graph, so saving after every page would make a crawl write O(n^2) bytes.
+const SAVE_EVERY_PAGES: usize = 100;
+
+// the server that images are sent to, which is the embedding server in src/bin/embedding_server.rs unless EMBEDDING_SERVER_URL says otherwise
+pub const DEFAULT_EMBEDDING_SERVER_URL: &str = "http://127.0.0.1:8000";
+pub const EMBEDDING_SERVER_URL_VAR: &str = "EMBEDDING_SERVER_URL";
 
 
 
 
-async fn fetch_html_content(url: &str) -> Result<String, reqwest::Error> {
-    /*
 
-        Make an HTTP request to get the HTML content for the given URL
-    
-    */
-    let response = reqwest::get(url).await?;
-    let html_content = response.text().await?;
-    Ok(html_content)
-}
 
 pub async fn send_image_url(image_url: &str, page_url: &str) -> Result<String, reqwest::Error> {
     let client = reqwest::Client::new();
 
-    let body_string = format!("{{\"image_url\": \"{}\", \"page_url\":\"{}\"}}", image_url, page_url);
+    // serde_json escapes the urls, so a url with a quote in it can't break the request
+    let body_string = serde_json::json!({ "image_url": image_url, "page_url": page_url }).to_string();
 
     //println!("body string: {}",body_string);
-   
-    let res = client.post("http://209.97.152.154:8000/upsert_image_url").header("Content-Type", "application/json").body(body_string).send().await?;
+
+    let server_url = env::var(EMBEDDING_SERVER_URL_VAR).unwrap_or(DEFAULT_EMBEDDING_SERVER_URL.to_string());
+    let res = client.post(format!("{}/upsert_image_url", server_url)).header("Content-Type", "application/json").body(body_string).send().await?;
 
     let message = res.text().await?;
 
@@ -38,44 +52,85 @@ pub async fn send_image_url(image_url: &str, page_url: &str) -> Result<String, r
 
 
 
+struct CrawlState {
+    /*
+    
+        Everything that the recursive crawl reads or updates as it goes from page to page
+    
+    */
+
+    crawler: Crawler,
+    graph: WebPageGraph,
+
+    // Settings for fetching each page
+    fetch_config: FetchConfig,
+
+    // The host rank from the last time the crawl was ranked, which decides which links are followed first
+    host_rank: HostRank,
+
+    // Set if the crawl embeds pages and images itself, instead of sending images to the embedding server
+    embeddings: Option<EmbeddingWriter>,
+
+    // The maximum recursion depth for the crawler
+    max_recursion_depth: i32,
+
+    // The maximium nunber of pages to add to the index
+    url_max: i32,
+}
+
 #[async_recursion]
-async fn recursive_page_crawl(crawler: &mut Crawler, url: &str, recursion_depth: i32, max_recursion_depth:i32, url_max: i32) {
+async fn recursive_page_crawl(state: &mut CrawlState, url: &str, referring_url: Option<&str>, recursion_depth: i32) {
     /*
     
-        This function is intended for crawling wikipedia. It will open the url, add it to the crawler's hash set, and then it will run itself on all of the page's outgoing links 
+        This function is intended for crawling wikipedia. It will open the url, add it to the crawler's hash set and the web page graph, and then it will run itself on all of the page's outgoing links 
+
+        referring_url is the page that linked to url, or None for the page that the crawl starts from
         
     
     */
 
     // Because the web is so vast, it will easily reach a massive recursion depth, without something preventing that from happening. 
     // This ensures that it does not surpass a certain given recursion depth
-    if recursion_depth >= max_recursion_depth {
+    if recursion_depth >= state.max_recursion_depth {
         //println!("Maximum recursion depth exceeded.");
         return 
     }
 
     // If the maximum number of urls has been gathered by the crawler, then the function will just return so that the process can stop. 
-    if (crawler.set.len() as i32) >= url_max {
+    if (state.crawler.set.len() as i32) >= state.url_max {
         return 
     }
 
     // Fetch the HTML content of the URL 
-    match fetch_html_content(url).await {
-        Ok(html_content) => {
+    match fetch::fetch_html_content(url, &state.fetch_config).await {
+        Ok(FetchResult::Html(html_content)) => {
 
             // get the links to other wikipedia pages from the link
-            let parse_result = parse::extract_wikipedia_HTML(&html_content, url);
+            let parse_result = parse::extract_wikipedia_html(&html_content);
 
             
 
             
             // Insert the current url into the crawler's history
-            crawler.set.insert(url.to_string());
+            state.crawler.set.insert(url.to_string());
+            state.crawler.fetched.insert(url.to_string(), FetchRecord {
+                crawl_depth: recursion_depth as u32,
+                fetched_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+            });
+
+            // Record the page's outgoing links in the web page graph
+            state.graph.insert_page(url, &parse_result.relevant_page_links);
+
+            // save every so often, so that a crawl that is stopped partway through keeps most of what it found. The crawl is saved again when it finishes.
+            if state.crawler.fetched.len().is_multiple_of(SAVE_EVERY_PAGES) {
+                state.crawler.bincode_save(CRAWLER_PATH);
+                state.graph.bincode_save(GRAPH_PATH);
+            }
             
             // Information about the page being crawled
             println!("Crawled page:");
             println!("page: {}, depth: {}, page links: {}, image links: {}, url: {}", 
-                crawler.set.len(),
+                state.crawler.set.len(),
                 recursion_depth+1,
                 parse_result.relevant_page_links.len(),
                 parse_result.relevant_image_links.len(),
@@ -83,41 +138,63 @@ async fn recursive_page_crawl(crawler: &mut Crawler, url: &str, recursion_depth:
             );
 
             // Here is where we can do things with the image links
-            // They should be upserted to the client
-            let mut itr = 0;
-            for image_link in parse_result.relevant_image_links.iter() {
-                if itr >= 9 { break; }
-                itr+=1;
-                
-                let upsert_res = send_image_url(image_link,url);
-                match upsert_res.await {
-                    Ok(message) => {
-                        // print out the status of the upsert, and the link for the image that was sent
-                        println!("\tupsert: {}  {}", message, image_link);
-                    }
-                    Err(err) => {
-                        println!("Error fetching upsert response: {}", err);
+            // If the crawl is embedding pages itself, the page and its images are written straight into the vector indexes. Otherwise the images are upserted to the embedding server.
+            let image_links: Vec<String> = parse_result.relevant_image_links.iter().take(9).cloned().collect();
+            if let Some(embeddings) = state.embeddings.as_mut() {
+                embeddings.add_page(url, &parse_result.page_text).await;
+                embeddings.add_images(url, &image_links, &state.fetch_config).await;
+            } else {
+                for image_link in image_links.iter() {
+                    let upsert_res = send_image_url(image_link,url);
+                    match upsert_res.await {
+                        Ok(message) => {
+                            // print out the status of the upsert, and the link for the image that was sent
+                            println!("\tupsert: {}  {}", message, image_link);
+                        }
+                        Err(err) => {
+                            println!("Error fetching upsert response: {}", err);
+                        }
                     }
                 }
             }
 
 
+            // Follow the links on the hosts with the highest host rank first, so that if the url maximum is reached, the pages that were left out are the ones on the least important hosts. Each link's score is looked up once before sorting, and the sort is stable, so links on the same host keep the order they have on the page.
+            let mut page_links: Vec<(f32, &String)> = parse_result.relevant_page_links.iter().map(|link| (state.host_rank.url_score(link), link)).collect();
+            page_links.sort_by(|a, b| b.0.total_cmp(&a.0));
+
             // iterate through each of the page links in the wikipedia html
-            for link in parse_result.relevant_page_links.iter() {
+            for (_, link) in page_links {
                 // make sure that the given link has not already been visited by the crawler
-                if !crawler.set.contains(link) {
+                if !state.crawler.set.contains(link) {
                     // perform the recursive function
-                    recursive_page_crawl(
-                        crawler,
-                        link,
-                        recursion_depth+1, 
-                        max_recursion_depth,
-                        url_max,
-                    ).await;
+                    recursive_page_crawl(state, link, Some(url), recursion_depth+1).await;
+                }
+            }
+        }
+        Ok(FetchResult::NonHtml(ContentKind::Image)) => {
+            // A link that points straight to an image can be upserted like the images found inside of pages, as an image on the page that linked to it
+            state.crawler.set.insert(url.to_string());
+            let page_url = referring_url.unwrap_or(url);
+            if let Some(embeddings) = state.embeddings.as_mut() {
+                embeddings.add_images(page_url, &[url.to_string()], &state.fetch_config).await;
+            } else {
+                match send_image_url(url, page_url).await {
+                    Ok(message) => {
+                        println!("\tupsert: {}  {}", message, url);
+                    }
+                    Err(err) => {
+                        println!("Error fetching upsert response: {}", err);
+                    }
                 }
             }
-            // after exploring all the links, save the crawl history
-            crawler.bincode_save("crawl_history/crawl_1.bin");
+        }
+        Ok(FetchResult::NonHtml(content_kind)) => {
+            // There is no handler for this kind of content yet
+            state.crawler.record_skip(url, SkipReason::UnsupportedContent(content_kind));
+        }
+        Ok(FetchResult::Skipped(reason)) => {
+            state.crawler.record_skip(url, reason);
         }
         Err(err) => {
             println!("Error fetching html content: {}", err);
@@ -130,6 +207,22 @@ async fn recursive_page_crawl(crawler: &mut Crawler, url: &str, recursion_depth:
 use bincode::{config, Decode, Encode};
 use std::fs;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Decode, Encode, Clone, Copy)]
+pub struct FetchRecord {
+    /*
+    
+        When and how a page was crawled
+    
+    */
+
+    // The number of links between the start page and this page, when the crawler found it
+    pub crawl_depth: u32,
+
+    // The time that the page was fetched, in seconds since the unix epoch
+    pub fetched_at: u64,
+}
 
 #[derive(Decode, Encode)]
 struct Crawler {
@@ -143,10 +236,23 @@ struct Crawler {
     // the hashset contains the urls of every web page that the crawler has visited. Every time that the crawler opens a web page, it will check if the url is in the hashset beforehand, to make sure that it is not visiting a page that has already been visited
     set : HashSet<String>,
 
+    // urls that were fetched, but not crawled, along with the reason why they were skipped. These are also added to the hashset, so that they are not fetched again.
+    skipped : HashMap<String, String>,
+
+    // when and how each crawled page was fetched
+    fetched : HashMap<String, FetchRecord>,
+}
 
+pub fn load_fetch_records(crawler_path: &str) -> HashMap<String, FetchRecord> {
+    /*
+    
+        Load the fetch record of every page crawled by a previous crawl
+    
+    */
+    Crawler::bincode_load(crawler_path).fetched
 }
 
-pub async fn initialize_crawl() {
+pub async fn initialize_crawl(embed: bool) {
     // This is the url where the recursive crawl is gathered from
     let start_url = "https://wikipedia.org/wiki/Google_Search";
 
@@ -157,13 +263,32 @@ pub async fn initialize_crawl() {
     let max_recursion_depth = 32;
 
     // place where the crawl data is stored
-    let crawler_path = "crawl_history/crawl_1.bin";
+    let crawler_path = CRAWLER_PATH;
+
+    // place where the web page graph is stored
+    let graph_path = GRAPH_PATH;
 
 
-    let bincode_config = config::standard();
+    // Settings for fetching each page
+    let fetch_config = FetchConfig::default();
+
+    // The host rank from the last time the crawl was ranked, which decides which links are followed first
+    let mut host_rank = HostRank::new();
+    if Path::new(host_rank::HOST_RANK_PATH).is_file() {
+        host_rank = HostRank::bincode_load(host_rank::HOST_RANK_PATH);
+        println!("loaded host rank for {} hosts", host_rank.scores.len());
+    }
+
+    // If embed is set, the crawler embeds pages and images itself with the embedding provider from the environment (see embedding.rs), instead of sending images to the embedding server
+    let mut embeddings: Option<EmbeddingWriter> = None;
+    if embed {
+        embeddings = Some(EmbeddingWriter::load(embedding::provider_from_env()));
+    }
 
     let mut crawler = Crawler {
-        set: HashSet::new()
+        set: HashSet::new(),
+        skipped: HashMap::new(),
+        fetched: HashMap::new(),
     };
 
     // check if a previous crawl file exists, and if it does, load it
@@ -174,62 +299,109 @@ pub async fn initialize_crawl() {
         println!("Found previous crawl");
 
         
-        let crawler_binary = fs::read(crawler_path).expect("Unable to read previous crawl binary from disk");
-        let (mut _crawler, len) : (Crawler, usize) = bincode::decode_from_slice(&crawler_binary[..], bincode_config).unwrap();
-        println!("loaded previous crawl. contains {} urls", _crawler.set.len());
+        let _crawler = Crawler::bincode_load(crawler_path);
+        println!("loaded previous crawl. contains {} urls, {} skipped", _crawler.set.len(), _crawler.skipped.len());
 
         //for link in &_crawler.set {
         //    println!("{link}");
         //}
         crawler = _crawler;
     } else {  println!("No previous crawl found, creating new crawler..."); }
+
+    // The graph is saved separately from the crawler, so that it can be loaded on its own for ranking and analysis
+    let mut graph = WebPageGraph::new();
+    if Path::new(graph_path).is_file() {
+        graph = WebPageGraph::bincode_load(graph_path);
+        println!("loaded previous web page graph. contains {} pages, {} links", graph.node_count(), graph.edge_count());
+    }
     
     
     println!("Initializing recursive crawl...");
 
-    // Start recursively crawling the web
-    recursive_page_crawl(
-        &mut crawler,
-        start_url, 
-        0,
+    let mut state = CrawlState {
+        crawler,
+        graph,
+        fetch_config,
+        host_rank,
+        embeddings,
         max_recursion_depth,
         url_max,
-    ).await;
+    };
+
+    // Start recursively crawling the web
+    recursive_page_crawl(&mut state, start_url, None, 0).await;
 
 
-    println!("Crawling process finished. Crawl contains: {} urls", crawler.set.len());
+    println!("Crawling process finished. Crawl contains: {} urls", state.crawler.set.len());
 
     
 
-    crawler.bincode_save(crawler_path);
+    state.crawler.bincode_save(crawler_path);
+    state.graph.bincode_save(graph_path);
+    if let Some(embeddings) = state.embeddings.as_mut() {
+        embeddings.save();
+    }
 
 
    
 }
 
 impl Crawler {
+    fn record_skip(&mut self, url: &str, reason: SkipReason) {
+        /*
+        
+            Remember that a url was skipped, and why, so that it is not fetched again
+        
+        */
+        println!("Skipped page: {}  ({})", url, reason);
+        self.set.insert(url.to_string());
+        self.skipped.insert(url.to_string(), reason.to_string());
+    }
+
+    fn bincode_load(crawler_path: &str) -> Crawler {
+        /*
+        
+            Load a crawler from disk. Crawl files from older versions of the crawler are missing the fields that were added after them, so the fields are decoded one at a time, and any that are missing from the end of the file are left empty.
+        
+        */
+        let bincode_config = config::standard();
+
+        let crawler_binary = fs::read(crawler_path).expect("Unable to read previous crawl binary from disk");
+
+        let (set, mut read) : (HashSet<String>, usize) = bincode::decode_from_slice(&crawler_binary[..], bincode_config).expect("Unable to decode previous crawl binary");
+
+        let mut skipped : HashMap<String, String> = HashMap::new();
+        if read < crawler_binary.len() {
+            let (_skipped, len) = bincode::decode_from_slice(&crawler_binary[read..], bincode_config).expect("Unable to decode previous crawl binary");
+            skipped = _skipped;
+            read += len;
+        }
+
+        let mut fetched : HashMap<String, FetchRecord> = HashMap::new();
+        if read < crawler_binary.len() {
+            let (_fetched, _) = bincode::decode_from_slice(&crawler_binary[read..], bincode_config).expect("Unable to decode previous crawl binary");
+            fetched = _fetched;
+        }
+
+        Crawler {
+            set,
+            skipped,
+            fetched,
+        }
+    }
+
     fn bincode_save(&self, crawler_path: &str) {
         let bincode_config = config::standard();
     
         // Save the crawler set
         let encoded_crawler : Vec<u8> = bincode::encode_to_vec(self, bincode_config).unwrap();
-        fs::write(crawler_path, encoded_crawler).expect("Unable to write binary crawl file to disk");
+        write_atomic(crawler_path, &encoded_crawler);
     
         println!("Crawl history written to disk.")
     }
 }
 
 
-fn bincode_write_crawler(crawler: &Crawler, crawler_path: &str) {
-    let bincode_config = config::standard();
-
-    // Save the crawler set
-    let encoded_crawler : Vec<u8> = bincode::encode_to_vec(crawler, bincode_config).unwrap();
-    fs::write(crawler_path, encoded_crawler).expect("Unable to write binary crawl file to disk");
-
-    println!("Crawl history written to disk.")
-}
-
 /*
 
 #[tokio::main]
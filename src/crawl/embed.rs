@@ -0,0 +1,143 @@
+/*
+
+    This script writes the embeddings of the pages and images that the crawler finds straight into the vector indexes, so that a crawl can be searched without sending anything to the embedding server.
+
+    Pages and images are kept in separate indexes (PAGE_VECTOR_INDEX_PATH and IMAGE_VECTOR_INDEX_PATH), since the local embedding provider puts text and images in different spaces. When the crawl doesn't embed images itself, the embedding server writes them to the same image index. Each point's id is a hash of its url, so crawling a page again replaces its embedding instead of adding another one.
+
+    Every embedding is written to its index's log before it is added (see vector_search/wal.rs), so a crawl that is stopped in between saves doesn't lose what it embedded since the last save.
+
+    The document frequencies of the words on every page are counted as well, and saved with the indexes, so that the local embedding provider can weight words by how rare they are the next time it is loaded (see embedding/local.rs).
+
+*/
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::embedding::local::DOCUMENT_FREQUENCIES_PATH;
+use crate::embedding::{DocumentFrequencies, EmbeddingProvider};
+use crate::fetch::{self, FetchConfig};
+use crate::url_host::host_of;
+use crate::vector_search::wal::{DurableIndex, FsyncPolicy};
+use crate::vector_search::{self, url_point_id, DistanceMetric, PointVector, VectorPayload, VectorSearchClient};
+
+// how many pages and images are embedded before the indexes are saved
+const SAVE_EVERY: usize = 100;
+
+pub struct EmbeddingWriter {
+    provider: Box<dyn EmbeddingProvider>,
+    pages: DurableIndex,
+    images: DurableIndex,
+
+    // the document frequencies of every page that has been crawled, including in earlier crawls
+    document_frequencies: DocumentFrequencies,
+}
+
+impl EmbeddingWriter {
+    pub fn load(provider: Box<dyn EmbeddingProvider>) -> EmbeddingWriter {
+        /*
+
+            Load the indexes from the last crawl, or start new ones if there aren't any
+
+        */
+        let fsync_policy = FsyncPolicy::from_env();
+        let pages = DurableIndex::open(vector_search::PAGE_VECTOR_INDEX_PATH, provider.dimension(), DistanceMetric::Cosine, fsync_policy);
+        let images = DurableIndex::open(vector_search::IMAGE_VECTOR_INDEX_PATH, provider.dimension(), DistanceMetric::Cosine, fsync_policy);
+        println!("loaded vector indexes with {} pages and {} images", pages.point_count(), images.point_count());
+        let document_frequencies = if Path::new(DOCUMENT_FREQUENCIES_PATH).is_file() {
+            DocumentFrequencies::bincode_load(DOCUMENT_FREQUENCIES_PATH)
+        } else {
+            DocumentFrequencies::default()
+        };
+        EmbeddingWriter { provider, pages, images, document_frequencies }
+    }
+
+    pub async fn add_page(&mut self, url: &str, page_text: &str) {
+        self.document_frequencies.add_text(page_text);
+        let texts = vec![page_text.to_string()];
+        match self.provider.embed_texts(&texts).await {
+            Ok(mut embeddings) => {
+                let point = PointVector { vec: embeddings.remove(0), payload: payload(url, "", "text/html") };
+                self.pages.upsert(url_point_id(url), point);
+                self.save_if_needed();
+            }
+            Err(err) => {
+                println!("Error embedding page {}: {}", url, err);
+            }
+        }
+    }
+
+    pub async fn add_images(&mut self, page_url: &str, image_urls: &[String], fetch_config: &FetchConfig) {
+        /*
+
+            Download the images, and embed the ones that could be downloaded as one batch. If the batch fails, the images are embedded one at a time, so that one image that can't be decoded doesn't lose the rest.
+
+        */
+        let mut downloaded_urls: Vec<&String> = Vec::new();
+        let mut downloaded: Vec<Vec<u8>> = Vec::new();
+        let mut mime_types: Vec<String> = Vec::new();
+        for image_url in image_urls.iter() {
+            match fetch::fetch_image_bytes(image_url, fetch_config).await {
+                Ok(Ok((bytes, mime_type))) => {
+                    downloaded_urls.push(image_url);
+                    downloaded.push(bytes);
+                    mime_types.push(mime_type);
+                }
+                Ok(Err(reason)) => {
+                    println!("\tskipped image: {}  {}", reason, image_url);
+                }
+                Err(err) => {
+                    println!("Error fetching image {}: {}", image_url, err);
+                }
+            }
+        }
+
+        let embeddings: Vec<Option<Vec<f32>>> = match self.provider.embed_images(&downloaded).await {
+            Ok(embeddings) => embeddings.into_iter().map(Some).collect(),
+            Err(_) => {
+                let mut embeddings: Vec<Option<Vec<f32>>> = Vec::with_capacity(downloaded.len());
+                for (image_url, image) in downloaded_urls.iter().zip(downloaded.iter()) {
+                    match self.provider.embed_images(std::slice::from_ref(image)).await {
+                        Ok(mut embedding) => embeddings.push(Some(embedding.remove(0))),
+                        Err(err) => {
+                            println!("Error embedding image {}: {}", image_url, err);
+                            embeddings.push(None);
+                        }
+                    }
+                }
+                embeddings
+            }
+        };
+
+        for ((image_url, mime_type), embedding) in downloaded_urls.into_iter().zip(mime_types.iter()).zip(embeddings) {
+            if let Some(embedding) = embedding {
+                let point = PointVector { vec: embedding, payload: payload(page_url, image_url, mime_type) };
+                self.images.upsert(url_point_id(image_url), point);
+                println!("\tembedded image: {}", image_url);
+            }
+        }
+        self.save_if_needed();
+    }
+
+    pub fn save(&mut self) {
+        self.pages.checkpoint();
+        self.images.checkpoint();
+        self.document_frequencies.bincode_save(DOCUMENT_FREQUENCIES_PATH);
+    }
+
+    fn save_if_needed(&mut self) {
+        if self.pages.unsaved_changes() + self.images.unsaved_changes() >= SAVE_EVERY {
+            self.save();
+        }
+    }
+}
+
+fn payload(page_url: &str, image_url: &str, content_type: &str) -> VectorPayload {
+    VectorPayload {
+        page_url: page_url.to_string(),
+        image_url: image_url.to_string(),
+        host: host_of(page_url).unwrap_or_default(),
+        content_type: content_type.to_string(),
+        crawl_date: SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+        ..Default::default()
+    }
+}
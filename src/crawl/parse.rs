@@ -8,61 +8,6 @@
 
 
 use scraper::{Html, Selector};
-use std::fs;
-
-pub fn extract_relevant_wikipedia_links(html_content: &str) -> Vec<String> {
-    /*
-    
-        Parse the HTML content of a wikipedia page, and return a vector containing a string for each of the relevant links to the crawler 
-    
-    */
-
-
-    // parse the HTML for the web page
-    let document = Html::parse_document(&html_content);
-
-    // create a selector for the link tags
-    let selector = Selector::parse("a").expect("failed to parse CSS selector");
-
-    let mut relevant_links: Vec<String> = Vec::new();
-
-    // iterate through all the anchor tags and extract the href attribute
-    for element in document.select(&selector) {
-        // extract the href attribute
-        if let Some(href) = element.value().attr("href") {
-
-            // Here the link is filtered to make sure that the function only returns desired links. Most of these criteria were created through trial and error, and do a fairly good job of only allowing links to other wikipedia pages to pass through
-
-            if href.contains("wikipedia.org") { continue }
-
-            if href.contains("wikidata.org") { continue }
-
-            if href.contains("wikimedia") { continue }
-
-            if href.contains("https://") { continue }
-
-            if href.contains(":") { continue }
-
-            if href.contains("#") { continue }
-
-            if href.contains("%") { continue }
-
-            if href.contains("&") { continue }
-
-            if href.contains("disambiguation") { continue }
-
-            let mut wikipedia_url = String::from("https://wikipedia.org");
-            wikipedia_url.push_str(href);
-
-            // assuming the link has passed through the 'filter'
-            relevant_links.push(wikipedia_url);
-        }
-    }
-
-    return relevant_links
-}
-
-
 
 pub struct HTMLExtractionResult {
     /*
@@ -76,38 +21,12 @@ pub struct HTMLExtractionResult {
 
     // Relevant image links in the page
     pub relevant_image_links: Vec<String>,
-}
 
-impl HTMLExtractionResult {
-    pub fn print_info(&self) {
-        /*
-        Prints the relevant information about the extraction
-        */
-        println!("Relevant Page Links: ");
-        for l in self.relevant_page_links.iter() {
-            println!("{}",l);
-        }
-        println!("Relevant Image Links ");
-        for l in self.relevant_image_links.iter() {
-            println!("{}", l);
-        }
-    }
-    pub fn make_content_html(&self, content_path: &str) {
-        /*
-            Makes an HTML page with all of the relevant images extracted from the page
-        */
-        let mut out_html = String::new();
-
-        for image_link in self.relevant_image_links.iter() {
-            out_html.push_str("<img src='");
-            out_html.push_str(image_link);
-            out_html.push_str("'>");
-        }
-        fs::write(content_path, out_html).expect("failed to write HTMLExtractResult content page");
-    }
+    // The title and the text of the page's paragraphs, which is what the page's embedding is made from
+    pub page_text: String,
 }
 
-pub fn extract_wikipedia_HTML(html_content: &str, url: &str) -> HTMLExtractionResult 
+pub fn extract_wikipedia_html(html_content: &str) -> HTMLExtractionResult 
 {
     /*
     
@@ -116,7 +35,7 @@ pub fn extract_wikipedia_HTML(html_content: &str, url: &str) -> HTMLExtractionRe
     */
 
     // Parse the HTML
-    let document = Html::parse_document(&html_content);
+    let document = Html::parse_document(html_content);
 
 
     // Extract all of the page links in the web page
@@ -211,9 +130,49 @@ pub fn extract_wikipedia_HTML(html_content: &str, url: &str) -> HTMLExtractionRe
         }
     }
 
-    return HTMLExtractionResult{
+    // Extract the text of the page
+
+    // the title and the paragraphs are what the page is about, unlike the navigation and the reference lists
+    let text_selector = Selector::parse("title, h1, p").expect("failed to parse CSS selector");
+    let mut page_text = String::new();
+    for element in document.select(&text_selector) {
+        for text in element.text() {
+            page_text.push_str(text);
+        }
+        page_text.push(' ');
+    }
+
+    HTMLExtractionResult{
         relevant_image_links,
         relevant_page_links,
+        page_text,
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_article_links_images_and_text() {
+        let html_content = r#"<html><head><title>Monarch butterfly</title></head><body>
+            <h1>Monarch butterfly</h1>
+            <p>The <a href="/wiki/Monarch_butterfly_migration">migration</a> is well known.</p>
+            <a href="/wiki/File:Monarch.jpg">file page</a>
+            <a href="https://example.com/">external</a>
+            <a href="/wiki/Butterfly#Wings">section</a>
+            <a href="/wiki/Danaus">Danaus</a>
+            <img src="//upload.wikimedia.org/monarch.jpg" width="220" height="160">
+            <img src="//upload.wikimedia.org/icon.png" width="20" height="20">
+            <img src="/static/images/footer.png">
+            <ul><li>navigation</li></ul>
+        </body></html>"#;
+
+        let result = extract_wikipedia_html(html_content);
+        assert_eq!(result.relevant_page_links, vec!["https://wikipedia.org/wiki/Monarch_butterfly_migration", "https://wikipedia.org/wiki/Danaus"]);
+        assert_eq!(result.relevant_image_links, vec!["https://upload.wikimedia.org/monarch.jpg"]);
+        assert!(result.page_text.contains("Monarch butterfly") && result.page_text.contains("is well known"));
+        assert!(!result.page_text.contains("navigation"));
+    }
+}
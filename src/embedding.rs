@@ -0,0 +1,100 @@
+/*
+
+    This script contains the EmbeddingProvider trait, which turns page text and images into the vectors that are stored in the vector index.
+
+    There are two providers:
+
+        LocalEmbeddingProvider (local.rs)   a deterministic baseline that needs no model: hashed TF-IDF for text, and a color histogram plus perceptual features for images
+        HttpEmbeddingProvider (http.rs)     sends batches to a model server running locally, so any model can be used without this project depending on it
+
+    The local provider's text and image vectors are in different spaces, so they can't be compared with each other and should be kept in separate indexes. A multimodal model behind the HTTP provider can put both in the same space.
+
+    Embedding is async, so that the crawler and the embedding server can wait on a model server without blocking. The trait returns boxed futures instead of using async fn, so that providers can be used as a dyn EmbeddingProvider.
+
+*/
+
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+
+pub mod http;
+pub mod local;
+
+pub use http::HttpEmbeddingProvider;
+pub use local::{DocumentFrequencies, LocalEmbeddingProvider};
+
+// If this environment variable is set to the url of a model server, embeddings come from it instead of the local provider
+pub const EMBEDDING_MODEL_URL_VAR: &str = "EMBEDDING_MODEL_URL";
+
+// The number of dimensions of the model server's embeddings
+pub const EMBEDDING_MODEL_DIMENSION_VAR: &str = "EMBEDDING_MODEL_DIMENSION";
+
+// The number of dimensions of the local provider's embeddings, and of the model server's if it isn't given
+pub const DEFAULT_EMBEDDING_DIMENSION: usize = 256;
+
+#[derive(Debug)]
+pub enum EmbeddingError {
+    // The model server couldn't be reached, or responded with an error
+    Http(reqwest::Error),
+
+    // The model server responded with something that isn't a batch of embeddings of the right size
+    InvalidResponse(String),
+
+    // The image couldn't be decoded
+    UnsupportedImage(String),
+}
+
+impl fmt::Display for EmbeddingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmbeddingError::Http(err) => write!(f, "embedding request failed: {}", err),
+            EmbeddingError::InvalidResponse(message) => write!(f, "invalid embedding response: {}", message),
+            EmbeddingError::UnsupportedImage(message) => write!(f, "unsupported image: {}", message),
+        }
+    }
+}
+
+impl From<reqwest::Error> for EmbeddingError {
+    fn from(err: reqwest::Error) -> Self {
+        EmbeddingError::Http(err)
+    }
+}
+
+// one embedding for each input, in the same order
+pub type EmbeddingFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<Vec<f32>>, EmbeddingError>> + Send + 'a>>;
+
+pub trait EmbeddingProvider: Send + Sync {
+    // The number of dimensions of every embedding
+    fn dimension(&self) -> usize;
+
+    // Embed a batch of texts
+    fn embed_texts<'a>(&'a self, texts: &'a [String]) -> EmbeddingFuture<'a>;
+
+    // Embed a batch of images, given as the bytes of their files (jpeg, png, gif or webp)
+    fn embed_images<'a>(&'a self, images: &'a [Vec<u8>]) -> EmbeddingFuture<'a>;
+}
+
+pub fn provider_from_env() -> Box<dyn EmbeddingProvider> {
+    /*
+
+        The model server in EMBEDDING_MODEL_URL if it is set, and the local provider otherwise, with the document frequencies that the crawler saved if there are any
+
+    */
+    match env::var(EMBEDDING_MODEL_URL_VAR) {
+        Ok(model_url) => {
+            let dimension = env::var(EMBEDDING_MODEL_DIMENSION_VAR)
+                .map(|dimension| dimension.parse::<usize>().expect("EMBEDDING_MODEL_DIMENSION must be a number"))
+                .unwrap_or(DEFAULT_EMBEDDING_DIMENSION);
+            println!("Embedding with the model server at {} ({} dimensions)", model_url, dimension);
+            Box::new(HttpEmbeddingProvider::new(&model_url, dimension))
+        }
+        Err(_) if Path::new(local::DOCUMENT_FREQUENCIES_PATH).is_file() => {
+            let document_frequencies = DocumentFrequencies::bincode_load(local::DOCUMENT_FREQUENCIES_PATH);
+            println!("Embedding with the local provider, weighting words by the document frequencies of {} crawled pages", document_frequencies.document_count());
+            Box::new(LocalEmbeddingProvider::with_document_frequencies(DEFAULT_EMBEDDING_DIMENSION, document_frequencies))
+        }
+        Err(_) => Box::new(LocalEmbeddingProvider::new(DEFAULT_EMBEDDING_DIMENSION)),
+    }
+}
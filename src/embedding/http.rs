@@ -0,0 +1,206 @@
+/*
+
+    This script contains the HTTP embedding provider, which sends batches to a model server, so that any model can be run next to the crawler without this project depending on it.
+
+    The model server has to accept:
+
+        POST <url>/embed/text    {"inputs": ["text", ...]}
+        POST <url>/embed/image   {"inputs": ["<base64 of the image file>", ...]}
+
+    and respond to both with {"embeddings": [[f32, ...], ...]}, one embedding for each input, in the same order.
+
+*/
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::{EmbeddingError, EmbeddingFuture, EmbeddingProvider};
+
+// models can take a while on large batches, but a server that never responds shouldn't hang the crawler
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    inputs: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+pub struct HttpEmbeddingProvider {
+    base_url: String,
+    dimension: usize,
+    client: reqwest::Client,
+}
+
+impl HttpEmbeddingProvider {
+    pub fn new(base_url: &str, dimension: usize) -> HttpEmbeddingProvider {
+        HttpEmbeddingProvider {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            dimension,
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().expect("Unable to build HTTP client"),
+        }
+    }
+
+    async fn embed(&self, endpoint: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, EmbeddingError> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let body = serde_json::to_string(&EmbedRequest { inputs }).unwrap();
+        let response = self.client.post(format!("{}/{}", self.base_url, endpoint))
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send().await?
+            .error_for_status()?;
+        let bytes = response.bytes().await?;
+
+        let embeddings = serde_json::from_slice::<EmbedResponse>(&bytes)
+            .map_err(|err| EmbeddingError::InvalidResponse(err.to_string()))?
+            .embeddings;
+        if embeddings.len() != inputs.len() {
+            return Err(EmbeddingError::InvalidResponse(format!("{} embeddings for {} inputs", embeddings.len(), inputs.len())));
+        }
+        if let Some(embedding) = embeddings.iter().find(|embedding| embedding.len() != self.dimension) {
+            return Err(EmbeddingError::InvalidResponse(format!("embedding has {} dimensions instead of {}", embedding.len(), self.dimension)));
+        }
+        Ok(embeddings)
+    }
+}
+
+impl EmbeddingProvider for HttpEmbeddingProvider {
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed_texts<'a>(&'a self, texts: &'a [String]) -> EmbeddingFuture<'a> {
+        Box::pin(self.embed("embed/text", texts))
+    }
+
+    fn embed_images<'a>(&'a self, images: &'a [Vec<u8>]) -> EmbeddingFuture<'a> {
+        Box::pin(async move {
+            let encoded: Vec<String> = images.iter().map(|image| base64::engine::general_purpose::STANDARD.encode(image)).collect();
+            self.embed("embed/image", &encoded).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn model_server(status: &'static str, response_body: String) -> (String, mpsc::Receiver<(String, String)>) {
+        /*
+
+            A model server that answers one request with the given status and body, and sends back the path and body of the request it got
+
+        */
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let path = request_line.split(' ').nth(1).unwrap_or("").to_string();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            sender.send((path, String::from_utf8(body).unwrap())).unwrap();
+
+            let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, response_body.len(), response_body);
+            reader.into_inner().write_all(response.as_bytes()).unwrap();
+        });
+        (base_url, receiver)
+    }
+
+    fn embeddings_json(embeddings: &[Vec<f32>]) -> String {
+        serde_json::json!({ "embeddings": embeddings }).to_string()
+    }
+
+    #[tokio::test]
+    async fn texts_are_sent_as_a_batch() {
+        let (base_url, requests) = model_server("200 OK", embeddings_json(&[vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]]));
+        let provider = HttpEmbeddingProvider::new(&format!("{}/", base_url), 3);
+        let texts = vec!["monarch".to_string(), "tower".to_string()];
+        let embeddings = provider.embed_texts(&texts).await.expect("Unable to embed texts");
+        assert_eq!(embeddings, vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]]);
+
+        let (path, body) = requests.recv().unwrap();
+        assert_eq!(path, "/embed/text");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap(), serde_json::json!({ "inputs": ["monarch", "tower"] }));
+    }
+
+    #[tokio::test]
+    async fn images_are_sent_as_base64() {
+        let (base_url, requests) = model_server("200 OK", embeddings_json(&[vec![0.5, 0.5]]));
+        let provider = HttpEmbeddingProvider::new(&base_url, 2);
+        let images = vec![b"not really a png".to_vec()];
+        assert_eq!(provider.embed_images(&images).await.expect("Unable to embed images"), vec![vec![0.5, 0.5]]);
+
+        let (path, body) = requests.recv().unwrap();
+        assert_eq!(path, "/embed/image");
+        let expected = base64::engine::general_purpose::STANDARD.encode(b"not really a png");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap(), serde_json::json!({ "inputs": [expected] }));
+    }
+
+    #[tokio::test]
+    async fn an_empty_batch_sends_nothing() {
+        // nothing is listening here, so this would fail if a request were made
+        let provider = HttpEmbeddingProvider::new("http://127.0.0.1:9", 3);
+        assert!(provider.embed_texts(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_wrong_number_of_embeddings_is_rejected() {
+        let (base_url, _requests) = model_server("200 OK", embeddings_json(&[vec![1.0, 0.0, 0.0]]));
+        let provider = HttpEmbeddingProvider::new(&base_url, 3);
+        let texts = vec!["monarch".to_string(), "tower".to_string()];
+        match provider.embed_texts(&texts).await {
+            Err(EmbeddingError::InvalidResponse(message)) => assert_eq!(message, "1 embeddings for 2 inputs"),
+            other => panic!("expected an invalid response, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_wrong_dimension_is_rejected() {
+        let (base_url, _requests) = model_server("200 OK", embeddings_json(&[vec![1.0, 0.0, 0.0], vec![1.0, 0.0]]));
+        let provider = HttpEmbeddingProvider::new(&base_url, 3);
+        let texts = vec!["monarch".to_string(), "tower".to_string()];
+        match provider.embed_texts(&texts).await {
+            Err(EmbeddingError::InvalidResponse(message)) => assert_eq!(message, "embedding has 2 dimensions instead of 3"),
+            other => panic!("expected an invalid response, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_from_the_model_server_are_returned() {
+        let (base_url, _requests) = model_server("500 Internal Server Error", "{}".to_string());
+        let provider = HttpEmbeddingProvider::new(&base_url, 3);
+        assert!(matches!(provider.embed_texts(&["monarch".to_string()]).await, Err(EmbeddingError::Http(_))));
+
+        let (base_url, _requests) = model_server("200 OK", "not json".to_string());
+        let provider = HttpEmbeddingProvider::new(&base_url, 3);
+        assert!(matches!(provider.embed_texts(&["monarch".to_string()]).await, Err(EmbeddingError::InvalidResponse(_))));
+    }
+}
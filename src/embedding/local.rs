@@ -0,0 +1,305 @@
+/*
+
+    This script contains the local embedding provider, a deterministic baseline that doesn't need a model. The same input always gives the same vector, on any machine.
+
+    Text is embedded with hashed TF-IDF: every word is hashed into one of dimension buckets, with a sign taken from another bit of the hash so that words that share a bucket mostly cancel out instead of adding up. Each word is weighted by 1 + ln(count), times its inverse document frequency if document frequencies were given, so that common words count for less. Texts that share more rare words have a higher cosine similarity.
+
+    The crawler counts the document frequencies of the pages it embeds, and saves them to DOCUMENT_FREQUENCIES_PATH along with its vector indexes. provider_from_env (embedding.rs) loads them, so the crawler and the embedding server weight words by the pages that were crawled before they started. The weights don't change while a provider is in use, so that every text it embeds is weighted the same way.
+
+    Images are embedded from three sets of features, each normalized so that they count the same:
+
+        color histogram   the fraction of pixels in each of 4 x 4 x 4 RGB bins, square rooted so that a few large bins don't drown out the rest
+        difference hash   whether each pixel of a 9 x 8 grayscale thumbnail is brighter than the one to its left, which stays the same when an image is resized or recompressed
+        thumbnail         an 8 x 8 grayscale thumbnail with its mean brightness subtracted, for the rough layout of the image
+
+    The 192 features are then folded into dimension buckets.
+
+*/
+
+use bincode::{config, Decode, Encode};
+use image::imageops::FilterType;
+use std::collections::HashMap;
+use std::fs;
+
+use super::{EmbeddingError, EmbeddingFuture, EmbeddingProvider};
+use crate::atomic_file::write_atomic;
+use crate::hash::fnv1a;
+
+// place where the document frequencies of the crawled pages are stored
+pub const DOCUMENT_FREQUENCIES_PATH: &str = "crawl_history/document_frequencies_1.bin";
+
+// the number of buckets that document frequencies are counted in. This is much larger than the dimension, so that few words share a count.
+const DOCUMENT_FREQUENCY_BUCKETS: usize = 1 << 20;
+
+// bins per color channel in the color histogram
+const HISTOGRAM_BINS: usize = 4;
+
+// images are shrunk to this size before the histogram is taken, which is plenty for counting colors
+const HISTOGRAM_THUMBNAIL_SIZE: u32 = 64;
+
+const HASH_WIDTH: u32 = 8;
+const HASH_HEIGHT: u32 = 8;
+
+#[derive(Decode, Encode)]
+pub struct DocumentFrequencies {
+    /*
+
+        The number of documents that each word is in, counted in hashed buckets
+
+    */
+    document_count: u64,
+    counts: Vec<u32>,
+}
+
+impl Default for DocumentFrequencies {
+    fn default() -> Self {
+        DocumentFrequencies { document_count: 0, counts: vec![0; DOCUMENT_FREQUENCY_BUCKETS] }
+    }
+}
+
+impl DocumentFrequencies {
+    pub fn from_texts(texts: &[String]) -> DocumentFrequencies {
+        let mut document_frequencies = DocumentFrequencies::default();
+        for text in texts.iter() {
+            document_frequencies.add_text(text);
+        }
+        document_frequencies
+    }
+
+    pub fn add_text(&mut self, text: &str) {
+        // count each word of a document once, however many times it is in it
+        let mut words: Vec<u64> = words(text).map(|word| fnv1a(word.as_bytes())).collect();
+        words.sort_unstable();
+        words.dedup();
+        for hash in words.into_iter() {
+            let bucket = (hash % DOCUMENT_FREQUENCY_BUCKETS as u64) as usize;
+            self.counts[bucket] = self.counts[bucket].saturating_add(1);
+        }
+        self.document_count += 1;
+    }
+
+    pub fn document_count(&self) -> u64 {
+        self.document_count
+    }
+
+    pub fn bincode_save(&self, document_frequencies_path: &str) {
+        let bincode_config = config::standard();
+
+        let encoded : Vec<u8> = bincode::encode_to_vec(self, bincode_config).unwrap();
+        write_atomic(document_frequencies_path, &encoded);
+    }
+
+    pub fn bincode_load(document_frequencies_path: &str) -> DocumentFrequencies {
+        let bincode_config = config::standard();
+
+        let binary = fs::read(document_frequencies_path).expect("Unable to read document frequencies from disk");
+        let (document_frequencies, _) : (DocumentFrequencies, usize) = bincode::decode_from_slice(&binary[..], bincode_config).expect("Unable to decode document frequencies");
+        assert_eq!(document_frequencies.counts.len(), DOCUMENT_FREQUENCY_BUCKETS, "document frequencies were counted in a different number of buckets");
+
+        document_frequencies
+    }
+
+    fn inverse_document_frequency(&self, hash: u64) -> f32 {
+        // smoothed, so that a word that is in every document still counts a little, and a word that was never seen doesn't divide by zero
+        let document_frequency = self.counts[(hash % DOCUMENT_FREQUENCY_BUCKETS as u64) as usize] as f32;
+        ((1.0 + self.document_count as f32) / (1.0 + document_frequency)).ln() + 1.0
+    }
+}
+
+pub struct LocalEmbeddingProvider {
+    dimension: usize,
+    document_frequencies: Option<DocumentFrequencies>,
+}
+
+impl LocalEmbeddingProvider {
+    pub fn new(dimension: usize) -> LocalEmbeddingProvider {
+        assert!(dimension > 0, "embeddings need at least one dimension");
+        LocalEmbeddingProvider { dimension, document_frequencies: None }
+    }
+
+    pub fn with_document_frequencies(dimension: usize, document_frequencies: DocumentFrequencies) -> LocalEmbeddingProvider {
+        LocalEmbeddingProvider { dimension, document_frequencies: Some(document_frequencies) }
+    }
+
+    pub fn embed_text(&self, text: &str) -> Vec<f32> {
+        let mut word_counts: HashMap<u64, u32> = HashMap::new();
+        for word in words(text) {
+            *word_counts.entry(fnv1a(word.as_bytes())).or_insert(0) += 1;
+        }
+
+        let mut vector: Vec<f32> = vec![0.0; self.dimension];
+        for (hash, count) in word_counts.into_iter() {
+            let mut weight = 1.0 + (count as f32).ln();
+            if let Some(document_frequencies) = &self.document_frequencies {
+                weight *= document_frequencies.inverse_document_frequency(hash);
+            }
+            let bucket = (hash % self.dimension as u64) as usize;
+            vector[bucket] += if hash >> 63 == 0 { weight } else { -weight };
+        }
+
+        normalize(&mut vector);
+        vector
+    }
+
+    pub fn embed_image(&self, image_bytes: &[u8]) -> Result<Vec<f32>, EmbeddingError> {
+        let image = image::load_from_memory(image_bytes).map_err(|err| EmbeddingError::UnsupportedImage(err.to_string()))?;
+
+        // color histogram
+        let colors = image.thumbnail(HISTOGRAM_THUMBNAIL_SIZE, HISTOGRAM_THUMBNAIL_SIZE).to_rgb8();
+        let mut histogram: Vec<f32> = vec![0.0; HISTOGRAM_BINS * HISTOGRAM_BINS * HISTOGRAM_BINS];
+        for pixel in colors.pixels() {
+            let [red, green, blue] = pixel.0;
+            let bin = |value: u8| value as usize * HISTOGRAM_BINS / 256;
+            histogram[(bin(red) * HISTOGRAM_BINS + bin(green)) * HISTOGRAM_BINS + bin(blue)] += 1.0;
+        }
+        histogram.iter_mut().for_each(|count| *count = count.sqrt());
+        normalize(&mut histogram);
+
+        // difference hash, from a thumbnail one pixel wider than the hash so that every bit has a pixel to its left
+        let hash_pixels = image.resize_exact(HASH_WIDTH + 1, HASH_HEIGHT, FilterType::Triangle).to_luma8();
+        let mut difference_hash: Vec<f32> = Vec::with_capacity((HASH_WIDTH * HASH_HEIGHT) as usize);
+        for y in 0..HASH_HEIGHT {
+            for x in 0..HASH_WIDTH {
+                let brighter = hash_pixels.get_pixel(x + 1, y).0[0] > hash_pixels.get_pixel(x, y).0[0];
+                difference_hash.push(if brighter { 1.0 } else { -1.0 });
+            }
+        }
+        normalize(&mut difference_hash);
+
+        // thumbnail
+        let thumbnail_pixels = image.resize_exact(HASH_WIDTH, HASH_HEIGHT, FilterType::Triangle).to_luma8();
+        let mut thumbnail: Vec<f32> = thumbnail_pixels.pixels().map(|pixel| pixel.0[0] as f32 / 255.0).collect();
+        let mean = thumbnail.iter().sum::<f32>() / thumbnail.len() as f32;
+        thumbnail.iter_mut().for_each(|value| *value -= mean);
+        normalize(&mut thumbnail);
+
+        let mut vector: Vec<f32> = vec![0.0; self.dimension];
+        for (feature, value) in histogram.iter().chain(difference_hash.iter()).chain(thumbnail.iter()).enumerate() {
+            vector[feature % self.dimension] += value;
+        }
+        normalize(&mut vector);
+        Ok(vector)
+    }
+}
+
+impl EmbeddingProvider for LocalEmbeddingProvider {
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed_texts<'a>(&'a self, texts: &'a [String]) -> EmbeddingFuture<'a> {
+        Box::pin(async move { Ok(texts.iter().map(|text| self.embed_text(text)).collect()) })
+    }
+
+    fn embed_images<'a>(&'a self, images: &'a [Vec<u8>]) -> EmbeddingFuture<'a> {
+        Box::pin(async move { images.iter().map(|image| self.embed_image(image)).collect() })
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    // lowercase words, skipping numbers and words shorter than 3 letters, which are mostly noise like "en" or image sizes in urls
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3 && !word.chars().all(|c| c.is_ascii_digit()))
+        .map(|word| word.to_lowercase())
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempPath;
+    use crate::vector_search::cosine_similarity;
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+    use std::io::Cursor;
+
+    const MONARCH: &str = "The monarch butterfly is a milkweed butterfly known for its migration";
+    const TOWER: &str = "The Eiffel Tower is a wrought iron lattice tower in Paris";
+
+    fn encode(image: &RgbImage, format: ImageOutputFormat) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        DynamicImage::ImageRgb8(image.clone()).write_to(&mut Cursor::new(&mut bytes), format).expect("Unable to encode test image");
+        bytes
+    }
+
+    fn sunset() -> RgbImage {
+        RgbImage::from_fn(200, 120, |x, y| image::Rgb([255 - (y * 2) as u8, (x / 2) as u8, 40]))
+    }
+
+    fn checkerboard() -> RgbImage {
+        RgbImage::from_fn(200, 120, |x, y| if (x / 20 + y / 20) % 2 == 0 { image::Rgb([20, 40, 220]) } else { image::Rgb([240, 240, 240]) })
+    }
+
+    #[test]
+    fn texts_with_the_same_rare_words_are_close() {
+        let provider = LocalEmbeddingProvider::new(256);
+        let monarch = provider.embed_text(MONARCH);
+        assert_eq!(monarch, provider.embed_text(MONARCH));
+        assert!((monarch.iter().map(|value| value * value).sum::<f32>() - 1.0).abs() < 1e-5);
+        let migration = provider.embed_text("Every autumn the monarch butterfly migration covers thousands of miles");
+        let tower = provider.embed_text(TOWER);
+        assert!(cosine_similarity(&monarch, &migration) > cosine_similarity(&monarch, &tower));
+    }
+
+    #[test]
+    fn common_words_count_for_less_with_document_frequencies() {
+        let corpus: Vec<String> = vec![
+            "the history of the city".to_string(),
+            "the river and the bridge".to_string(),
+            "the monarch butterfly".to_string(),
+            "the tower and the city".to_string(),
+        ];
+        let provider = LocalEmbeddingProvider::new(256);
+        let idf_provider = LocalEmbeddingProvider::with_document_frequencies(256, DocumentFrequencies::from_texts(&corpus));
+        let common = |provider: &LocalEmbeddingProvider| cosine_similarity(&provider.embed_text("the the the monarch"), &provider.embed_text("the the the bridge"));
+        assert!(common(&idf_provider) < common(&provider));
+    }
+
+    #[test]
+    fn document_frequencies_are_the_same_after_saving_and_loading() {
+        let corpus: Vec<String> = vec![
+            "the history of the city".to_string(),
+            "the river and the bridge".to_string(),
+            "the monarch butterfly".to_string(),
+        ];
+        let temp_path = TempPath::new("document_frequencies.bin");
+        DocumentFrequencies::from_texts(&corpus).bincode_save(temp_path.path());
+        let loaded = DocumentFrequencies::bincode_load(temp_path.path());
+        assert_eq!(loaded.document_count(), 3);
+
+        let provider = LocalEmbeddingProvider::with_document_frequencies(256, DocumentFrequencies::from_texts(&corpus));
+        let loaded_provider = LocalEmbeddingProvider::with_document_frequencies(256, loaded);
+        assert_eq!(provider.embed_text("the monarch and the river"), loaded_provider.embed_text("the monarch and the river"));
+    }
+
+    #[test]
+    fn images_that_differ_in_size_and_compression_are_close() {
+        let provider = LocalEmbeddingProvider::new(256);
+        let smaller_sunset = image::imageops::resize(&sunset(), 100, 60, FilterType::Triangle);
+
+        let sunset_vec = provider.embed_image(&encode(&sunset(), ImageOutputFormat::Png)).expect("Unable to embed png");
+        let smaller_vec = provider.embed_image(&encode(&smaller_sunset, ImageOutputFormat::Jpeg(80))).expect("Unable to embed jpeg");
+        let checkerboard_vec = provider.embed_image(&encode(&checkerboard(), ImageOutputFormat::Png)).expect("Unable to embed png");
+        assert!(cosine_similarity(&sunset_vec, &smaller_vec) > 0.9);
+        assert!(cosine_similarity(&sunset_vec, &smaller_vec) > cosine_similarity(&sunset_vec, &checkerboard_vec) + 0.3);
+        assert!(matches!(provider.embed_image(b"not an image"), Err(EmbeddingError::UnsupportedImage(_))));
+    }
+
+    #[tokio::test]
+    async fn the_trait_gives_the_same_embeddings_in_order() {
+        let local = LocalEmbeddingProvider::new(256);
+        let texts = vec![MONARCH.to_string(), TOWER.to_string()];
+        let images = vec![encode(&checkerboard(), ImageOutputFormat::Png), encode(&sunset(), ImageOutputFormat::Png)];
+        let expected_texts = vec![local.embed_text(MONARCH), local.embed_text(TOWER)];
+        let expected_images = vec![local.embed_image(&images[0]).unwrap(), local.embed_image(&images[1]).unwrap()];
+
+        let provider: Box<dyn EmbeddingProvider> = Box::new(local);
+        assert_eq!(provider.embed_texts(&texts).await.expect("Unable to embed texts"), expected_texts);
+        assert_eq!(provider.embed_images(&images).await.expect("Unable to embed images"), expected_images);
+    }
+}
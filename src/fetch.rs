@@ -0,0 +1,309 @@
+/*
+
+    This script contains the methods used to fetch web pages before they are parsed.
+
+    Every response is checked before its body is downloaded, so that the crawler does not buffer large PDFs or binaries and hand them to the HTML parser. The body is streamed in chunks up to a maximum size, and then decoded using the charset from the headers or from the page's <meta> tags.
+
+*/
+
+use encoding_rs::{Encoding, UTF_8};
+use reqwest;
+use std::fmt;
+
+// The HTML spec only looks for a <meta charset> in the first 1024 bytes of a document
+const META_CHARSET_PRESCAN_BYTES: usize = 1024;
+
+pub struct FetchConfig {
+    /*
+
+        Settings for how the crawler fetches pages
+
+    */
+
+    // The maximum number of bytes that will be downloaded for a single response. Anything larger is skipped.
+    pub max_body_bytes: usize,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            // Wikipedia articles are rarely more than a couple of megabytes
+            max_body_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentKind {
+    /*
+
+        The kind of content that a response contains, determined from its Content-Type header
+
+    */
+    Html,
+    Image,
+    Pdf,
+    Other(String),
+}
+
+impl ContentKind {
+    pub fn from_mime_type(mime_type: &str) -> ContentKind {
+        match mime_type {
+            "text/html" | "application/xhtml+xml" => ContentKind::Html,
+            "application/pdf" => ContentKind::Pdf,
+            _ if mime_type.starts_with("image/") => ContentKind::Image,
+            _ => ContentKind::Other(mime_type.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /*
+
+        The reason that a fetched url was not passed on to the parser
+
+    */
+
+    // The server responded with a status code other than success
+    HttpStatus(u16),
+
+    // The body was (or was going to be) larger than the configured maximum
+    BodyTooLarge { limit: usize },
+
+    // There is no handler for this kind of content
+    UnsupportedContent(ContentKind),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::HttpStatus(status) => write!(f, "http status {}", status),
+            SkipReason::BodyTooLarge { limit } => write!(f, "body larger than {} bytes", limit),
+            SkipReason::UnsupportedContent(ContentKind::Other(mime_type)) => write!(f, "unsupported content type {}", mime_type),
+            SkipReason::UnsupportedContent(kind) => write!(f, "unsupported content type {:?}", kind),
+        }
+    }
+}
+
+pub enum FetchResult {
+    /*
+
+        The result of fetching a url. Only HTML responses have their body downloaded, everything else is left for a type-specific handler to deal with.
+
+    */
+
+    // The decoded HTML content of the page
+    Html(String),
+
+    // The response was not HTML. The body has not been downloaded.
+    NonHtml(ContentKind),
+
+    // The response was not usable
+    Skipped(SkipReason),
+}
+
+pub async fn fetch_html_content(url: &str, config: &FetchConfig) -> Result<FetchResult, reqwest::Error> {
+    /*
+
+        Make an HTTP request to get the HTML content for the given URL. The headers are checked before the body is downloaded, and the body is streamed up to the configured maximum size.
+
+    */
+    let mut response = reqwest::get(url).await?;
+
+    if !response.status().is_success() {
+        return Ok(FetchResult::Skipped(SkipReason::HttpStatus(response.status().as_u16())));
+    }
+
+    // Read the Content-Type header. If the server does not send one, the response is assumed to be HTML
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("text/html")
+        .to_string();
+    let (mime_type, header_charset) = parse_content_type(&content_type);
+
+    let content_kind = ContentKind::from_mime_type(&mime_type);
+    if content_kind != ContentKind::Html {
+        return Ok(FetchResult::NonHtml(content_kind));
+    }
+
+    match read_body(&mut response, config.max_body_bytes).await? {
+        Some(body) => Ok(FetchResult::Html(decode_html(&body, header_charset.as_deref()))),
+        None => Ok(FetchResult::Skipped(SkipReason::BodyTooLarge { limit: config.max_body_bytes })),
+    }
+}
+
+pub async fn fetch_image_bytes(url: &str, config: &FetchConfig) -> Result<Result<(Vec<u8>, String), SkipReason>, reqwest::Error> {
+    /*
+
+        Download an image so that it can be embedded, and return its bytes and its mime type. Like pages, the headers are checked first, and the body is streamed up to the configured maximum size. This is used by both the crawler and the embedding server.
+
+    */
+    let mut response = reqwest::get(url).await?;
+
+    if !response.status().is_success() {
+        return Ok(Err(SkipReason::HttpStatus(response.status().as_u16())));
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let (mime_type, _) = parse_content_type(&content_type);
+    let content_kind = ContentKind::from_mime_type(&mime_type);
+    if content_kind != ContentKind::Image {
+        return Ok(Err(SkipReason::UnsupportedContent(content_kind)));
+    }
+
+    match read_body(&mut response, config.max_body_bytes).await? {
+        Some(body) => Ok(Ok((body, mime_type))),
+        None => Ok(Err(SkipReason::BodyTooLarge { limit: config.max_body_bytes })),
+    }
+}
+
+async fn read_body(response: &mut reqwest::Response, max_body_bytes: usize) -> Result<Option<Vec<u8>>, reqwest::Error> {
+    /*
+
+        Stream the body of a response, stopping as soon as it goes over max_body_bytes. None means that the body was too large. If the server says up front that it is, none of it is downloaded.
+
+    */
+    if let Some(content_length) = response.content_length() {
+        if content_length > max_body_bytes as u64 {
+            return Ok(None);
+        }
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_body_bytes {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body))
+}
+
+fn parse_content_type(content_type: &str) -> (String, Option<String>) {
+    /*
+
+        Split a Content-Type header such as "text/html; charset=UTF-8" into its lowercase mime type and its charset, if it has one
+
+    */
+    let mut parts = content_type.split(';');
+    let mime_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+
+    let mut charset = None;
+    for parameter in parts {
+        if let Some((name, value)) = parameter.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("charset") {
+                charset = Some(value.trim().trim_matches('"').trim_matches('\'').to_string());
+            }
+        }
+    }
+
+    (mime_type, charset)
+}
+
+fn decode_html(body: &[u8], header_charset: Option<&str>) -> String {
+    /*
+
+        Decode the bytes of an HTML page into a string. The charset is taken from the Content-Type header, then from a <meta> tag in the page, and finally defaults to UTF-8. A byte order mark at the start of the body overrides all of these.
+
+    */
+    let encoding = header_charset
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .or_else(|| sniff_meta_charset(body))
+        .unwrap_or(UTF_8);
+
+    // decode() checks for a byte order mark, and replaces malformed sequences instead of failing
+    let (decoded, _, _) = encoding.decode(body);
+    decoded.into_owned()
+}
+
+fn sniff_meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    /*
+
+        Look for the charset declared in the beginning of an HTML page, either as <meta charset="..."> or as <meta http-equiv="Content-Type" content="text/html; charset=...">
+
+    */
+    let prescan_len = body.len().min(META_CHARSET_PRESCAN_BYTES);
+
+    // Every charset label is ASCII, so a lossy conversion is good enough for finding it
+    let head = String::from_utf8_lossy(&body[..prescan_len]).to_ascii_lowercase();
+
+    let mut search_from = 0;
+    while let Some(meta_start) = head[search_from..].find("<meta") {
+        let meta_start = search_from + meta_start;
+        let meta_end = head[meta_start..].find('>').map(|end| meta_start + end).unwrap_or(head.len());
+        let meta_tag = &head[meta_start..meta_end];
+
+        if let Some(charset_start) = meta_tag.find("charset=") {
+            let label: String = meta_tag[charset_start + "charset=".len()..]
+                .trim_start_matches(['"', '\''])
+                .chars()
+                .take_while(|c| !c.is_whitespace() && !matches!(c, '"' | '\'' | ';' | '/' | '>'))
+                .collect();
+
+            // A page can't really declare itself as UTF-16 from inside the page, so the spec says to read those as UTF-8
+            if let Some(encoding) = Encoding::for_label(label.as_bytes()) {
+                return Some(encoding.output_encoding());
+            }
+        }
+
+        search_from = meta_end;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types_are_split_into_mime_type_and_charset() {
+        assert_eq!(parse_content_type("text/html"), ("text/html".to_string(), None));
+        assert_eq!(parse_content_type("Text/HTML; charset=UTF-8"), ("text/html".to_string(), Some("UTF-8".to_string())));
+        assert_eq!(parse_content_type("text/html; boundary=x; Charset=\"windows-1252\""), ("text/html".to_string(), Some("windows-1252".to_string())));
+        assert_eq!(parse_content_type("text/html;charset='iso-8859-1' "), ("text/html".to_string(), Some("iso-8859-1".to_string())));
+        assert_eq!(parse_content_type(""), (String::new(), None));
+    }
+
+    #[test]
+    fn meta_charsets_are_found_in_the_prescan_window() {
+        assert_eq!(sniff_meta_charset(b"<html><head><meta charset=\"windows-1252\">"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(sniff_meta_charset(b"<META HTTP-EQUIV='Content-Type' CONTENT='text/html; charset=ISO-8859-2'>"), Some(encoding_rs::ISO_8859_2));
+        assert_eq!(sniff_meta_charset(b"<meta name=\"viewport\"><meta charset=utf-16>"), Some(UTF_8));
+        assert_eq!(sniff_meta_charset(b"<meta charset=\"not-a-charset\">"), None);
+
+        // a meta charset that starts past the window is ignored, like browsers do
+        let mut late = vec![b' '; META_CHARSET_PRESCAN_BYTES];
+        late.extend_from_slice(b"<meta charset=\"windows-1252\">");
+        assert_eq!(sniff_meta_charset(&late), None);
+    }
+
+    #[test]
+    fn html_is_decoded_with_the_right_charset() {
+        // "café" in windows-1252
+        let body = b"<p>caf\xe9</p>";
+        assert_eq!(decode_html(body, Some("windows-1252")), "<p>caf\u{e9}</p>");
+
+        // the header wins over the page, and the page wins over the default of UTF-8
+        let page = b"<meta charset=\"windows-1252\"><p>caf\xe9</p>";
+        assert_eq!(decode_html(page, None), "<meta charset=\"windows-1252\"><p>caf\u{e9}</p>");
+        assert_eq!(decode_html("<p>caf\u{e9}</p>".as_bytes(), Some("utf-8")), "<p>caf\u{e9}</p>");
+
+        // a byte order mark overrides everything else
+        let mut bom = vec![0xef, 0xbb, 0xbf];
+        bom.extend_from_slice("<p>caf\u{e9}</p>".as_bytes());
+        assert_eq!(decode_html(&bom, Some("windows-1252")), "<p>caf\u{e9}</p>");
+
+        // bytes that aren't valid in the charset are replaced instead of failing
+        assert_eq!(decode_html(b"<p>\xff\xfe ok</p>", None), "<p>\u{fffd}\u{fffd} ok</p>");
+        assert_eq!(decode_html(b"<p>\xff ok</p>", Some("no-such-charset")), "<p>\u{fffd} ok</p>");
+    }
+}
@@ -0,0 +1,377 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use crate::compact_graph::CompactGraph;
+
+/*
+
+    Graph Analytics:
+    Measurements of the structure of the crawled web page graph. The graph is first converted into a CompactGraph, so that every algorithm can work with u32 ids and CSR arrays instead of urls.
+
+    Pages that were linked to but never crawled are counted as nodes, they just have no outgoing links.
+
+*/
+
+pub const GRAPH_ANALYTICS_PATH: &str = "crawl_history/graph_analytics.json";
+
+// How many of the most linked to and most linking pages are listed in the report
+const TOP_PAGE_COUNT: usize = 10;
+
+const UNVISITED: u32 = u32::MAX;
+
+#[derive(Serialize)]
+pub struct GraphAnalyticsReport {
+    pub node_count: usize,
+    pub crawled_count: usize,
+    pub edge_count: usize,
+
+    // degree -> number of pages with that degree
+    pub in_degree_distribution: BTreeMap<usize, usize>,
+    pub out_degree_distribution: BTreeMap<usize, usize>,
+
+    // the pages with the most links to them, and the pages with the most links out of them
+    pub top_in_degree: Vec<(String, usize)>,
+    pub top_out_degree: Vec<(String, usize)>,
+
+    pub strongly_connected_components: usize,
+    pub largest_strongly_connected_component: usize,
+    pub weakly_connected_components: usize,
+    pub largest_weakly_connected_component: usize,
+
+    // pages with no outgoing links. Crawled dangling pages really have no links, the rest were just never crawled.
+    pub dangling_nodes: usize,
+    pub crawled_dangling_nodes: usize,
+
+    // the fraction of links a -> b where b also links back to a. Links from a page to itself are not counted.
+    pub reciprocity: f64,
+}
+
+impl GraphAnalyticsReport {
+    pub fn print_report(&self) {
+        /*
+        Prints the report in a readable form
+        */
+        println!("Web page graph analytics:");
+        println!("pages: {}, crawled pages: {}, links: {}", self.node_count, self.crawled_count, self.edge_count);
+        println!("strongly connected components: {}, largest: {} pages", self.strongly_connected_components, self.largest_strongly_connected_component);
+        println!("weakly connected components: {}, largest: {} pages", self.weakly_connected_components, self.largest_weakly_connected_component);
+        println!("dangling pages: {} ({} crawled)", self.dangling_nodes, self.crawled_dangling_nodes);
+        println!("reciprocity: {:.4}", self.reciprocity);
+
+        println!("Most linked to pages:");
+        for (url, degree) in self.top_in_degree.iter() {
+            println!("\t{}  {}", degree, url);
+        }
+        println!("Pages with the most links:");
+        for (url, degree) in self.top_out_degree.iter() {
+            println!("\t{}  {}", degree, url);
+        }
+
+        println!("In-degree distribution (degree: pages):");
+        for (degree, count) in self.in_degree_distribution.iter() {
+            println!("\t{}: {}", degree, count);
+        }
+        println!("Out-degree distribution (degree: pages):");
+        for (degree, count) in self.out_degree_distribution.iter() {
+            println!("\t{}: {}", degree, count);
+        }
+    }
+
+    pub fn save_json(&self, report_path: &str) {
+        let json = serde_json::to_string_pretty(self).expect("Unable to serialize graph analytics report");
+        fs::write(report_path, json).expect("Unable to write graph analytics report to disk");
+
+        println!("Graph analytics report written to disk.")
+    }
+}
+
+pub fn analyze(graph: &CompactGraph) -> GraphAnalyticsReport {
+    /*
+
+        Compute every measurement in the report for the compact form of a web page graph
+
+    */
+    let node_count = graph.node_count();
+
+    let mut in_degree_distribution: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut out_degree_distribution: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut dangling_nodes = 0;
+    let mut crawled_dangling_nodes = 0;
+    for id in 0..node_count as u32 {
+        *in_degree_distribution.entry(graph.in_degree(id)).or_insert(0) += 1;
+        *out_degree_distribution.entry(graph.out_degree(id)).or_insert(0) += 1;
+
+        if graph.out_degree(id) == 0 {
+            dangling_nodes += 1;
+            if (id as usize) < graph.crawled_count() {
+                crawled_dangling_nodes += 1;
+            }
+        }
+    }
+
+    let (strongly_connected_components, scc_sizes) = component_sizes(&strongly_connected_components(graph));
+    let (weakly_connected_components, wcc_sizes) = component_sizes(&weakly_connected_components(graph));
+
+    GraphAnalyticsReport {
+        node_count,
+        crawled_count: graph.crawled_count(),
+        edge_count: graph.edge_count(),
+        in_degree_distribution,
+        out_degree_distribution,
+        top_in_degree: top_pages_by(graph, |id| graph.in_degree(id)),
+        top_out_degree: top_pages_by(graph, |id| graph.out_degree(id)),
+        strongly_connected_components,
+        largest_strongly_connected_component: scc_sizes.into_iter().max().unwrap_or(0),
+        weakly_connected_components,
+        largest_weakly_connected_component: wcc_sizes.into_iter().max().unwrap_or(0),
+        dangling_nodes,
+        crawled_dangling_nodes,
+        reciprocity: reciprocity(graph),
+    }
+}
+
+pub fn strongly_connected_components(graph: &CompactGraph) -> Vec<u32> {
+    /*
+
+        Tarjan's algorithm, written with an explicit stack instead of recursion so that long chains of links can't overflow the call stack. Returns the id of the component that each node belongs to.
+
+    */
+    let node_count = graph.node_count();
+
+    let mut index: Vec<u32> = vec![UNVISITED; node_count];
+    let mut lowlink: Vec<u32> = vec![0; node_count];
+    let mut on_stack: Vec<bool> = vec![false; node_count];
+    let mut stack: Vec<u32> = Vec::new();
+    let mut component: Vec<u32> = vec![UNVISITED; node_count];
+
+    let mut next_index: u32 = 0;
+    let mut next_component: u32 = 0;
+
+    for root in 0..node_count as u32 {
+        if index[root as usize] != UNVISITED {
+            continue;
+        }
+
+        // each frame holds a node, and the outgoing links of that node that have not been followed yet
+        index[root as usize] = next_index;
+        lowlink[root as usize] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root as usize] = true;
+        let mut call_stack = vec![(root, graph.outgoing(root))];
+
+        while let Some((node, edges)) = call_stack.last_mut() {
+            let node = *node;
+
+            match edges.next() {
+                Some(target) => {
+                    if index[target as usize] == UNVISITED {
+                        // "recurse" into the target
+                        index[target as usize] = next_index;
+                        lowlink[target as usize] = next_index;
+                        next_index += 1;
+                        stack.push(target);
+                        on_stack[target as usize] = true;
+                        call_stack.push((target, graph.outgoing(target)));
+                    } else if on_stack[target as usize] {
+                        lowlink[node as usize] = lowlink[node as usize].min(index[target as usize]);
+                    }
+                }
+                None => {
+                    // every link has been followed, so "return" to the parent
+                    call_stack.pop();
+                    if let Some((parent, _)) = call_stack.last() {
+                        lowlink[*parent as usize] = lowlink[*parent as usize].min(lowlink[node as usize]);
+                    }
+
+                    // the node is the root of a component, so everything above it on the stack is in its component
+                    if lowlink[node as usize] == index[node as usize] {
+                        loop {
+                            let member = stack.pop().unwrap();
+                            on_stack[member as usize] = false;
+                            component[member as usize] = next_component;
+                            if member == node {
+                                break;
+                            }
+                        }
+                        next_component += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    component
+}
+
+pub fn weakly_connected_components(graph: &CompactGraph) -> Vec<u32> {
+    /*
+
+        Union-find over every link, ignoring the direction of the links. Returns the id of the component that each node belongs to.
+
+    */
+    let node_count = graph.node_count();
+
+    let mut parent: Vec<u32> = (0..node_count as u32).collect();
+    let mut size: Vec<u32> = vec![1; node_count];
+
+    fn find(parent: &mut [u32], mut node: u32) -> u32 {
+        while parent[node as usize] != node {
+            // path halving
+            parent[node as usize] = parent[parent[node as usize] as usize];
+            node = parent[node as usize];
+        }
+        node
+    }
+
+    for source in 0..node_count as u32 {
+        for target in graph.outgoing(source) {
+            let mut a = find(&mut parent, source);
+            let mut b = find(&mut parent, target);
+            if a == b {
+                continue;
+            }
+            // the smaller tree goes under the larger one
+            if size[a as usize] < size[b as usize] {
+                std::mem::swap(&mut a, &mut b);
+            }
+            parent[b as usize] = a;
+            size[a as usize] += size[b as usize];
+        }
+    }
+
+    // renumber the roots so that the component ids are 0..component_count
+    let mut component: Vec<u32> = vec![UNVISITED; node_count];
+    let mut root_component: Vec<u32> = vec![UNVISITED; node_count];
+    let mut next_component: u32 = 0;
+    for node in 0..node_count as u32 {
+        let root = find(&mut parent, node);
+        if root_component[root as usize] == UNVISITED {
+            root_component[root as usize] = next_component;
+            next_component += 1;
+        }
+        component[node as usize] = root_component[root as usize];
+    }
+
+    component
+}
+
+pub fn reciprocity(graph: &CompactGraph) -> f64 {
+    let mut edges: HashSet<(u32, u32)> = HashSet::with_capacity(graph.edge_count());
+    for source in 0..graph.node_count() as u32 {
+        for target in graph.outgoing(source) {
+            if source != target {
+                edges.insert((source, target));
+            }
+        }
+    }
+
+    if edges.is_empty() {
+        return 0.0;
+    }
+
+    let reciprocated = edges.iter().filter(|(source, target)| edges.contains(&(*target, *source))).count();
+    reciprocated as f64 / edges.len() as f64
+}
+
+fn component_sizes(component: &[u32]) -> (usize, Vec<usize>) {
+    // returns the number of components, and the size of each one
+    let component_count = component.iter().map(|id| *id as usize + 1).max().unwrap_or(0);
+    let mut sizes: Vec<usize> = vec![0; component_count];
+    for id in component.iter() {
+        sizes[*id as usize] += 1;
+    }
+    (component_count, sizes)
+}
+
+fn top_pages_by<F: Fn(u32) -> usize>(graph: &CompactGraph, degree: F) -> Vec<(String, usize)> {
+    let mut ids: Vec<u32> = (0..graph.node_count() as u32).collect();
+    ids.sort_by(|a, b| degree(*b).cmp(&degree(*a)).then(a.cmp(b)));
+    ids.truncate(TOP_PAGE_COUNT);
+
+    ids.into_iter().map(|id| (graph.url(id).to_string(), degree(id))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web_graph::WebPageGraph;
+
+    fn graph_from_links(links: &[(&str, &[&str])]) -> CompactGraph {
+        let mut graph = WebPageGraph::new();
+        for (url, linked_urls) in links.iter() {
+            let linked_urls: Vec<String> = linked_urls.iter().map(|url| url.to_string()).collect();
+            graph.insert_page(url, &linked_urls);
+        }
+        CompactGraph::from_web_graph(&graph)
+    }
+
+    fn groups(graph: &CompactGraph, component: &[u32]) -> Vec<Vec<String>> {
+        // the urls in each component, sorted so that they can be compared no matter how the components were numbered
+        let mut groups: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        for (id, component) in component.iter().enumerate() {
+            groups.entry(*component).or_default().push(graph.url(id as u32).to_string());
+        }
+        let mut groups: Vec<Vec<String>> = groups.into_values().map(|mut group| {
+            group.sort();
+            group
+        }).collect();
+        groups.sort();
+        groups
+    }
+
+    fn small_graph() -> CompactGraph {
+        // a -> b -> c -> a is a cycle that leads into the cycle d <-> e. g links to f, which was never crawled.
+        graph_from_links(&[
+            ("a", &["b"]),
+            ("b", &["c"]),
+            ("c", &["a", "d"]),
+            ("d", &["e"]),
+            ("e", &["d"]),
+            ("g", &["f"]),
+        ])
+    }
+
+    #[test]
+    fn strongly_connected_components_of_small_graph() {
+        let graph = small_graph();
+        let expected: Vec<Vec<&str>> = vec![vec!["a", "b", "c"], vec!["d", "e"], vec!["f"], vec!["g"]];
+        assert_eq!(groups(&graph, &strongly_connected_components(&graph)), expected);
+    }
+
+    #[test]
+    fn weakly_connected_components_of_small_graph() {
+        let graph = small_graph();
+        let expected: Vec<Vec<&str>> = vec![vec!["a", "b", "c", "d", "e"], vec!["f", "g"]];
+        assert_eq!(groups(&graph, &weakly_connected_components(&graph)), expected);
+    }
+
+    #[test]
+    fn long_chain_does_not_overflow_the_stack() {
+        // a cycle of 200,000 pages would overflow the call stack if Tarjan's algorithm were recursive
+        let page_count = 200_000;
+        let urls: Vec<String> = (0..page_count).map(|page| page.to_string()).collect();
+        let mut graph = WebPageGraph::new();
+        for page in 0..page_count {
+            graph.insert_page(&urls[page], &[urls[(page + 1) % page_count].clone()]);
+        }
+        let graph = CompactGraph::from_web_graph(&graph);
+
+        assert_eq!(component_sizes(&strongly_connected_components(&graph)), (1, vec![page_count]));
+        assert_eq!(component_sizes(&weakly_connected_components(&graph)), (1, vec![page_count]));
+    }
+
+    #[test]
+    fn report_of_small_graph() {
+        let report = analyze(&small_graph());
+        assert_eq!((report.node_count, report.crawled_count, report.edge_count), (7, 6, 7));
+        assert_eq!((report.strongly_connected_components, report.largest_strongly_connected_component), (4, 3));
+        assert_eq!((report.weakly_connected_components, report.largest_weakly_connected_component), (2, 5));
+        assert_eq!((report.dangling_nodes, report.crawled_dangling_nodes), (1, 0));
+        // only d -> e and e -> d are reciprocated
+        assert!((report.reciprocity - 2.0 / 7.0).abs() < 1e-12);
+        assert_eq!(report.top_out_degree[0], ("c".to_string(), 2));
+        assert_eq!(report.in_degree_distribution, BTreeMap::from([(0, 1), (1, 5), (2, 1)]));
+    }
+}
@@ -0,0 +1,515 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::web_graph::WebPageGraph;
+
+/*
+
+    Graph Export:
+    Writes a WebPageGraph out in formats that other tools can open, so that the link graph can be looked at in Gephi (GraphML), Graphviz (DOT), networkx (node-link JSON), or a spreadsheet (edge list CSV/TSV).
+
+    Every page in the graph is a node, including pages that were linked to but never crawled. An edge is only written if both of the pages it connects are written.
+
+*/
+
+pub enum ExportFormat {
+    GraphML,
+    Dot,
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "graphml" => Some(ExportFormat::GraphML),
+            "dot" => Some(ExportFormat::Dot),
+            "csv" => Some(ExportFormat::Csv),
+            "tsv" => Some(ExportFormat::Tsv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ExportOptions<'a> {
+    /*
+
+        Which part of the graph gets exported, and what is written along with it
+
+    */
+
+    // Only export these pages. If this is None, every page can be exported.
+    pub node_subset: Option<&'a HashSet<String>>,
+
+    // Only export pages with at least this many links in and out of them (in-degree + out-degree)
+    pub min_degree: usize,
+
+    // If this is given, each node's page rank is written as a node attribute
+    pub page_rank: Option<&'a HashMap<String, f64>>,
+}
+
+pub fn export_graph(graph: &WebPageGraph, format: ExportFormat, options: &ExportOptions, export_path: &str) {
+    /*
+
+        Write the graph to a file in the given format
+
+    */
+    let file = File::create(export_path).expect("Unable to create graph export file");
+    let mut writer = BufWriter::new(file);
+
+    write_graph(graph, format, options, &mut writer).expect("Unable to write graph export");
+
+    writer.flush().expect("Unable to write graph export");
+    println!("Graph exported to {}", export_path);
+}
+
+pub fn write_graph<W: Write>(graph: &WebPageGraph, format: ExportFormat, options: &ExportOptions, writer: &mut W) -> io::Result<()> {
+    match format {
+        ExportFormat::GraphML => write_graphml(graph, options, writer),
+        ExportFormat::Dot => write_dot(graph, options, writer),
+        ExportFormat::Csv => write_edge_list(graph, options, ',', writer),
+        ExportFormat::Tsv => write_edge_list(graph, options, '\t', writer),
+        ExportFormat::Json => write_node_link_json(graph, options, writer),
+    }
+}
+
+struct ExportSelection<'g> {
+    /*
+
+        The nodes and edges that pass the export filters, in a stable (sorted) order
+
+    */
+    nodes: Vec<&'g str>,
+    edges: Vec<(&'g str, &'g str)>,
+}
+
+fn select<'g>(graph: &'g WebPageGraph, options: &ExportOptions) -> ExportSelection<'g> {
+    // count the links going in and out of every page, including ones that were never crawled
+    let mut degree: HashMap<&str, usize> = HashMap::new();
+    for node in graph.node_hashmap.values() {
+        *degree.entry(node.url.as_str()).or_insert(0) += node.linked_urls.len();
+        for linked_url in node.linked_urls.iter() {
+            *degree.entry(linked_url.as_str()).or_insert(0) += 1;
+        }
+    }
+
+    let mut nodes: Vec<&str> = degree
+        .iter()
+        .filter(|(url, degree)| {
+            **degree >= options.min_degree
+                && options.node_subset.is_none_or(|subset| subset.contains(**url))
+        })
+        .map(|(url, _)| *url)
+        .collect();
+    nodes.sort();
+
+    let selected: HashSet<&str> = nodes.iter().copied().collect();
+
+    let mut sources: Vec<&String> = graph.node_hashmap.keys().collect();
+    sources.sort();
+
+    let mut edges: Vec<(&str, &str)> = Vec::new();
+    for source in sources {
+        if !selected.contains(source.as_str()) {
+            continue;
+        }
+        for target in graph.node_hashmap[source].linked_urls.iter() {
+            if selected.contains(target.as_str()) {
+                edges.push((source.as_str(), target.as_str()));
+            }
+        }
+    }
+
+    ExportSelection { nodes, edges }
+}
+
+pub fn write_graphml<W: Write>(graph: &WebPageGraph, options: &ExportOptions, writer: &mut W) -> io::Result<()> {
+    let selection = select(graph, options);
+
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+    if options.page_rank.is_some() {
+        writeln!(writer, "  <key id=\"page_rank\" for=\"node\" attr.name=\"page_rank\" attr.type=\"double\"/>")?;
+    }
+    writeln!(writer, "  <graph id=\"web_page_graph\" edgedefault=\"directed\">")?;
+
+    for url in selection.nodes.iter() {
+        match options.page_rank.and_then(|page_rank| page_rank.get(*url)) {
+            Some(score) => {
+                writeln!(writer, "    <node id=\"{}\">", escape_xml(url))?;
+                writeln!(writer, "      <data key=\"page_rank\">{}</data>", score)?;
+                writeln!(writer, "    </node>")?;
+            }
+            None => writeln!(writer, "    <node id=\"{}\"/>", escape_xml(url))?,
+        }
+    }
+    for (source, target) in selection.edges.iter() {
+        writeln!(writer, "    <edge source=\"{}\" target=\"{}\"/>", escape_xml(source), escape_xml(target))?;
+    }
+
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")?;
+    Ok(())
+}
+
+pub fn write_dot<W: Write>(graph: &WebPageGraph, options: &ExportOptions, writer: &mut W) -> io::Result<()> {
+    let selection = select(graph, options);
+
+    writeln!(writer, "digraph web_page_graph {{")?;
+    for url in selection.nodes.iter() {
+        match options.page_rank.and_then(|page_rank| page_rank.get(*url)) {
+            Some(score) => writeln!(writer, "    {} [page_rank={}];", quote_dot(url), score)?,
+            None => writeln!(writer, "    {};", quote_dot(url))?,
+        }
+    }
+    for (source, target) in selection.edges.iter() {
+        writeln!(writer, "    {} -> {};", quote_dot(source), quote_dot(target))?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+pub fn write_edge_list<W: Write>(graph: &WebPageGraph, options: &ExportOptions, delimiter: char, writer: &mut W) -> io::Result<()> {
+    /*
+
+        Write one row per edge. An edge list has nowhere to put node attributes, so if page rank is given, the page rank of the source and target are written as extra columns.
+
+    */
+    let selection = select(graph, options);
+
+    match options.page_rank {
+        Some(page_rank) => {
+            writeln!(writer, "source{d}target{d}source_page_rank{d}target_page_rank", d = delimiter)?;
+            for &(source, target) in selection.edges.iter() {
+                writeln!(
+                    writer,
+                    "{}{d}{}{d}{}{d}{}",
+                    quote_delimited(source, delimiter),
+                    quote_delimited(target, delimiter),
+                    page_rank.get(source).map(|score| score.to_string()).unwrap_or_default(),
+                    page_rank.get(target).map(|score| score.to_string()).unwrap_or_default(),
+                    d = delimiter,
+                )?;
+            }
+        }
+        None => {
+            writeln!(writer, "source{}target", delimiter)?;
+            for (source, target) in selection.edges.iter() {
+                writeln!(writer, "{}{}{}", quote_delimited(source, delimiter), delimiter, quote_delimited(target, delimiter))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct NodeLinkGraph<'g> {
+    directed: bool,
+    multigraph: bool,
+    graph: HashMap<String, String>,
+    nodes: Vec<NodeLinkNode<'g>>,
+    links: Vec<NodeLinkEdge<'g>>,
+}
+
+#[derive(Serialize)]
+struct NodeLinkNode<'g> {
+    id: &'g str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_rank: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct NodeLinkEdge<'g> {
+    source: &'g str,
+    target: &'g str,
+}
+
+pub fn write_node_link_json<W: Write>(graph: &WebPageGraph, options: &ExportOptions, writer: &mut W) -> io::Result<()> {
+    /*
+
+        Write the graph in the node-link format that networkx reads with json_graph.node_link_graph
+
+    */
+    let selection = select(graph, options);
+
+    let node_link_graph = NodeLinkGraph {
+        directed: true,
+        multigraph: false,
+        graph: HashMap::new(),
+        nodes: selection.nodes.iter().map(|&url| NodeLinkNode {
+            id: url,
+            page_rank: options.page_rank.and_then(|page_rank| page_rank.get(url).copied()),
+        }).collect(),
+        links: selection.edges.iter().map(|&(source, target)| NodeLinkEdge { source, target }).collect(),
+    };
+
+    serde_json::to_writer(&mut *writer, &node_link_graph)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn quote_dot(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_delimited(text: &str, delimiter: char) -> String {
+    // Fields only need quotes if they contain the delimiter, a quote, or a line break
+    if text.contains(delimiter) || text.contains('"') || text.contains('\n') || text.contains('\r') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "https://a.org/search?q=1&page=<2>";
+    const B: &str = "https://b.org/\"quoted\"";
+    const C: &str = "https://c.org/one,two";
+    const D: &str = "https://d.org/never_crawled";
+
+    fn small_graph() -> WebPageGraph {
+        // a -> b, a -> c, b -> c, c -> a, and c -> d, where d was never crawled. The urls need escaping in every format.
+        let mut graph = WebPageGraph::new();
+        graph.insert_page(A, &[B.to_string(), C.to_string()]);
+        graph.insert_page(B, &[C.to_string()]);
+        graph.insert_page(C, &[A.to_string(), D.to_string()]);
+        graph
+    }
+
+    fn page_rank() -> HashMap<String, f64> {
+        [(A, 0.25), (B, 0.125), (C, 0.5), (D, 0.0625)].iter().map(|(url, score)| (url.to_string(), *score)).collect()
+    }
+
+    #[derive(Debug, Default, PartialEq)]
+    struct ReadGraph {
+        nodes: Vec<String>,
+        edges: Vec<(String, String)>,
+        page_rank: HashMap<String, f64>,
+    }
+
+    impl ReadGraph {
+        fn sorted(mut self) -> ReadGraph {
+            self.nodes.sort();
+            self.edges.sort();
+            self
+        }
+    }
+
+    fn export(format: ExportFormat, options: &ExportOptions) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        write_graph(&small_graph(), format, options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn expected(nodes: &[&str], edges: &[(&str, &str)], with_page_rank: bool) -> ReadGraph {
+        let page_rank = if with_page_rank {
+            page_rank().into_iter().filter(|(url, _)| nodes.contains(&url.as_str())).collect()
+        } else {
+            HashMap::new()
+        };
+        ReadGraph {
+            nodes: nodes.iter().map(|url| url.to_string()).collect(),
+            edges: edges.iter().map(|(source, target)| (source.to_string(), target.to_string())).collect(),
+            page_rank,
+        }
+        .sorted()
+    }
+
+    fn all_edges() -> Vec<(&'static str, &'static str)> {
+        vec![(A, B), (A, C), (B, C), (C, A), (C, D)]
+    }
+
+    fn read_graphml(text: &str) -> ReadGraph {
+        let unescape = |value: &str| value.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&");
+        let attribute = |line: &str, name: &str| {
+            let start = line.find(&format!("{}=\"", name)).unwrap() + name.len() + 2;
+            let end = start + line[start..].find('"').unwrap();
+            unescape(&line[start..end])
+        };
+
+        let mut graph = ReadGraph::default();
+        for line in text.lines().map(|line| line.trim()) {
+            if line.starts_with("<node ") {
+                graph.nodes.push(attribute(line, "id"));
+            } else if line.starts_with("<edge ") {
+                graph.edges.push((attribute(line, "source"), attribute(line, "target")));
+            } else if let Some(score) = line.strip_prefix("<data key=\"page_rank\">") {
+                let score = score.strip_suffix("</data>").unwrap().parse().unwrap();
+                graph.page_rank.insert(graph.nodes.last().unwrap().clone(), score);
+            }
+        }
+        graph.sorted()
+    }
+
+    fn read_dot(text: &str) -> ReadGraph {
+        let mut graph = ReadGraph::default();
+        for line in text.lines().map(|line| line.trim()).filter(|line| line.starts_with('"')) {
+            // split the line into its quoted ids, and whatever follows the last one
+            let mut ids: Vec<String> = Vec::new();
+            let mut rest = String::new();
+            let mut characters = line.chars();
+            while let Some(c) = characters.next() {
+                if c != '"' {
+                    rest.push(c);
+                    continue;
+                }
+                rest.clear();
+                let mut id = String::new();
+                while let Some(c) = characters.next() {
+                    match c {
+                        '\\' => id.push(characters.next().unwrap()),
+                        '"' => break,
+                        _ => id.push(c),
+                    }
+                }
+                ids.push(id);
+            }
+
+            match ids.len() {
+                1 => {
+                    if let Some(score) = rest.trim().strip_prefix("[page_rank=") {
+                        graph.page_rank.insert(ids[0].clone(), score.trim_end_matches("];").parse().unwrap());
+                    }
+                    graph.nodes.push(ids.remove(0));
+                }
+                2 => {
+                    assert_eq!(rest.trim(), ";");
+                    graph.edges.push((ids[0].clone(), ids[1].clone()));
+                }
+                _ => panic!("unexpected DOT line: {}", line),
+            }
+        }
+        graph.sorted()
+    }
+
+    fn read_delimited_row(line: &str, delimiter: char) -> Vec<String> {
+        let mut fields: Vec<String> = vec![String::new()];
+        let mut quoted = false;
+        let mut characters = line.chars().peekable();
+        while let Some(c) = characters.next() {
+            match c {
+                '"' if quoted && characters.peek() == Some(&'"') => {
+                    characters.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                c if c == delimiter && !quoted => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    fn read_edge_list(text: &str, delimiter: char) -> ReadGraph {
+        // an edge list only has the nodes that have an edge
+        let mut lines = text.lines();
+        let header = read_delimited_row(lines.next().unwrap(), delimiter);
+        let mut graph = ReadGraph::default();
+        for line in lines {
+            let row = read_delimited_row(line, delimiter);
+            assert_eq!(row.len(), header.len());
+            for (url, score) in [(&row[0], row.get(2)), (&row[1], row.get(3))] {
+                if !graph.nodes.contains(url) {
+                    graph.nodes.push(url.clone());
+                }
+                if let Some(score) = score {
+                    graph.page_rank.insert(url.clone(), score.parse().unwrap());
+                }
+            }
+            graph.edges.push((row[0].clone(), row[1].clone()));
+        }
+        graph.sorted()
+    }
+
+    fn read_node_link_json(text: &str) -> ReadGraph {
+        let json: serde_json::Value = serde_json::from_str(text).unwrap();
+        assert_eq!(json["directed"], true);
+
+        let mut graph = ReadGraph::default();
+        for node in json["nodes"].as_array().unwrap() {
+            let id = node["id"].as_str().unwrap().to_string();
+            if let Some(score) = node.get("page_rank") {
+                graph.page_rank.insert(id.clone(), score.as_f64().unwrap());
+            }
+            graph.nodes.push(id);
+        }
+        for link in json["links"].as_array().unwrap() {
+            graph.edges.push((link["source"].as_str().unwrap().to_string(), link["target"].as_str().unwrap().to_string()));
+        }
+        graph.sorted()
+    }
+
+    #[test]
+    fn graphml_round_trip() {
+        let page_rank = page_rank();
+        let options = ExportOptions { page_rank: Some(&page_rank), ..Default::default() };
+        assert_eq!(read_graphml(&export(ExportFormat::GraphML, &options)), expected(&[A, B, C, D], &all_edges(), true));
+        assert_eq!(read_graphml(&export(ExportFormat::GraphML, &ExportOptions::default())), expected(&[A, B, C, D], &all_edges(), false));
+    }
+
+    #[test]
+    fn dot_round_trip() {
+        let page_rank = page_rank();
+        let options = ExportOptions { page_rank: Some(&page_rank), ..Default::default() };
+        assert_eq!(read_dot(&export(ExportFormat::Dot, &options)), expected(&[A, B, C, D], &all_edges(), true));
+        assert_eq!(read_dot(&export(ExportFormat::Dot, &ExportOptions::default())), expected(&[A, B, C, D], &all_edges(), false));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let page_rank = page_rank();
+        let options = ExportOptions { page_rank: Some(&page_rank), ..Default::default() };
+        assert_eq!(read_edge_list(&export(ExportFormat::Csv, &options), ','), expected(&[A, B, C, D], &all_edges(), true));
+        assert_eq!(read_edge_list(&export(ExportFormat::Csv, &ExportOptions::default()), ','), expected(&[A, B, C, D], &all_edges(), false));
+    }
+
+    #[test]
+    fn tsv_round_trip() {
+        let page_rank = page_rank();
+        let options = ExportOptions { page_rank: Some(&page_rank), ..Default::default() };
+        assert_eq!(read_edge_list(&export(ExportFormat::Tsv, &options), '\t'), expected(&[A, B, C, D], &all_edges(), true));
+        assert_eq!(read_edge_list(&export(ExportFormat::Tsv, &ExportOptions::default()), '\t'), expected(&[A, B, C, D], &all_edges(), false));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let page_rank = page_rank();
+        let options = ExportOptions { page_rank: Some(&page_rank), ..Default::default() };
+        assert_eq!(read_node_link_json(&export(ExportFormat::Json, &options)), expected(&[A, B, C, D], &all_edges(), true));
+        assert_eq!(read_node_link_json(&export(ExportFormat::Json, &ExportOptions::default())), expected(&[A, B, C, D], &all_edges(), false));
+    }
+
+    #[test]
+    fn filters_by_degree_and_subset() {
+        // a has 3 links in and out, b has 2, c has 4 and d has 1
+        let options = ExportOptions { min_degree: 3, ..Default::default() };
+        assert_eq!(read_node_link_json(&export(ExportFormat::Json, &options)), expected(&[A, C], &[(A, C), (C, A)], false));
+
+        let subset: HashSet<String> = [A, B, D].iter().map(|url| url.to_string()).collect();
+        let options = ExportOptions { node_subset: Some(&subset), ..Default::default() };
+        assert_eq!(read_graphml(&export(ExportFormat::GraphML, &options)), expected(&[A, B, D], &[(A, B)], false));
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::compact_graph::CompactGraph;
+use crate::web_graph::WebPageGraph;
+
+/*
+
+    Graph Paths:
+    Answers questions like "how do you get from article A to article B by only clicking links?" (the Wikipedia game) using the crawled web page graph. Only links out of crawled pages are known, so a path can end on a page that was never crawled, but it can't go through one.
+
+*/
+
+pub struct PathFinder {
+    graph: CompactGraph,
+}
+
+impl PathFinder {
+    pub fn new(graph: &WebPageGraph) -> PathFinder {
+        PathFinder {
+            graph: CompactGraph::from_web_graph(graph),
+        }
+    }
+
+    pub fn from_compact(graph: CompactGraph) -> PathFinder {
+        PathFinder { graph }
+    }
+
+    pub fn shortest_path(&self, from_url: &str, to_url: &str) -> Option<Vec<String>> {
+        /*
+
+            The shortest chain of links from one page to another, including both pages
+
+        */
+        let from = self.graph.id(from_url)?;
+        let to = self.graph.id(to_url)?;
+
+        bidirectional_bfs(&self.graph, from, to, &HashSet::new(), &HashSet::new())
+            .map(|path| self.urls(&path))
+    }
+
+    pub fn k_shortest_paths(&self, from_url: &str, to_url: &str, k: usize) -> Vec<Vec<String>> {
+        /*
+
+            Up to k of the shortest paths from one page to another, shortest first. None of the paths visit the same page twice.
+
+        */
+        let (from, to) = match (self.graph.id(from_url), self.graph.id(to_url)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Vec::new(),
+        };
+
+        yen_k_shortest_paths(&self.graph, from, to, k)
+            .iter()
+            .map(|path| self.urls(path))
+            .collect()
+    }
+
+    pub fn within_hops(&self, url: &str, max_hops: usize) -> Vec<(String, usize)> {
+        /*
+
+            Every page that can be reached from the given page by following at most max_hops links, along with how many links it takes to get there. The pages are sorted by distance.
+
+        */
+        let source = match self.graph.id(url) {
+            Some(source) => source,
+            None => return Vec::new(),
+        };
+
+        let mut distance: HashMap<u32, usize> = HashMap::new();
+        distance.insert(source, 0);
+        let mut queue: VecDeque<u32> = VecDeque::new();
+        queue.push_back(source);
+
+        let mut reached: Vec<(String, usize)> = Vec::new();
+        while let Some(node) = queue.pop_front() {
+            let node_distance = distance[&node];
+            if node_distance == max_hops {
+                continue;
+            }
+            for target in self.graph.outgoing(node) {
+                if let Entry::Vacant(entry) = distance.entry(target) {
+                    entry.insert(node_distance + 1);
+                    reached.push((self.graph.url(target).to_string(), node_distance + 1));
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        reached
+    }
+
+    fn urls(&self, path: &[u32]) -> Vec<String> {
+        path.iter().map(|id| self.graph.url(*id).to_string()).collect()
+    }
+}
+
+pub fn page_title(url: &str) -> String {
+    /*
+
+        Turn a wikipedia url into the title of the article, e.g. https://wikipedia.org/wiki/Monarch_butterfly -> Monarch butterfly
+
+    */
+    let name = match url.find("/wiki/") {
+        Some(start) => &url[start + "/wiki/".len()..],
+        None => url,
+    };
+    name.replace('_', " ")
+}
+
+fn bidirectional_bfs(graph: &CompactGraph, from: u32, to: u32, blocked_nodes: &HashSet<u32>, blocked_edges: &HashSet<(u32, u32)>) -> Option<Vec<u32>> {
+    /*
+
+        Breadth first search outwards from both ends at once, following outgoing links from the start and incoming links from the end, always growing whichever side has the smaller frontier. This visits far fewer pages than searching from one end, because the number of pages within n links grows so quickly.
+
+    */
+    if blocked_nodes.contains(&from) || blocked_nodes.contains(&to) {
+        return None;
+    }
+    if from == to {
+        return Some(vec![from]);
+    }
+
+    // node -> (the node before it on the path, its distance from that side's end)
+    let mut forward: HashMap<u32, (u32, usize)> = HashMap::new();
+    let mut backward: HashMap<u32, (u32, usize)> = HashMap::new();
+    forward.insert(from, (from, 0));
+    backward.insert(to, (to, 0));
+
+    let mut forward_frontier: Vec<u32> = vec![from];
+    let mut backward_frontier: Vec<u32> = vec![to];
+
+    while !forward_frontier.is_empty() && !backward_frontier.is_empty() {
+        let expand_forward = forward_frontier.len() <= backward_frontier.len();
+
+        let mut next_frontier: Vec<u32> = Vec::new();
+        let mut best_meeting: Option<(u32, usize)> = None;
+
+        // The whole level is expanded before checking where the two sides met, so that the shortest of the meetings is used
+        if expand_forward {
+            for node in forward_frontier.iter() {
+                let node_distance = forward[node].1;
+                for target in graph.outgoing(*node) {
+                    if blocked_nodes.contains(&target) || blocked_edges.contains(&(*node, target)) || forward.contains_key(&target) {
+                        continue;
+                    }
+                    forward.insert(target, (*node, node_distance + 1));
+                    next_frontier.push(target);
+
+                    if let Some((_, other_distance)) = backward.get(&target) {
+                        let length = node_distance + 1 + other_distance;
+                        if best_meeting.is_none_or(|(_, best)| length < best) {
+                            best_meeting = Some((target, length));
+                        }
+                    }
+                }
+            }
+            forward_frontier = next_frontier;
+        } else {
+            for node in backward_frontier.iter() {
+                let node_distance = backward[node].1;
+                for source in graph.incoming(*node) {
+                    if blocked_nodes.contains(&source) || blocked_edges.contains(&(source, *node)) || backward.contains_key(&source) {
+                        continue;
+                    }
+                    backward.insert(source, (*node, node_distance + 1));
+                    next_frontier.push(source);
+
+                    if let Some((_, other_distance)) = forward.get(&source) {
+                        let length = node_distance + 1 + other_distance;
+                        if best_meeting.is_none_or(|(_, best)| length < best) {
+                            best_meeting = Some((source, length));
+                        }
+                    }
+                }
+            }
+            backward_frontier = next_frontier;
+        }
+
+        if let Some((meeting, _)) = best_meeting {
+            // walk back to the start, then forwards to the end
+            let mut path: Vec<u32> = vec![meeting];
+            let mut node = meeting;
+            while node != from {
+                node = forward[&node].0;
+                path.push(node);
+            }
+            path.reverse();
+
+            let mut node = meeting;
+            while node != to {
+                node = backward[&node].0;
+                path.push(node);
+            }
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+fn yen_k_shortest_paths(graph: &CompactGraph, from: u32, to: u32, k: usize) -> Vec<Vec<u32>> {
+    /*
+
+        Yen's algorithm. Each new path is found by taking one of the paths already found, keeping the beginning of it up to some page (the spur page), and then finding the shortest way from the spur page to the end that doesn't reuse a link that one of the found paths takes at that point.
+
+    */
+    let mut found: Vec<Vec<u32>> = Vec::new();
+    if k == 0 {
+        return found;
+    }
+    match bidirectional_bfs(graph, from, to, &HashSet::new(), &HashSet::new()) {
+        Some(path) => found.push(path),
+        None => return found,
+    }
+
+    let mut candidates: Vec<Vec<u32>> = Vec::new();
+
+    while found.len() < k {
+        let previous = found.last().unwrap().clone();
+
+        for spur_index in 0..previous.len() - 1 {
+            let spur_node = previous[spur_index];
+            let root_path = &previous[..=spur_index];
+
+            // don't take the same next link as any found path that shares this root
+            let mut blocked_edges: HashSet<(u32, u32)> = HashSet::new();
+            for path in found.iter() {
+                if path.len() > spur_index + 1 && &path[..=spur_index] == root_path {
+                    blocked_edges.insert((path[spur_index], path[spur_index + 1]));
+                }
+            }
+
+            // don't go back through the root path
+            let blocked_nodes: HashSet<u32> = root_path[..spur_index].iter().copied().collect();
+
+            if let Some(spur_path) = bidirectional_bfs(graph, spur_node, to, &blocked_nodes, &blocked_edges) {
+                let mut candidate: Vec<u32> = root_path[..spur_index].to_vec();
+                candidate.extend(spur_path);
+                if !candidates.contains(&candidate) && !found.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            break;
+        }
+
+        // move the shortest candidate into the found paths
+        let shortest = (0..candidates.len())
+            .min_by(|a, b| candidates[*a].len().cmp(&candidates[*b].len()).then(candidates[*a].cmp(&candidates[*b])))
+            .unwrap();
+        found.push(candidates.swap_remove(shortest));
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_graph() -> PathFinder {
+        // three ways from a to d with two links each (through b, c and g), one with three links (through e and f), d links back to a, and f links to z, which was never crawled
+        let mut graph = WebPageGraph::new();
+        let links = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect::<Vec<String>>();
+        graph.insert_page("a", &links(&["g", "c", "b", "e"]));
+        graph.insert_page("b", &links(&["d"]));
+        graph.insert_page("c", &links(&["d"]));
+        graph.insert_page("g", &links(&["d"]));
+        graph.insert_page("e", &links(&["f"]));
+        graph.insert_page("f", &links(&["d", "z"]));
+        graph.insert_page("d", &links(&["a"]));
+        PathFinder::new(&graph)
+    }
+
+    #[test]
+    fn shortest_paths_meet_in_the_middle() {
+        let finder = path_graph();
+        assert_eq!(finder.shortest_path("a", "d"), Some(vec!["a".to_string(), "b".to_string(), "d".to_string()]));
+        assert_eq!(finder.shortest_path("d", "f"), Some(vec!["d".to_string(), "a".to_string(), "e".to_string(), "f".to_string()]));
+        assert_eq!(finder.shortest_path("b", "z").map(|path| path.len()), Some(6));
+    }
+
+    #[test]
+    fn a_page_is_its_own_shortest_path() {
+        let finder = path_graph();
+        assert_eq!(finder.shortest_path("a", "a"), Some(vec!["a".to_string()]));
+        assert_eq!(finder.k_shortest_paths("a", "a", 3), vec![vec!["a".to_string()]]);
+    }
+
+    #[test]
+    fn unreachable_pages_have_no_paths() {
+        let finder = path_graph();
+        // z was never crawled, so nothing is known to be linked from it
+        assert_eq!(finder.shortest_path("z", "a"), None);
+        assert!(finder.k_shortest_paths("z", "a", 3).is_empty());
+        // pages that aren't in the graph at all
+        assert_eq!(finder.shortest_path("a", "y"), None);
+        assert!(finder.k_shortest_paths("y", "a", 3).is_empty());
+
+        // a blocked end can't be reached either
+        let blocked: HashSet<u32> = [finder.graph.id("d").unwrap()].into_iter().collect();
+        assert_eq!(bidirectional_bfs(&finder.graph, finder.graph.id("a").unwrap(), finder.graph.id("d").unwrap(), &blocked, &HashSet::new()), None);
+    }
+
+    #[test]
+    fn k_shortest_paths_are_shortest_first_with_ties_in_page_order() {
+        let finder = path_graph();
+        let path = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect::<Vec<String>>();
+
+        // the three paths with two links come first, in the order of their pages, whatever order a links to them in
+        assert_eq!(finder.k_shortest_paths("a", "d", 3), vec![path(&["a", "b", "d"]), path(&["a", "c", "d"]), path(&["a", "g", "d"])]);
+
+        // asking for more paths than there are only returns the ones that exist, none of which visit a page twice
+        let paths = finder.k_shortest_paths("a", "d", 10);
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[3], path(&["a", "e", "f", "d"]));
+        assert!(finder.k_shortest_paths("a", "d", 0).is_empty());
+    }
+
+    #[test]
+    fn pages_within_hops_stop_at_the_hop_limit() {
+        let finder = path_graph();
+        let within = |url: &str, max_hops: usize| {
+            let mut reached = finder.within_hops(url, max_hops);
+            reached.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+            reached
+        };
+        let reached = |pages: &[(&str, usize)]| pages.iter().map(|(url, hops)| (url.to_string(), *hops)).collect::<Vec<(String, usize)>>();
+
+        assert!(within("a", 0).is_empty());
+        assert_eq!(within("a", 1), reached(&[("b", 1), ("c", 1), ("e", 1), ("g", 1)]));
+        // d links back to a, but a is where the search started, so it isn't reached again
+        assert_eq!(within("a", 2), reached(&[("b", 1), ("c", 1), ("e", 1), ("g", 1), ("d", 2), ("f", 2)]));
+        assert_eq!(within("a", 10), reached(&[("b", 1), ("c", 1), ("e", 1), ("g", 1), ("d", 2), ("f", 2), ("z", 3)]));
+        assert!(within("z", 10).is_empty());
+        assert!(within("y", 10).is_empty());
+    }
+}
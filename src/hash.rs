@@ -0,0 +1,28 @@
+/*
+
+    Hashing:
+    FNV-1a, used wherever the search engine needs a hash that comes out the same on every machine and every run: the ids of urls in the vector indexes, the words of the local embedding provider, and the checksums of the vector index log. The standard library's hasher is seeded randomly, so it can't be used for anything that is saved.
+
+*/
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    // 64 bit FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_reference_hashes() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::compact_graph::CompactGraph;
+
+/*
+
+    HITS:
+    Kleinberg's hubs and authorities. A page is a good authority if good hubs link to it, and a good hub if it links to good authorities:
+
+        authority(p) = sum of hub(q) over every q that links to p
+        hub(p)       = sum of authority(q) over every q that p links to
+
+    These are repeated until the scores stop changing. On Wikipedia, "List of ..." and overview articles tend to be the hubs, and the articles they list are the authorities, which is a different signal from page rank.
+
+    HITS can be run on the whole graph, or on the subgraph around a query (the root set, usually the top search results, plus the pages that link to them and that they link to), which is how it was originally meant to be used.
+
+*/
+
+pub struct HitsConfig {
+    // The iteration stops once the L1 distance between two iterations (of both scores) is below this
+    pub tolerance: f64,
+
+    // The iteration stops after this many iterations, even if it has not converged
+    pub max_iterations: usize,
+
+    // When building a query subgraph, at most this many of the pages linking to each root page are added, so that very popular pages don't pull in most of the graph
+    pub max_in_neighbors_per_root: usize,
+}
+
+impl Default for HitsConfig {
+    fn default() -> Self {
+        HitsConfig {
+            tolerance: 1e-8,
+            max_iterations: 100,
+            max_in_neighbors_per_root: 50,
+        }
+    }
+}
+
+pub struct HitsResult {
+    // The pages that were scored, as ids in the compact graph
+    pub nodes: Vec<u32>,
+
+    // The hub and authority score of each page in nodes. Each of these sums to 1.
+    pub hub: Vec<f64>,
+    pub authority: Vec<f64>,
+
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl HitsResult {
+    pub fn scores_by_url(&self, graph: &CompactGraph) -> (HashMap<String, f64>, HashMap<String, f64>) {
+        /*
+            Returns the hub scores and the authority scores by url
+        */
+        let mut hub: HashMap<String, f64> = HashMap::with_capacity(self.nodes.len());
+        let mut authority: HashMap<String, f64> = HashMap::with_capacity(self.nodes.len());
+        for (index, node) in self.nodes.iter().enumerate() {
+            hub.insert(graph.url(*node).to_string(), self.hub[index]);
+            authority.insert(graph.url(*node).to_string(), self.authority[index]);
+        }
+        (hub, authority)
+    }
+}
+
+pub fn global_hits(graph: &CompactGraph, config: &HitsConfig) -> HitsResult {
+    /*
+
+        HITS over every page in the graph
+
+    */
+    let nodes: Vec<u32> = (0..graph.node_count() as u32).collect();
+    hits(graph, nodes, config)
+}
+
+pub fn query_hits(graph: &CompactGraph, root_urls: &[String], config: &HitsConfig) -> HitsResult {
+    /*
+
+        HITS over the subgraph around a query. The base set is the root pages, every page they link to, and up to max_in_neighbors_per_root of the pages that link to each of them.
+
+    */
+    let mut base_set: HashSet<u32> = HashSet::new();
+    for url in root_urls.iter() {
+        let root = match graph.id(url) {
+            Some(root) => root,
+            None => continue,
+        };
+        base_set.insert(root);
+        base_set.extend(graph.outgoing(root));
+        base_set.extend(graph.incoming(root).take(config.max_in_neighbors_per_root));
+    }
+
+    let mut nodes: Vec<u32> = base_set.into_iter().collect();
+    nodes.sort();
+    hits(graph, nodes, config)
+}
+
+pub fn hits(graph: &CompactGraph, nodes: Vec<u32>, config: &HitsConfig) -> HitsResult {
+    /*
+
+        Run HITS on the subgraph made up of the given pages, and only the links between them
+
+    */
+    let node_count = nodes.len();
+    if node_count == 0 {
+        return HitsResult { nodes, hub: Vec::new(), authority: Vec::new(), iterations: 0, converged: true };
+    }
+
+    // the links between the pages, with the pages renumbered 0..node_count
+    let local_index: HashMap<u32, u32> = nodes.iter().enumerate().map(|(index, node)| (*node, index as u32)).collect();
+    let mut edges: Vec<(u32, u32)> = Vec::new();
+    for (source_index, source) in nodes.iter().enumerate() {
+        for target in graph.outgoing(*source) {
+            if let Some(target_index) = local_index.get(&target) {
+                edges.push((source_index as u32, *target_index));
+            }
+        }
+    }
+
+    let uniform = 1.0 / node_count as f64;
+    let mut hub: Vec<f64> = vec![uniform; node_count];
+    let mut authority: Vec<f64> = vec![uniform; node_count];
+    let mut next_hub: Vec<f64> = vec![0.0; node_count];
+    let mut next_authority: Vec<f64> = vec![0.0; node_count];
+
+    let mut iterations = 0;
+    let mut converged = false;
+    while iterations < config.max_iterations {
+        next_authority.iter_mut().for_each(|value| *value = 0.0);
+        for (source, target) in edges.iter() {
+            next_authority[*target as usize] += hub[*source as usize];
+        }
+        normalize(&mut next_authority);
+
+        next_hub.iter_mut().for_each(|value| *value = 0.0);
+        for (source, target) in edges.iter() {
+            next_hub[*source as usize] += next_authority[*target as usize];
+        }
+        normalize(&mut next_hub);
+
+        let change: f64 = hub.iter().zip(next_hub.iter()).map(|(a, b)| (a - b).abs()).sum::<f64>()
+            + authority.iter().zip(next_authority.iter()).map(|(a, b)| (a - b).abs()).sum::<f64>();
+
+        std::mem::swap(&mut hub, &mut next_hub);
+        std::mem::swap(&mut authority, &mut next_authority);
+        iterations += 1;
+
+        if change < config.tolerance {
+            converged = true;
+            break;
+        }
+    }
+
+    HitsResult {
+        nodes,
+        hub,
+        authority,
+        iterations,
+        converged,
+    }
+}
+
+fn normalize(scores: &mut [f64]) {
+    // scale the scores so they sum to 1. If there are no links at all, they are left at 0.
+    let total: f64 = scores.iter().sum();
+    if total > 0.0 {
+        for score in scores.iter_mut() {
+            *score /= total;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web_graph::WebPageGraph;
+
+    fn links(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn hubs_and_authorities_of_a_known_graph() {
+        // hub_1 and hub_2 link to both authorities, hub_3 only links to authority_1
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("hub_1", &links(&["authority_1", "authority_2"]));
+        graph.insert_page("hub_2", &links(&["authority_1", "authority_2"]));
+        graph.insert_page("hub_3", &links(&["authority_1"]));
+        let graph = CompactGraph::from_web_graph(&graph);
+
+        let result = global_hits(&graph, &HitsConfig::default());
+        assert!(result.converged);
+        assert!((result.hub.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!((result.authority.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        // The authorities are the principal eigenvector of A^T A = [[3, 2], [2, 2]], whose eigenvalue is (5 + sqrt(17)) / 2, and the hubs are A times the authorities
+        let (hub, authority) = result.scores_by_url(&graph);
+        assert!((authority["authority_1"] - 0.56155).abs() < 1e-4);
+        assert!((authority["authority_2"] - 0.43845).abs() < 1e-4);
+        assert!((hub["hub_1"] - 0.39039).abs() < 1e-4);
+        assert!((hub["hub_2"] - 0.39039).abs() < 1e-4);
+        assert!((hub["hub_3"] - 0.21922).abs() < 1e-4);
+
+        // pages that nothing links to aren't authorities, and pages that don't link anywhere aren't hubs
+        assert_eq!(authority["hub_1"], 0.0);
+        assert_eq!(hub["authority_1"], 0.0);
+    }
+
+    #[test]
+    fn query_subgraph_is_the_root_pages_and_their_neighbours() {
+        // root links to linked, which links to far. linking_1, linking_2 and linking_3 link to root, and unrelated only links to linked.
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("root", &links(&["linked"]));
+        graph.insert_page("linked", &links(&["far"]));
+        graph.insert_page("linking_1", &links(&["root"]));
+        graph.insert_page("linking_2", &links(&["root"]));
+        graph.insert_page("linking_3", &links(&["root"]));
+        graph.insert_page("unrelated", &links(&["linked"]));
+        let graph = CompactGraph::from_web_graph(&graph);
+
+        let config = HitsConfig { max_in_neighbors_per_root: 2, ..Default::default() };
+        let result = query_hits(&graph, &links(&["root", "not_in_the_graph"]), &config);
+        let mut urls: Vec<&str> = result.nodes.iter().map(|node| graph.url(*node)).collect();
+        urls.sort();
+
+        // only two of the pages linking to the root are added, and pages two links away aren't added at all
+        assert_eq!(urls, vec!["linked", "linking_1", "linking_2", "root"]);
+        assert_eq!(result.hub.len(), 4);
+        assert!(query_hits(&graph, &links(&["not_in_the_graph"]), &config).nodes.is_empty());
+    }
+}
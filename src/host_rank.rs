@@ -0,0 +1,216 @@
+use bincode::{config, Decode, Encode};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::url_host::host_of;
+
+use crate::atomic_file::write_atomic;
+use crate::page_rank::{self, PageRankConfig, PageRankResult, SparseMarkovTransitionMatrix, SparseTransitionMatrixRow, TransitionMatrixEdge};
+use crate::web_graph::WebPageGraph;
+
+/*
+
+    Host Rank:
+    Page rank over hosts instead of pages. Every page is grouped by its host (en.wikipedia.org, www.example.com, ...), and the links between pages become weighted links between hosts, where the weight is the number of page links from one host to the other. Links within a host are left out.
+
+    This is much smaller than the page graph, so it is cheap to compute, and it is harder to game: a site can make as many pages linking to itself as it wants, but that does not change its host rank, and all the pages on a host have to share the rank that the host gets from the other hosts that link to it.
+
+*/
+
+// place where the host rank of every host is stored
+pub const HOST_RANK_PATH: &str = "crawl_history/host_rank_1.bin";
+
+pub struct HostGraph {
+    // every host that a crawled page is on, or that a crawled page links to, sorted
+    pub hosts: Vec<String>,
+
+    // (source host, target host) -> the number of page links from the source host to the target host. Links that stay on a host are left out.
+    pub edges: BTreeMap<(u32, u32), u32>,
+}
+
+impl HostGraph {
+    pub fn from_web_graph(graph: &WebPageGraph) -> HostGraph {
+        /*
+
+            Group the pages of a web page graph by host. Urls that don't have a host are left out.
+
+        */
+        let mut page_hosts: HashMap<&str, Option<String>> = HashMap::new();
+        for node in graph.node_hashmap.values() {
+            page_hosts.entry(node.url.as_str()).or_insert_with(|| host_of(&node.url));
+            for link in node.linked_urls.iter() {
+                page_hosts.entry(link.as_str()).or_insert_with(|| host_of(link));
+            }
+        }
+
+        let mut hosts: Vec<String> = page_hosts.values().flatten().cloned().collect();
+        hosts.sort();
+        hosts.dedup();
+        let host_index: HashMap<&str, u32> = hosts.iter().enumerate().map(|(index, host)| (host.as_str(), index as u32)).collect();
+        let id_of = |url: &str| page_hosts[url].as_deref().map(|host| host_index[host]);
+
+        let mut edges: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+        for node in graph.node_hashmap.values() {
+            let source = match id_of(&node.url) {
+                Some(source) => source,
+                None => continue,
+            };
+            for link in node.linked_urls.iter() {
+                match id_of(link) {
+                    Some(target) if target != source => *edges.entry((source, target)).or_insert(0) += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        HostGraph {
+            hosts,
+            edges,
+        }
+    }
+
+    pub fn host_count(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn construct_markov_transition_matrix(&self) -> SparseMarkovTransitionMatrix {
+        /*
+
+            The transition matrix of a random surfer that leaves each host through a link picked at random from all of the page links out of it, so a host that gets more links from another host gets more of that host's rank.
+
+        */
+        let host_count = self.host_count();
+
+        let mut out_weight: Vec<u64> = vec![0; host_count];
+        for ((source, _), weight) in self.edges.iter() {
+            out_weight[*source as usize] += *weight as u64;
+        }
+
+        let mut transition_matrix_rows: Vec<SparseTransitionMatrixRow> = (0..host_count).map(|_| SparseTransitionMatrixRow { transition_edges: Vec::new() }).collect();
+        for ((source, target), weight) in self.edges.iter() {
+            transition_matrix_rows[*target as usize].transition_edges.push(TransitionMatrixEdge {
+                outgoing_node: *source as usize,
+                transition_probability: *weight as f64 / out_weight[*source as usize] as f64,
+            });
+        }
+
+        SparseMarkovTransitionMatrix {
+            urls: self.hosts.clone(),
+            transition_matrix_rows,
+            dangling_nodes: (0..host_count).filter(|host| out_weight[*host] == 0).collect(),
+        }
+    }
+}
+
+#[derive(Decode, Encode)]
+pub struct HostRank {
+    /*
+
+        The host rank of every host, saved after it is calculated so that the search ranking and the crawler can both use it
+
+    */
+
+    pub scores: HashMap<String, f32>,
+}
+
+impl HostRank {
+    pub fn new() -> HostRank {
+        HostRank {
+            scores: HashMap::new()
+        }
+    }
+
+    pub fn compute(graph: &WebPageGraph, config: &PageRankConfig) -> (HostRank, PageRankResult) {
+        /*
+
+            Build the host graph of a web page graph and calculate its page rank. The result of the power iteration is returned too, so that its convergence can be reported.
+
+        */
+        let host_graph = HostGraph::from_web_graph(graph);
+        let matrix = host_graph.construct_markov_transition_matrix();
+        let result = page_rank::power_iteration(&matrix, config);
+
+        let scores = matrix.urls.iter().cloned().zip(result.scores.iter().map(|score| *score as f32)).collect();
+        (HostRank { scores }, result)
+    }
+
+    pub fn host_score(&self, host: &str) -> f32 {
+        // Hosts that weren't in the graph have no host rank
+        self.scores.get(host).copied().unwrap_or(0.0)
+    }
+
+    pub fn url_score(&self, url: &str) -> f32 {
+        /*
+            The host rank of the host that a url is on. This is used as a ranking signal for the page, and to decide which links the crawler should follow first.
+        */
+        host_of(url).map(|host| self.host_score(&host)).unwrap_or(0.0)
+    }
+
+    pub fn bincode_save(&self, host_rank_path: &str) {
+        let bincode_config = config::standard();
+
+        let encoded : Vec<u8> = bincode::encode_to_vec(self, bincode_config).unwrap();
+        write_atomic(host_rank_path, &encoded);
+
+        println!("Host rank written to disk.")
+    }
+
+    pub fn bincode_load(host_rank_path: &str) -> HostRank {
+        let bincode_config = config::standard();
+
+        let binary = fs::read(host_rank_path).expect("Unable to read host rank from disk");
+        let (host_rank, _) : (HostRank, usize) = bincode::decode_from_slice(&binary[..], bincode_config).expect("Unable to decode host rank");
+
+        host_rank
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempPath;
+
+    fn host_web_graph() -> WebPageGraph {
+        // a.com links to b.com from two of its pages and to c.com from one, and b.com links back to a.com. c.com was never crawled.
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("https://a.com/1", &["https://a.com/2".to_string(), "https://b.com/x".to_string(), "https://c.com/z".to_string(), "not a url".to_string()]);
+        graph.insert_page("https://www.a.com/2", &["https://b.com/y".to_string()]);
+        graph.insert_page("https://b.com/x", &["https://a.com/1".to_string()]);
+        graph
+    }
+
+    #[test]
+    fn page_links_are_grouped_into_weighted_host_links() {
+        let host_graph = HostGraph::from_web_graph(&host_web_graph());
+        assert_eq!(host_graph.hosts, vec!["a.com", "b.com", "c.com"]);
+
+        // the two links from a.com to b.com become one link with a weight of 2, and the link from a.com to itself is left out
+        let edges: Vec<((u32, u32), u32)> = host_graph.edges.iter().map(|(edge, weight)| (*edge, *weight)).collect();
+        assert_eq!(edges, vec![((0, 1), 2), ((0, 2), 1), ((1, 0), 1)]);
+
+        let matrix = host_graph.construct_markov_transition_matrix();
+        assert_eq!(matrix.dangling_nodes, vec![2]);
+        let into_b = &matrix.transition_matrix_rows[1].transition_edges;
+        assert_eq!(into_b.len(), 1);
+        assert!((into_b[0].transition_probability - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn host_rank_of_a_small_host_graph() {
+        let config = PageRankConfig { tolerance: 1e-10, max_iterations: 1000, ..Default::default() };
+        let (host_rank, result) = HostRank::compute(&host_web_graph(), &config);
+        assert!(result.converged);
+
+        // a.com -> b.com with probability 2/3, a.com -> c.com with 1/3, b.com -> a.com, and c.com is dangling
+        assert!((host_rank.host_score("a.com") - 0.41488).abs() < 1e-4);
+        assert!((host_rank.host_score("b.com") - 0.35134).abs() < 1e-4);
+        assert!((host_rank.host_score("c.com") - 0.23379).abs() < 1e-4);
+        assert_eq!(host_rank.url_score("https://www.B.com/anything"), host_rank.host_score("b.com"));
+        assert_eq!(host_rank.url_score("https://d.com/"), 0.0);
+        assert_eq!(host_rank.url_score("not a url"), 0.0);
+
+        let host_rank_path = TempPath::new("host_rank.bin");
+        host_rank.bincode_save(host_rank_path.path());
+        assert_eq!(HostRank::bincode_load(host_rank_path.path()).scores, host_rank.scores);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::compact_graph::CompactGraph;
+use crate::page_rank::{self, PageRankConfig, PageRankResult, SparseMarkovTransitionMatrix};
+use crate::web_graph::WebPageGraph;
+
+/*
+
+    Incremental Page Rank:
+    After a recrawl, most of the graph is the same as before, so the page rank from before is already close to the new page rank. Instead of starting the power iteration from the uniform distribution, these start from the old scores.
+
+    Warm start: run the normal power iteration, starting from the old scores.
+
+    Push: a local update, that only does work near the pages that changed. The new page rank x satisfies the linear system
+
+        (I - dM)x = (1 - d)t
+
+    where M is the transition matrix with dangling pages sending their probability through the teleport vector t. Writing x = x_old + delta, delta satisfies (I - dM)delta = r, where r = (1 - d)t + dMx_old - x_old is the residual of the old scores on the new graph. r is close to zero everywhere except around the pages whose links changed. Pushing a page u moves its residual into delta, and sends d * r_u / out_degree(u) of residual to each page it links to, which keeps (I - dM)delta + r = r_old the same. Pushing stops when every page's residual is below epsilon, and then the error of the scores is at most ||r||_1 / (1 - d).
+
+*/
+
+pub enum GraphEdit {
+    AddNode(String),
+    RemoveNode(String),
+    AddEdge(String, String),
+    RemoveEdge(String, String),
+}
+
+pub fn diff_graphs(old: &WebPageGraph, new: &WebPageGraph) -> Vec<GraphEdit> {
+    /*
+
+        The edits that turn one graph into another, e.g. the graph from the last time the crawl was ranked into the graph after a recrawl. Removing a page also removes the links to it, so pages are removed first, and links that the new graph still has to a removed page are added back.
+
+    */
+    let removed: HashSet<&str> = old.node_hashmap.keys().filter(|url| !new.node_hashmap.contains_key(*url)).map(|url| url.as_str()).collect();
+
+    let mut edits: Vec<GraphEdit> = Vec::new();
+    let mut removed_urls: Vec<&str> = removed.iter().copied().collect();
+    removed_urls.sort();
+    edits.extend(removed_urls.into_iter().map(|url| GraphEdit::RemoveNode(url.to_string())));
+
+    let mut new_urls: Vec<&String> = new.node_hashmap.keys().collect();
+    new_urls.sort();
+    for url in new_urls.iter().filter(|url| !old.node_hashmap.contains_key(**url)) {
+        edits.push(GraphEdit::AddNode(url.to_string()));
+    }
+
+    for url in new_urls {
+        let old_links: HashSet<&str> = match old.node_hashmap.get(url) {
+            Some(node) => node.linked_urls.iter().map(|link| link.as_str()).filter(|link| !removed.contains(link)).collect(),
+            None => HashSet::new(),
+        };
+        let new_links = &new.node_hashmap[url].linked_urls;
+        let new_link_set: HashSet<&str> = new_links.iter().map(|link| link.as_str()).collect();
+
+        let mut removed_links: Vec<&&str> = old_links.iter().filter(|link| !new_link_set.contains(**link)).collect();
+        removed_links.sort();
+        edits.extend(removed_links.into_iter().map(|link| GraphEdit::RemoveEdge(url.clone(), link.to_string())));
+        for link in new_links.iter().filter(|link| !old_links.contains(link.as_str())) {
+            edits.push(GraphEdit::AddEdge(url.clone(), link.clone()));
+        }
+    }
+
+    edits
+}
+
+pub fn apply_edits(graph: &mut WebPageGraph, edits: &[GraphEdit]) {
+    for edit in edits.iter() {
+        match edit {
+            GraphEdit::AddNode(url) => {
+                if !graph.node_hashmap.contains_key(url) {
+                    graph.insert_page(url, &[]);
+                }
+            }
+            GraphEdit::RemoveNode(url) => graph.remove_page(url),
+            GraphEdit::AddEdge(from_url, to_url) => graph.add_link(from_url, to_url),
+            GraphEdit::RemoveEdge(from_url, to_url) => graph.remove_link(from_url, to_url),
+        }
+    }
+}
+
+fn initial_scores(matrix: &SparseMarkovTransitionMatrix, previous: &HashMap<String, f64>) -> Vec<f64> {
+    // The old score of every page that is still in the graph. New pages start with nothing.
+    matrix.urls.iter().map(|url| previous.get(url).copied().unwrap_or(0.0)).collect()
+}
+
+pub fn warm_start_page_rank(graph: &WebPageGraph, previous: &HashMap<String, f64>, config: &PageRankConfig) -> (HashMap<String, f64>, PageRankResult) {
+    /*
+
+        Recalculate page rank on an edited graph, starting from the page rank before the edits. The result is returned as well, for its iteration count.
+
+    */
+    let matrix = page_rank::construct_markov_transition_matrix(graph);
+
+    let mut initial = initial_scores(&matrix, previous);
+
+    // pages that were removed took their scores with them, so the total has to be brought back up to 1
+    let total: f64 = initial.iter().sum();
+    if total > 0.0 {
+        for value in initial.iter_mut() {
+            *value /= total;
+        }
+    } else {
+        initial = vec![1.0 / matrix.node_count() as f64; matrix.node_count()];
+    }
+
+    let result: PageRankResult = page_rank::power_iteration_from(&matrix, config, initial);
+    (result.scores_by_url(&matrix), result)
+}
+
+pub struct PushUpdateResult {
+    pub scores: HashMap<String, f64>,
+
+    // the number of times a page's residual was pushed
+    pub pushes: usize,
+
+    // the L1 norm of the residual that was left
+    pub residual: f64,
+
+    // the L1 distance between these scores and the scores a full recompute would give is at most this
+    pub error_bound: f64,
+}
+
+pub fn push_page_rank_update(graph: &WebPageGraph, previous: &HashMap<String, f64>, damping_factor: f64, epsilon: f64) -> PushUpdateResult {
+    /*
+
+        Update page rank after edits to the graph by pushing residual from the pages around the edits. Every page whose residual is larger than epsilon gets pushed.
+
+    */
+    let compact_graph = CompactGraph::from_web_graph(graph);
+    let matrix = page_rank::construct_markov_transition_matrix_compact(&compact_graph);
+    let node_count = matrix.node_count();
+    let teleport = 1.0 / node_count as f64;
+
+    let mut x = initial_scores(&matrix, previous);
+
+    // r = (1 - d)t + dMx_old - x_old
+    let mut r: Vec<f64> = vec![0.0; node_count];
+    matrix.multiply(&x, &mut r);
+    let spread = damping_factor * matrix.dangling_mass(&x) + (1.0 - damping_factor);
+    for (residual, value) in r.iter_mut().zip(x.iter()) {
+        *residual = damping_factor * *residual + spread * teleport - value;
+    }
+
+    let mut queue: VecDeque<u32> = VecDeque::new();
+    let mut queued: Vec<bool> = vec![false; node_count];
+    for node in 0..node_count {
+        if r[node].abs() > epsilon {
+            queue.push_back(node as u32);
+            queued[node] = true;
+        }
+    }
+
+    let mut pushes = 0;
+    loop {
+        // residual pushed out of dangling pages goes to every page, so it is collected and spread all at once
+        let mut dangling_residual = 0.0;
+
+        while let Some(node) = queue.pop_front() {
+            queued[node as usize] = false;
+            let residual = r[node as usize];
+            if residual.abs() <= epsilon {
+                continue;
+            }
+            x[node as usize] += residual;
+            r[node as usize] = 0.0;
+            pushes += 1;
+
+            let out_degree = compact_graph.out_degree(node);
+            if out_degree == 0 {
+                dangling_residual += damping_factor * residual;
+                continue;
+            }
+            let share = damping_factor * residual / out_degree as f64;
+            for target in compact_graph.outgoing(node) {
+                r[target as usize] += share;
+                if !queued[target as usize] && r[target as usize].abs() > epsilon {
+                    queue.push_back(target);
+                    queued[target as usize] = true;
+                }
+            }
+        }
+
+        // stop once spreading the dangling residual wouldn't push any page over epsilon
+        if dangling_residual.abs() * teleport <= epsilon {
+            for residual in r.iter_mut() {
+                *residual += dangling_residual * teleport;
+            }
+            break;
+        }
+        for node in 0..node_count {
+            r[node] += dangling_residual * teleport;
+            if !queued[node] && r[node].abs() > epsilon {
+                queue.push_back(node as u32);
+                queued[node] = true;
+            }
+        }
+    }
+
+    let residual: f64 = r.iter().map(|value| value.abs()).sum();
+    let error_bound = residual / (1.0 - damping_factor);
+
+    PushUpdateResult {
+        scores: matrix.urls.iter().cloned().zip(x).collect(),
+        pushes,
+        residual,
+        error_bound,
+    }
+}
+
+pub fn compare_with_full_recompute(graph: &WebPageGraph, scores: &HashMap<String, f64>, config: &PageRankConfig) -> f64 {
+    /*
+
+        The L1 distance between incrementally updated scores and a full recompute, to check the incremental update against
+
+    */
+    let (full, _) = page_rank::page_rank(graph, config);
+    full.iter().map(|(url, score)| (score - scores.get(url).copied().unwrap_or(0.0)).abs()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synthetic_graph(node_count: usize, seed: u64) -> WebPageGraph {
+        let mut links: Vec<Vec<String>> = vec![Vec::new(); node_count];
+        for (source, target) in page_rank::synthetic_power_law_edges(node_count, 6, seed) {
+            links[source as usize].push(format!("page_{}", target));
+        }
+        let mut graph = WebPageGraph::new();
+        for (source, linked_urls) in links.iter().enumerate() {
+            graph.insert_page(&format!("page_{}", source), linked_urls);
+        }
+        graph
+    }
+
+    fn recrawled(graph: &WebPageGraph) -> WebPageGraph {
+        // a recrawl that drops some links, adds others, drops a page and finds some new ones
+        let mut graph = graph.node_hashmap.values().fold(WebPageGraph::new(), |mut copy, node| {
+            copy.insert_page(&node.url, &node.linked_urls);
+            copy
+        });
+        for source in (0..2000).step_by(97) {
+            let url = format!("page_{}", source);
+            let first_link = graph.node_hashmap[&url].linked_urls.first().cloned();
+            if let Some(first_link) = first_link {
+                graph.remove_link(&url, &first_link);
+            }
+            graph.add_link(&url, &format!("page_{}", (source * 7 + 3) % 2000));
+        }
+        graph.remove_page("page_1500");
+        for new_page in 0..20 {
+            graph.insert_page(&format!("new_page_{}", new_page), &["page_0".to_string(), format!("page_{}", new_page * 31)]);
+            graph.add_link("page_1", &format!("new_page_{}", new_page));
+        }
+        graph
+    }
+
+    fn exact_config() -> PageRankConfig {
+        PageRankConfig { tolerance: 1e-12, max_iterations: 1000, ..Default::default() }
+    }
+
+    fn link_sets(graph: &WebPageGraph) -> HashMap<String, HashSet<String>> {
+        graph.node_hashmap.iter().map(|(url, node)| (url.clone(), node.linked_urls.iter().cloned().collect())).collect()
+    }
+
+    #[test]
+    fn applying_the_diff_gives_the_new_graph() {
+        let old = synthetic_graph(2000, 11);
+        let new = recrawled(&old);
+
+        let mut edited = synthetic_graph(2000, 11);
+        apply_edits(&mut edited, &diff_graphs(&old, &new));
+        assert_eq!(link_sets(&edited), link_sets(&new));
+        assert!(diff_graphs(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn warm_start_matches_full_recompute_in_fewer_iterations() {
+        let old = synthetic_graph(2000, 11);
+        let (previous, _) = page_rank::page_rank(&old, &exact_config());
+        let new = recrawled(&old);
+
+        let config = PageRankConfig { tolerance: 1e-9, ..exact_config() };
+        let (scores, result) = warm_start_page_rank(&new, &previous, &config);
+        let (_, cold) = page_rank::page_rank(&new, &config);
+        assert!(result.converged);
+        assert!(result.iterations < cold.iterations, "warm start took {} iterations, cold start {}", result.iterations, cold.iterations);
+        assert!(compare_with_full_recompute(&new, &scores, &exact_config()) < 1e-7);
+    }
+
+    #[test]
+    fn push_update_is_within_its_error_bound() {
+        let old = synthetic_graph(2000, 11);
+        let (previous, _) = page_rank::page_rank(&old, &exact_config());
+        let new = recrawled(&old);
+
+        let update = push_page_rank_update(&new, &previous, 0.85, 1e-10);
+        let distance = compare_with_full_recompute(&new, &update.scores, &exact_config());
+        assert!(distance <= update.error_bound + 1e-9, "distance {:e} is over the error bound {:e}", distance, update.error_bound);
+        assert!(distance < 1e-5);
+
+        // nothing changed, so there is nothing to push
+        let unchanged = push_page_rank_update(&old, &previous, 0.85, 1e-10);
+        assert_eq!(unchanged.pushes, 0);
+    }
+}
@@ -0,0 +1,15 @@
+/*
+
+    The parts of the search engine that are shared between the crawler (main.rs) and the other binaries in src/bin, like the embedding server.
+
+*/
+
+pub mod atomic_file;
+pub mod embedding;
+pub mod fetch;
+pub mod hash;
+pub mod random;
+pub mod url_host;
+pub mod vector_search;
+#[cfg(test)]
+mod test_support;
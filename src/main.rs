@@ -19,43 +19,631 @@
 
 //use futures::executor::block_on;
 mod crawl;
+mod web_graph;
+mod compact_graph;
+mod graph_export;
+mod graph_analytics;
+mod graph_paths;
+mod page_rank;
+mod url_lookup;
+mod incremental_rank;
+mod hits;
+mod host_rank;
+mod trust_rank;
+#[cfg(test)]
+mod test_support;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs;
-use tokio;
+use std::path::Path;
+
+// The parts that are shared with the other binaries are in the library (lib.rs). They are brought in here, so that the crawler's modules use them as crate::embedding, crate::vector_search, ... like the rest of the crawler.
+use balene_search_engine::{atomic_file, embedding, fetch, random, url_host, vector_search};
+
+use compact_graph::CompactGraph;
+use graph_paths::PathFinder;
+use host_rank::HostRank;
+use trust_rank::{SpamSuspectReport, TrustScores, TrustSeeds};
+use url_lookup::{URLLookupHashMap, URLLookupTable};
+use web_graph::WebPageGraph;
 
 #[tokio::main]
 async fn main() {
-    // start crawling...
-    crawl::initialize_crawl().await;
+    let args: Vec<String> = env::args().collect();
 
+    match args.get(1).map(|arg| arg.as_str()) {
+        // find how two pages are connected: path <from> <to> [number of paths]
+        Some("path") if args.len() >= 4 => {
+            let path_count = args.get(4).map(|count| count.parse::<usize>().expect("number of paths must be a number")).unwrap_or(1);
+            print_paths(&args[2], &args[3], path_count);
+        }
+        // list the pages near a page: hops <page> <number of links>
+        Some("hops") if args.len() >= 4 => {
+            let max_hops = args[3].parse::<usize>().expect("number of links must be a number");
+            print_within_hops(&args[2], max_hops);
+        }
+        // calculate the page rank of the crawled graph, and save it with the other signals for each url
+        Some("rank") => {
+            build_url_lookup();
+        }
+        // update the page rank saved by rank after a recrawl, without recomputing it from scratch: rank-update [--push] [--check]
+        Some("rank-update") => {
+            update_page_rank(args[2..].iter().any(|arg| arg == "--push"), args[2..].iter().any(|arg| arg == "--check"));
+        }
+        // the ranking signals saved for some pages by rank: lookup <page> [page...]
+        Some("lookup") if args.len() >= 3 => {
+            print_url_signals(&args[2..]);
+        }
+        // personalized page rank around some pages: personalized <page> [page...]  or  personalized --category <category>
+        Some("personalized") if args.len() >= 3 => {
+            print_personalized_page_rank(&args[2..]);
+        }
+        // precompute the topic-sensitive page rank vectors for every topic in the topics file
+        Some("topic-rank") => {
+            build_topic_rank();
+        }
+        // blend the saved topic-sensitive page rank vectors for a query's topics: topic-blend <topic>[=weight] [topic[=weight]...]
+        Some("topic-blend") if args.len() >= 3 => {
+            print_topic_blend(&args[2..]);
+        }
+        // time the page rank solvers on a synthetic graph: bench-page-rank [number of pages] [average links per page]
+        Some("bench-page-rank") => {
+            let node_count = args.get(2).map(|count| count.parse::<usize>().expect("number of pages must be a number")).unwrap_or(1_000_000);
+            let average_degree = args.get(3).map(|degree| degree.parse::<usize>().expect("average links per page must be a number")).unwrap_or(10);
+            page_rank_benchmark(node_count, average_degree);
+        }
+        // calculate the host rank of every host in the crawled graph, and save it
+        Some("host-rank") => {
+            let graph = WebPageGraph::bincode_load(crawl::GRAPH_PATH);
+            build_host_rank(&graph);
+        }
+        // trust rank from the seeds file, and a report of the pages with high page rank but low trust: trust-rank [number of suspects]
+        Some("trust-rank") => {
+            let suspect_count = args.get(2).map(|count| count.parse::<usize>().expect("number of suspects must be a number")).unwrap_or(50);
+            build_spam_suspect_report(suspect_count);
+        }
+        // time the vector distance kernels: bench-distance [dimension] [number of vectors]
+        Some("bench-distance") => {
+            let dimension = args.get(2).map(|dimension| dimension.parse::<usize>().expect("dimension must be a number")).unwrap_or(768);
+            let vector_count = args.get(3).map(|count| count.parse::<usize>().expect("number of vectors must be a number")).unwrap_or(100_000);
+            distance_kernel_benchmark(dimension, vector_count);
+        }
+        // recall and memory of the quantized search against exact search: bench-quantization [dimension] [number of vectors]
+        Some("bench-quantization") => {
+            let dimension = args.get(2).map(|dimension| dimension.parse::<usize>().expect("dimension must be a number")).unwrap_or(512);
+            let vector_count = args.get(3).map(|count| count.parse::<usize>().expect("number of vectors must be a number")).unwrap_or(100_000);
+            quantization_benchmark(dimension, vector_count);
+        }
+        // measure the structure of the crawled graph, and save the report: analytics [report file]
+        Some("analytics") => {
+            let report_path = args.get(2).map(|path| path.as_str()).unwrap_or(graph_analytics::GRAPH_ANALYTICS_PATH);
+            let report = graph_analytics::analyze(&load_compact_graph());
+            report.print_report();
+            report.save_json(report_path);
+        }
+        // write the crawled graph out for other tools: export <graphml|dot|csv|tsv|json> <file> [--min-degree <n>] [--around <page> <number of links>] [--page-rank]
+        Some("export") if args.len() >= 4 => {
+            export_crawl_graph(&args[2], &args[3], &args[4..]);
+        }
+        // hubs and authorities around some pages: hits <page> [page...]
+        Some("hits") if args.len() >= 3 => {
+            print_query_hits(&args[2..]);
+        }
+        Some("path") | Some("hops") | Some("personalized") | Some("hits") | Some("export") | Some("lookup") | Some("topic-blend") => {
+            println!("usage:");
+            println!("\tpath <from page> <to page> [number of paths]");
+            println!("\thops <page> <number of links>");
+            println!("\trank");
+            println!("\trank-update [--push] [--check]");
+            println!("\tlookup <page> [page...]");
+            println!("\tpersonalized <page> [page...]");
+            println!("\tpersonalized --category <category>");
+            println!("\ttopic-rank");
+            println!("\ttopic-blend <topic>[=weight] [topic[=weight]...]");
+            println!("\thits <page> [page...]");
+            println!("\tanalytics [report file]");
+            println!("\texport <graphml|dot|csv|tsv|json> <file> [--min-degree <n>] [--around <page> <number of links>] [--page-rank]");
+            println!("\thost-rank");
+            println!("\ttrust-rank [number of suspects]");
+            println!("\tbench-page-rank [number of pages] [average links per page]");
+            println!("\tbench-distance [dimension] [number of vectors]");
+            println!("\tbench-quantization [dimension] [number of vectors]");
+            println!("\tembed-crawl");
+        }
+        Some("embed-crawl") => {
+            // crawl, embedding pages and images into the vector indexes as they are crawled
+            crawl::initialize_crawl(true).await;
+        }
+        _ => {
+            // start crawling...
+            crawl::initialize_crawl(false).await;
+        }
+    }
+}
 
+fn page_url(page: &str) -> String {
+    /*
+        Pages can be given on the command line either as a url, or as the title of a wikipedia article
+    */
+    if page.starts_with("http") {
+        return page.to_string();
+    }
+    format!("https://wikipedia.org/wiki/{}", page.replace(' ', "_"))
 }
 
+fn print_paths(from_page: &str, to_page: &str, path_count: usize) {
+    let path_finder = PathFinder::from_compact(load_compact_graph());
 
+    let paths = if path_count == 1 {
+        path_finder.shortest_path(&page_url(from_page), &page_url(to_page)).into_iter().collect()
+    } else {
+        path_finder.k_shortest_paths(&page_url(from_page), &page_url(to_page), path_count)
+    };
 
-// Ignore this
+    if paths.is_empty() {
+        println!("No path found from {} to {}", from_page, to_page);
+    }
+    for path in paths.iter() {
+        let titles: Vec<String> = path.iter().map(|url| graph_paths::page_title(url)).collect();
+        println!("{} links: {}", path.len() - 1, titles.join(" -> "));
+    }
+}
 
-async fn upsert_test() {
-    let upsert_res = crawl::send_image_url("https://upload.wikimedia.org/wikipedia/commons/thumb/7/73/Monarch_Butterfly_Danaus_plexippus_Male_2664px.jpg/220px-Monarch_Butterfly_Danaus_plexippus_Male_2664px.jpg","https://en.wikipedia.org/wiki/Monarch_butterfly");
+fn export_crawl_graph(format: &str, export_path: &str, flags: &[String]) {
+    /*
+        Export the crawled graph, optionally only the well linked pages, or the pages a few links away from one page, with their page rank
+    */
+    let format = graph_export::ExportFormat::parse(format).expect("format must be one of graphml, dot, csv, tsv or json");
 
-    match upsert_res.await {
-        Ok(message) => {
-            println!("{}", message);
+    let mut min_degree = 0;
+    let mut around: Option<(String, usize)> = None;
+    let mut with_page_rank = false;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--min-degree" => {
+                min_degree = flags.next().expect("--min-degree needs a number of links").parse::<usize>().expect("minimum degree must be a number");
+            }
+            "--around" => {
+                let page = flags.next().expect("--around needs a page and a number of links");
+                let max_hops = flags.next().expect("--around needs a page and a number of links").parse::<usize>().expect("number of links must be a number");
+                around = Some((page_url(page), max_hops));
+            }
+            "--page-rank" => with_page_rank = true,
+            _ => panic!("unknown export option {}", flag),
         }
-        Err(err) => {
-            println!("Error fetching html content: {}", err);
+    }
+
+    let graph = WebPageGraph::bincode_load(crawl::GRAPH_PATH);
+
+    let node_subset: Option<HashSet<String>> = around.map(|(url, max_hops)| {
+        let path_finder = PathFinder::new(&graph);
+        let mut subset: HashSet<String> = path_finder.within_hops(&url, max_hops).into_iter().map(|(url, _)| url).collect();
+        subset.insert(url);
+        subset
+    });
+
+    let page_rank: Option<HashMap<String, f64>> = with_page_rank.then(|| {
+        let (scores, result) = page_rank::page_rank(&graph, &page_rank::PageRankConfig::default());
+        println!("page rank: {} iterations, residual {:e}, converged: {}", result.iterations, result.residual, result.converged);
+        scores
+    });
+
+    let options = graph_export::ExportOptions {
+        node_subset: node_subset.as_ref(),
+        min_degree,
+        page_rank: page_rank.as_ref(),
+    };
+    graph_export::export_graph(&graph, format, &options, export_path);
+}
+
+fn load_compact_graph() -> CompactGraph {
+    /*
+        Memory map the compact graph that was saved from the crawl's graph, or build it again if the crawl's graph has changed since
+    */
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let saved = modified(compact_graph::COMPACT_GRAPH_PATH);
+    if saved.is_some() && saved >= modified(crawl::GRAPH_PATH) {
+        let compact_graph = CompactGraph::load_mmap(compact_graph::COMPACT_GRAPH_PATH);
+        println!("loaded compact graph: {} pages ({} crawled), {} links", compact_graph.node_count(), compact_graph.crawled_count(), compact_graph.edge_count());
+        return compact_graph;
+    }
+
+    CompactGraph::save_web_graph(&WebPageGraph::bincode_load(crawl::GRAPH_PATH), compact_graph::COMPACT_GRAPH_PATH);
+    let compact_graph = CompactGraph::load_mmap(compact_graph::COMPACT_GRAPH_PATH);
+    println!("built compact graph: {} pages ({} crawled), {} links", compact_graph.node_count(), compact_graph.crawled_count(), compact_graph.edge_count());
+    compact_graph
+}
+
+fn build_url_lookup() {
+    let graph = WebPageGraph::bincode_load(crawl::GRAPH_PATH);
+    let compact_graph = load_compact_graph();
+
+    let matrix = page_rank::construct_markov_transition_matrix_compact(&compact_graph);
+    let result = page_rank::power_iteration(&matrix, &page_rank::PageRankConfig::default());
+    println!("page rank: {} iterations, residual {:e}, converged: {}", result.iterations, result.residual, result.converged);
+    let scores = result.scores_by_url(&matrix);
+
+    let fetch_records = crawl::load_fetch_records(crawl::CRAWLER_PATH);
+
+    let hits_result = hits::global_hits(&compact_graph, &hits::HitsConfig::default());
+    println!("hits: {} iterations, converged: {}", hits_result.iterations, hits_result.converged);
+    let (hub, authority) = hits_result.scores_by_url(&compact_graph);
+
+    let host_rank = build_host_rank(&graph);
+
+    let mut lookup = URLLookupHashMap::from_crawl(&compact_graph, &scores, &fetch_records);
+    lookup.add_hits_scores(&hub, &authority);
+    lookup.add_host_rank(&host_rank);
+
+    // trust rank needs the hand picked seeds, so it is only included once there is a seeds file
+    if Path::new(trust_rank::TRUST_SEEDS_PATH).is_file() {
+        let seeds = TrustSeeds::load_json(trust_rank::TRUST_SEEDS_PATH);
+        match TrustScores::compute(&compact_graph, &page_rank::PageRankConfig::default(), &seeds) {
+            Some(trust_scores) => {
+                let (trust, distrust) = trust_scores.scores_by_url(&compact_graph);
+                lookup.add_trust_scores(&trust, &distrust);
+            }
+            None => println!("None of the trusted pages are in the graph, leaving out trust rank"),
         }
     }
+    lookup.save(url_lookup::URL_LOOKUP_PATH);
+
+    let mut ranked: Vec<(&String, &f64)> = scores.iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(a.1));
+    println!("Highest page rank:");
+    for (url, score) in ranked.iter().take(10) {
+        println!("\t{:.6}  {}", score, graph_paths::page_title(url));
+    }
 }
 
-fn parse_html_test() {
+fn update_page_rank(push: bool, check: bool) {
+    /*
+        The compact graph saved the last time the crawl was ranked is diffed against the crawl's graph, and the page rank saved in the url lookup table is updated for the edits, either with a warm started power iteration or by pushing residual from the pages that changed. The old scores only decide where the update starts from, so the result is correct even if they are out of date.
+    */
+    let config = page_rank::PageRankConfig::default();
 
-    let html_content = fs::read_to_string("monarch_butterfly.html").expect("Unable to read file");
-    
+    let mut graph = CompactGraph::load_mmap(compact_graph::COMPACT_GRAPH_PATH).to_web_graph();
+    let lookup = URLLookupTable::load_mmap(url_lookup::URL_LOOKUP_PATH);
+    let urls: Vec<&str> = graph.node_hashmap.values().flat_map(|node| std::iter::once(&node.url).chain(node.linked_urls.iter())).map(|url| url.as_str()).collect();
+    let previous: HashMap<String, f64> = urls.iter().zip(lookup.get_batch(&urls)).filter_map(|(url, payload)| payload.map(|payload| (url.to_string(), payload.page_rank as f64))).collect();
 
-    let parse_result = crawl::parse::extract_wikipedia_HTML(&html_content, "https://en.wikipedia.org/wiki/Monarch_butterfly");
+    let edits = incremental_rank::diff_graphs(&graph, &WebPageGraph::bincode_load(crawl::GRAPH_PATH));
+    println!("{} edits to the graph since it was last ranked", edits.len());
+    incremental_rank::apply_edits(&mut graph, &edits);
 
-    parse_result.print_info();
+    let scores = if push {
+        let update = incremental_rank::push_page_rank_update(&graph, &previous, config.damping_factor, 1e-10);
+        println!("push page rank update: {} pushes, residual {:e}, error bound {:e}", update.pushes, update.residual, update.error_bound);
+        update.scores
+    } else {
+        let (scores, result) = incremental_rank::warm_start_page_rank(&graph, &previous, &config);
+        println!("warm start page rank: {} iterations, residual {:e}, converged: {}", result.iterations, result.residual, result.converged);
+        scores
+    };
+
+    if check {
+        println!("L1 distance from a full recompute: {:e}", incremental_rank::compare_with_full_recompute(&graph, &scores, &config));
+    }
 
-    parse_result.make_content_html("monarch_butterfly_content.html");
+    let mut ranked: Vec<(&String, &f64)> = scores.iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(a.1));
+    println!("Highest page rank:");
+    for (url, score) in ranked.iter().take(10) {
+        println!("\t{:.6}  {}", score, graph_paths::page_title(url));
+    }
+}
+
+fn print_url_signals(pages: &[String]) {
+    /*
+        Print the signals that ranking would see for each page, from the url lookup table saved by rank
+    */
+    let table = URLLookupTable::load_mmap(url_lookup::URL_LOOKUP_PATH);
+    let urls: Vec<String> = pages.iter().map(|page| page_url(page)).collect();
+    let url_refs: Vec<&str> = urls.iter().map(|url| url.as_str()).collect();
+
+    for (url, payload) in urls.iter().zip(table.get_batch(&url_refs)) {
+        match payload {
+            Some(payload) => {
+                println!("{}", url);
+                println!("\tpage rank: {:.6}, hub: {:.6}, authority: {:.6}, host rank: {:.6}", payload.page_rank, payload.hub, payload.authority, payload.host_rank);
+                println!("\ttrust: {:.6}, distrust: {:.6}", payload.trust, payload.distrust);
+                println!("\tlinks in: {}, links out: {}", payload.in_degree, payload.out_degree);
+                match (payload.crawl_depth, payload.last_fetched) {
+                    (Some(depth), Some(fetched_at)) => println!("\tcrawled at depth {}, last fetched at {}", depth, fetched_at),
+                    _ => println!("\tnever crawled"),
+                }
+            }
+            None => println!("{} is not in the url lookup table ({} urls)", url, table.len()),
+        }
+    }
 }
 
+fn build_host_rank(graph: &WebPageGraph) -> HostRank {
+    let (host_rank, result) = HostRank::compute(graph, &page_rank::PageRankConfig::default());
+    println!("host rank: {} hosts, {} iterations, converged: {}", host_rank.scores.len(), result.iterations, result.converged);
+    host_rank.bincode_save(host_rank::HOST_RANK_PATH);
+
+    let mut ranked: Vec<(&String, &f32)> = host_rank.scores.iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(a.1));
+    println!("Highest host rank:");
+    for (host, score) in ranked.iter().take(10) {
+        println!("\t{:.6}  {}", score, host);
+    }
+
+    host_rank
+}
+
+fn build_spam_suspect_report(suspect_count: usize) {
+    // the seeds are picked by hand, so there is nothing to do until someone has written them
+    if !Path::new(trust_rank::TRUST_SEEDS_PATH).is_file() {
+        println!("trust-rank needs a seeds file at {}, with the urls of pages that are known to be trustworthy, and optionally pages that are known to be spam:", trust_rank::TRUST_SEEDS_PATH);
+        println!("\t{{\"trusted\": [\"https://wikipedia.org/wiki/Main_Page\"], \"spam\": []}}");
+        return;
+    }
+
+    let compact_graph = load_compact_graph();
+    let config = page_rank::PageRankConfig::default();
+
+    let matrix = page_rank::construct_markov_transition_matrix_compact(&compact_graph);
+    let result = page_rank::power_iteration(&matrix, &config);
+    println!("page rank: {} iterations, converged: {}", result.iterations, result.converged);
+
+    let seeds = TrustSeeds::load_json(trust_rank::TRUST_SEEDS_PATH);
+    let trust_scores = match TrustScores::compute(&compact_graph, &config, &seeds) {
+        Some(trust_scores) => trust_scores,
+        None => {
+            println!("None of the trusted pages in {} are in the graph", trust_rank::TRUST_SEEDS_PATH);
+            return;
+        }
+    };
+
+    let report = SpamSuspectReport::from_scores(&compact_graph, &result.scores, &trust_scores, suspect_count);
+    report.print_report();
+    report.save_json(trust_rank::SPAM_REPORT_PATH);
+}
+
+fn print_personalized_page_rank(targets: &[String]) {
+    let graph = WebPageGraph::bincode_load(crawl::GRAPH_PATH);
+    let matrix = page_rank::construct_markov_transition_matrix(&graph);
+
+    let target = if targets[0] == "--category" && targets.len() >= 2 {
+        page_rank::PersonalizationTarget::Category(targets[1].clone())
+    } else if targets.len() == 1 {
+        page_rank::PersonalizationTarget::Page(page_url(&targets[0]))
+    } else {
+        page_rank::PersonalizationTarget::Pages(targets.iter().map(|page| page_url(page)).collect())
+    };
+
+    let topics = match target {
+        page_rank::PersonalizationTarget::Category(_) => page_rank::Topics::load_json(page_rank::TOPICS_PATH),
+        _ => page_rank::Topics { categories: Default::default() },
+    };
+
+    match page_rank::personalized_page_rank(&matrix, &page_rank::PageRankConfig::default(), &target, &topics) {
+        Some(result) => {
+            let mut ranked: Vec<(usize, f64)> = result.scores.iter().copied().enumerate().collect();
+            ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+            println!("Highest personalized page rank:");
+            for (index, score) in ranked.iter().take(20) {
+                println!("\t{:.6}  {}", score, graph_paths::page_title(&matrix.urls[*index]));
+            }
+        }
+        None => println!("None of those pages are in the crawled graph"),
+    }
+}
+
+fn print_query_hits(pages: &[String]) {
+    let compact_graph = load_compact_graph();
+
+    let root_urls: Vec<String> = pages.iter().map(|page| page_url(page)).collect();
+    let result = hits::query_hits(&compact_graph, &root_urls, &hits::HitsConfig::default());
+    println!("{} pages in the subgraph, {} iterations, converged: {}", result.nodes.len(), result.iterations, result.converged);
+
+    let mut by_hub: Vec<usize> = (0..result.nodes.len()).collect();
+    by_hub.sort_by(|a, b| result.hub[*b].total_cmp(&result.hub[*a]));
+    println!("Top hubs:");
+    for index in by_hub.iter().take(10) {
+        println!("\t{:.6}  {}", result.hub[*index], graph_paths::page_title(compact_graph.url(result.nodes[*index])));
+    }
+
+    // the global page rank of each authority is shown next to it once rank has saved the url lookup table
+    let lookup = Path::new(url_lookup::URL_LOOKUP_PATH).is_file().then(|| URLLookupTable::load_mmap(url_lookup::URL_LOOKUP_PATH));
+
+    let mut by_authority: Vec<usize> = (0..result.nodes.len()).collect();
+    by_authority.sort_by(|a, b| result.authority[*b].total_cmp(&result.authority[*a]));
+    println!("Top authorities:");
+    for index in by_authority.iter().take(10) {
+        let url = compact_graph.url(result.nodes[*index]);
+        match lookup.as_ref() {
+            Some(lookup) => println!("\t{:.6}  {}  (page rank {:.6})", result.authority[*index], graph_paths::page_title(url), lookup.page_rank(url)),
+            None => println!("\t{:.6}  {}", result.authority[*index], graph_paths::page_title(url)),
+        }
+    }
+}
+
+fn build_topic_rank() {
+    let graph = WebPageGraph::bincode_load(crawl::GRAPH_PATH);
+    let matrix = page_rank::construct_markov_transition_matrix(&graph);
+    let topics = page_rank::Topics::load_json(page_rank::TOPICS_PATH);
+
+    let topic_rank = page_rank::TopicSensitivePageRank::compute(&matrix, &page_rank::PageRankConfig::default(), &topics);
+    topic_rank.bincode_save(page_rank::TOPIC_RANK_PATH);
+}
+
+fn print_topic_blend(topics: &[String]) {
+    /*
+        Topics are given as name=weight, or just name for a weight of 1
+    */
+    let topic_weights: HashMap<String, f64> = topics.iter().map(|topic| match topic.split_once('=') {
+        Some((name, weight)) => (name.to_string(), weight.parse::<f64>().expect("topic weight must be a number")),
+        None => (topic.clone(), 1.0),
+    }).collect();
+
+    let topic_rank = page_rank::TopicSensitivePageRank::bincode_load(page_rank::TOPIC_RANK_PATH);
+    for topic in topic_weights.keys().filter(|topic| !topic_rank.topics.contains(topic)) {
+        println!("topic {} is not in the topic-sensitive page rank, known topics: {}", topic, topic_rank.topics.join(", "));
+    }
+
+    let blended = topic_rank.blend(&topic_weights);
+    let mut ranked: Vec<(&String, &f64)> = blended.iter().filter(|(_, score)| **score > 0.0).collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(a.1));
+    println!("Highest blended topic page rank:");
+    for (url, score) in ranked.iter().take(20) {
+        println!("\t{:.6}  {}", score, graph_paths::page_title(url));
+    }
+}
+
+fn print_within_hops(page: &str, max_hops: usize) {
+    let path_finder = PathFinder::from_compact(load_compact_graph());
+
+    let reached = path_finder.within_hops(&page_url(page), max_hops);
+    println!("{} pages within {} links of {}", reached.len(), max_hops, page);
+    for (url, distance) in reached.iter() {
+        println!("\t{}  {}", distance, graph_paths::page_title(url));
+    }
+}
+
+
+
+fn distance_kernel_benchmark(dimension: usize, vector_count: usize) {
+    /*
+        Times each distance kernel that this CPU supports, comparing one query against many vectors
+    */
+    use vector_search::distance::{self, Kernel};
+    use std::time::Instant;
+
+    let vectors: Vec<f32> = (0..dimension * vector_count).map(|index| ((index * 7919) % 1000) as f32 / 1000.0 - 0.5).collect();
+    let query: Vec<f32> = (0..dimension).map(|index| ((index * 104729) % 1000) as f32 / 1000.0 - 0.5).collect();
+    println!("{} vectors with {} dimensions", vector_count, dimension);
+
+    type DistanceFunction = fn(Kernel, &[f32], &[f32]) -> f32;
+    let kernels: Vec<(&str, DistanceFunction)> = vec![
+        ("dot", distance::dot_with),
+        ("l2 squared", distance::l2_squared_with),
+        ("cosine", distance::cosine_with),
+    ];
+    for (name, function) in kernels.iter() {
+        for kernel in distance::supported_kernels() {
+            let start = Instant::now();
+            let mut total = 0.0;
+            for vector in vectors.chunks(dimension) {
+                total += function(kernel, &query, vector);
+            }
+            let seconds = start.elapsed().as_secs_f64();
+            println!("{} {:?}: {:.3}s ({:.1} million vectors/s, checksum {:.3})", name, kernel, seconds, vector_count as f64 / seconds / 1e6, total);
+        }
+    }
+
+    let mut out: Vec<f32> = vec![0.0; vector_count];
+    let start = Instant::now();
+    distance::dot_batch(&query, &vectors, &mut out);
+    let seconds = start.elapsed().as_secs_f64();
+    println!("dot batch {:?}: {:.3}s ({:.1} million vectors/s)", distance::detected_kernel(), seconds, vector_count as f64 / seconds / 1e6);
+}
+
+fn quantization_benchmark(dimension: usize, vector_count: usize) {
+    /*
+        Reports the recall, memory and search time of each quantized search mode against exact search, on clustered vectors like real embeddings
+    */
+    use random::XorShift64;
+    use vector_search::quantization::QuantizationConfig;
+    use vector_search::{SimpleSearch, DistanceMetric, PointVector, QueryVector, VectorPayload, VectorSearchClient};
+    use std::collections::HashSet;
+    use std::time::Instant;
+
+    let mut random = XorShift64::new(17);
+    let centers: Vec<Vec<f32>> = (0..100).map(|_| random.vector(dimension)).collect();
+    let mut clustered_vector = |center: usize| -> Vec<f32> {
+        (0..dimension).map(|d| centers[center % centers.len()][d] + 0.5 * random.next_signed_f32()).collect()
+    };
+
+    println!("Generating {} vectors with {} dimensions...", vector_count, dimension);
+    let mut index = SimpleSearch::new(dimension, DistanceMetric::Cosine);
+    for id in 0..vector_count {
+        index.upsert(id as u64, PointVector { vec: clustered_vector(id), payload: VectorPayload::default() });
+    }
+    let queries: Vec<QueryVector> = (0..100).map(|index| QueryVector { vec: clustered_vector(index * 7) }).collect();
+    let top_k = 10;
+
+    // quantizing saves the index with its codes and maps its vectors from there
+    let index_path = std::env::temp_dir().join("balene_quantization_benchmark.bin").to_string_lossy().to_string();
+
+    let start = Instant::now();
+    let expected: Vec<HashSet<u64>> = queries.iter().map(|query| index.search(query, top_k).into_iter().map(|result| result.id).collect()).collect();
+    let exact_seconds = start.elapsed().as_secs_f64();
+    println!("exact: recall@{} = 1.000, {:.1} MB, {:.2} ms/query", top_k, index.vector_bytes() as f64 / 1e6, exact_seconds * 1000.0 / queries.len() as f64);
+
+    let configs = [
+        ("int8", QuantizationConfig { binary_prefilter: false, rescore_oversample: 0, ..Default::default() }),
+        ("int8, rescored", QuantizationConfig { binary_prefilter: false, ..Default::default() }),
+        ("binary x16 + int8, rescored", QuantizationConfig { binary_oversample: 16, ..Default::default() }),
+        ("binary x64 + int8", QuantizationConfig { rescore_oversample: 0, ..Default::default() }),
+        ("binary x64 + int8, rescored", QuantizationConfig::default()),
+        ("binary x256 + int8, rescored", QuantizationConfig { binary_oversample: 256, ..Default::default() }),
+    ];
+    for (name, config) in configs {
+        let start = Instant::now();
+        index.quantize(config, &index_path);
+        let quantize_seconds = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let mut found = 0;
+        for (query, expected) in queries.iter().zip(expected.iter()) {
+            found += index.search(query, top_k).into_iter().filter(|result| expected.contains(&result.id)).count();
+        }
+        let seconds = start.elapsed().as_secs_f64();
+        let memory = index.quantized_memory_bytes().unwrap();
+        println!("{}: recall@{} = {:.3}, {:.1} MB of codes in memory ({:.1}x smaller than the vectors, which are read from disk), {:.2} ms/query, quantized and saved in {:.2}s",
+            name, top_k, found as f64 / (queries.len() * top_k) as f64, memory as f64 / 1e6, index.vector_bytes() as f64 / memory as f64,
+            seconds * 1000.0 / queries.len() as f64, quantize_seconds);
+    }
+    drop(index);
+    let _ = std::fs::remove_file(&index_path);
+}
+
+fn page_rank_benchmark(node_count: usize, average_degree: usize) {
+    /*
+        Times each of the page rank solvers on a synthetic power-law graph, and reports how many iterations each one does per second
+    */
+    use page_rank::{PageRankConfig, PageRankMethod, SparseMarkovTransitionMatrix};
+    use std::time::Instant;
+
+    println!("Generating a power-law graph with {} pages...", node_count);
+    let edges = page_rank::synthetic_power_law_edges(node_count, average_degree, 42);
+    let matrix = SparseMarkovTransitionMatrix::from_edges(node_count, &edges);
+    println!("{} pages, {} links", node_count, edges.len());
+
+    let configs = [
+        ("jacobi, 1 thread", PageRankConfig { threads: 1, ..Default::default() }),
+        ("jacobi, all threads", PageRankConfig { threads: 0, ..Default::default() }),
+        ("jacobi, all threads, adaptive", PageRankConfig { threads: 0, adaptive_threshold: Some(1e-5), ..Default::default() }),
+        ("gauss-seidel", PageRankConfig { method: PageRankMethod::GaussSeidel, ..Default::default() }),
+    ];
+
+    let mut reference: Option<Vec<f64>> = None;
+    for (name, config) in configs.iter() {
+        let start = Instant::now();
+        let result = page_rank::power_iteration(&matrix, config);
+        let seconds = start.elapsed().as_secs_f64();
+
+        // how far each solver's answer is from the first one's
+        let difference: f64 = match &reference {
+            Some(reference) => reference.iter().zip(result.scores.iter()).map(|(a, b)| (a - b).abs()).sum(),
+            None => 0.0,
+        };
+
+        println!("{}: {} iterations in {:.3}s ({:.2} iterations/s), converged: {}, L1 difference from first: {:e}",
+            name,
+            result.iterations,
+            seconds,
+            result.iterations as f64 / seconds,
+            result.converged,
+            difference,
+        );
+
+        if reference.is_none() {
+            reference = Some(result.scores);
+        }
+    }
+}
@@ -1,22 +1,86 @@
 
-/*
-struct TransitionMatrixEdge<'a> {
+use bincode::{config, Decode, Encode};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::thread;
+
+use crate::random::XorShift64;
+
+use crate::atomic_file::write_atomic;
+use crate::compact_graph::CompactGraph;
+use crate::web_graph::WebPageGraph;
+
+// the pages that make up each topic, for personalized and topic-sensitive page rank
+pub const TOPICS_PATH: &str = "crawl_history/topics.json";
+
+// place where the precomputed topic-sensitive page rank vectors are stored
+pub const TOPIC_RANK_PATH: &str = "crawl_history/topic_rank_1.bin";
+
+pub struct PageRankConfig {
+    /*
+    
+        Settings for the power iteration
+    
+    */
+
+    // The probability that the random surfer follows a link, instead of jumping to a random page
+    pub damping_factor: f64,
+
+    // The iteration stops once the L1 distance between two iterations is below this
+    pub tolerance: f64,
+
+    // The iteration stops after this many iterations, even if it has not converged
+    pub max_iterations: usize,
+
+    // How each iteration updates the scores
+    pub method: PageRankMethod,
+
+    // The number of threads used for the power iteration. 0 uses every core.
+    pub threads: usize,
+
+    // If this is set, a page stops being updated once its score changes by less than this fraction of itself in one iteration (adaptive page rank). This skips most of the work in later iterations, because most pages converge long before the slowest ones, at the cost of a slightly less accurate result.
+    pub adaptive_threshold: Option<f64>,
+}
+
+impl Default for PageRankConfig {
+    fn default() -> Self {
+        PageRankConfig {
+            damping_factor: 0.85,
+            tolerance: 1e-6,
+            max_iterations: 100,
+            method: PageRankMethod::Jacobi,
+            threads: 0,
+            adaptive_threshold: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageRankMethod {
+    // Every score in an iteration is calculated from the previous iteration's scores. The rows are split between threads.
+    Jacobi,
+
+    // The scores are updated in place, so each score is calculated from the newest scores available. Whether this needs fewer iterations than Jacobi depends on how the rows are ordered (it helps most when pages come after the pages that link to them), and it has to run on one thread.
+    GaussSeidel,
+}
+
+pub struct TransitionMatrixEdge {
     /*
     
         This populates the rows of the transition matrix
     
     */
 
-    outgoing_node: &'a WebPageNode,
-    incoming_node: &'a WebPageNode,
-     
+    // The index of the node that the edge comes from
+    pub outgoing_node: usize,
 
     // The probability that this edge will be traversed by a markov chain, given that it is already at 'outgoing_node'
-    transition_probability: f32,
-
+    pub transition_probability: f64,
 }
 
-struct SparseTransitionMatrixRow {
+pub struct SparseTransitionMatrixRow {
     /*
     
         A row in the sparse transition matrix. It contains a list representing each of the edges and their probabilities in the row.
@@ -24,14 +88,10 @@ struct SparseTransitionMatrixRow {
         Each row represents a node, and each edge inside the row contains the probability that a markov chain at the outgoing_node of the edge will traverse to the node. 
     
     */
-
-    // This is the url of the web page that the row represents
-    url: String,
-
-    transition_edges: Vec<TransitionMatrixEdge>,
+    pub transition_edges: Vec<TransitionMatrixEdge>,
 }
 
-struct SparseMarkovTransitionMatrix {
+pub struct SparseMarkovTransitionMatrix {
     /*
     
         Given a graph for a web page, we want to constuct a sparse matrix that represents the transition probabilities for the web page graph. Each row of the matrix represents the probability vector of arriving at that node from the node represented by each given index. If x_n is the probability vector representing the probability of being at each specific node at step n, then the probability of being at each specific node at step n+1 is the matrix vector product of P and x_n (Where P is the transition matrix, and P(a,b) is the probability of transitioning to node b from node a): 
@@ -50,63 +110,646 @@ struct SparseMarkovTransitionMatrix {
         [ ...                    ... ]  ...
         [P(0,m), P(1,m), ... , P(m,m)] [P(m)]
 
+        Pages with no outgoing links (dangling nodes) have a column of zeros, so the probability of being at them would leak out of the vector. They are kept track of separately, so that their probability can be spread back over every page.
+
     */
-    transition_matrix_rows: Vec<SparseTransitionMatrixRow>
 
+    // The url of the web page that each row (and column) represents
+    pub urls: Vec<String>,
+
+    pub transition_matrix_rows: Vec<SparseTransitionMatrixRow>,
+
+    // The indices of the nodes that have no outgoing links
+    pub dangling_nodes: Vec<usize>,
+}
+
+impl SparseMarkovTransitionMatrix {
+    pub fn node_count(&self) -> usize {
+        self.urls.len()
+    }
+
+    pub fn multiply(&self, x: &[f64], out: &mut [f64]) {
+        /*
+        
+            out = Px, the probability of being at each node after following one link from x. This does not include the probability that was at dangling nodes.
+        
+        */
+        for (row, out_value) in self.transition_matrix_rows.iter().zip(out.iter_mut()) {
+            let mut sum = 0.0;
+            for edge in row.transition_edges.iter() {
+                sum += edge.transition_probability * x[edge.outgoing_node];
+            }
+            *out_value = sum;
+        }
+    }
+
+    pub fn dangling_mass(&self, x: &[f64]) -> f64 {
+        self.dangling_nodes.iter().map(|node| x[*node]).sum()
+    }
+
+    pub fn from_edges(node_count: usize, edges: &[(u32, u32)]) -> SparseMarkovTransitionMatrix {
+        /*
+        
+            Build a transition matrix straight from (source, target) pairs, for graphs that don't come from a crawl. The urls are the node indices.
+        
+        */
+        let mut out_degree: Vec<u32> = vec![0; node_count];
+        for (source, _) in edges.iter() {
+            out_degree[*source as usize] += 1;
+        }
+
+        let mut transition_matrix_rows: Vec<SparseTransitionMatrixRow> = (0..node_count).map(|_| SparseTransitionMatrixRow { transition_edges: Vec::new() }).collect();
+        for (source, target) in edges.iter() {
+            transition_matrix_rows[*target as usize].transition_edges.push(TransitionMatrixEdge {
+                outgoing_node: *source as usize,
+                transition_probability: 1.0 / out_degree[*source as usize] as f64,
+            });
+        }
+
+        SparseMarkovTransitionMatrix {
+            urls: (0..node_count).map(|node| node.to_string()).collect(),
+            transition_matrix_rows,
+            dangling_nodes: (0..node_count).filter(|node| out_degree[*node] == 0).collect(),
+        }
+    }
+
+    pub fn partition_rows(&self, partition_count: usize) -> Vec<Range<usize>> {
+        /*
+        
+            Split the rows into contiguous ranges with about the same number of edges in each, so that every thread gets the same amount of work. Splitting by the number of rows instead would give the thread with the most linked to pages far more work than the others.
+        
+        */
+        let node_count = self.node_count();
+        let partition_count = partition_count.max(1).min(node_count.max(1));
+
+        // each row also costs a little on its own, even if it has no edges
+        let total_work: usize = self.transition_matrix_rows.iter().map(|row| row.transition_edges.len() + 1).sum();
+        let work_per_partition = total_work.div_ceil(partition_count);
+
+        let mut partitions: Vec<Range<usize>> = Vec::with_capacity(partition_count);
+        let mut start = 0;
+        let mut work = 0;
+        for (row_index, row) in self.transition_matrix_rows.iter().enumerate() {
+            work += row.transition_edges.len() + 1;
+            if work >= work_per_partition && partitions.len() < partition_count - 1 {
+                partitions.push(start..row_index + 1);
+                start = row_index + 1;
+                work = 0;
+            }
+        }
+        partitions.push(start..node_count);
+        partitions
+    }
+
+    pub fn url_index(&self) -> HashMap<&str, usize> {
+        self.urls.iter().enumerate().map(|(index, url)| (url.as_str(), index)).collect()
+    }
 }
 
-fn construct_markov_transition_matrix(graph: &WebPageGraph) -> SparseMarkovTransitionMatrix 
+pub fn construct_markov_transition_matrix(graph: &WebPageGraph) -> SparseMarkovTransitionMatrix 
 {
     /*
     
         Construct the markov transition matrix a web page graph. This is essentially transposing a sparse matrix. It is assumed that when a markov chain is at a specific node, it is equiprobable that it will traverse to any of the node's outgoing edges. 
+
+        Every url in the graph becomes a node, including the ones that were linked to but never crawled.
     
     */
 
-    // first, create the matrix rows. It is nescessary to create all of the rows before they are populated by web pages. 
-    let mut transition_matrix_rows: Vec<SparseTransitionMatrixRow> = Vec::new();
-    for node in graph.nodes.into_iter() {
+    // The compact graph has already done the transposing: its incoming links are exactly the edges of each row
+    let compact_graph = CompactGraph::from_web_graph(graph);
+    construct_markov_transition_matrix_compact(&compact_graph)
+}
+
+pub fn construct_markov_transition_matrix_compact(graph: &CompactGraph) -> SparseMarkovTransitionMatrix
+{
+    let node_count = graph.node_count();
+
+    let mut urls: Vec<String> = Vec::with_capacity(node_count);
+    let mut transition_matrix_rows: Vec<SparseTransitionMatrixRow> = Vec::with_capacity(node_count);
+    let mut dangling_nodes: Vec<usize> = Vec::new();
+
+    for id in 0..node_count as u32 {
+        urls.push(graph.url(id).to_string());
+
+        let transition_edges = graph.incoming(id).map(|source| TransitionMatrixEdge {
+            outgoing_node: source as usize,
+            transition_probability: 1.0 / graph.out_degree(source) as f64,
+        }).collect();
+        transition_matrix_rows.push(SparseTransitionMatrixRow { transition_edges });
+
+        if graph.out_degree(id) == 0 {
+            dangling_nodes.push(id as usize);
+        }
+    }
+
+    SparseMarkovTransitionMatrix {
+        urls,
+        transition_matrix_rows,
+        dangling_nodes,
+    }
+}
+
+pub struct PageRankResult {
+    // The page rank of each node, in the same order as the matrix's urls. These sum to 1.
+    pub scores: Vec<f64>,
+
+    pub iterations: usize,
+
+    // The L1 distance between the last two iterations
+    pub residual: f64,
+
+    pub converged: bool,
+}
+
+impl PageRankResult {
+    pub fn scores_by_url(&self, matrix: &SparseMarkovTransitionMatrix) -> HashMap<String, f64> {
+        matrix.urls.iter().cloned().zip(self.scores.iter().copied()).collect()
+    }
+}
+
+pub fn page_rank(graph: &WebPageGraph, config: &PageRankConfig) -> (HashMap<String, f64>, PageRankResult) {
+    /*
+    
+        Calculate the page rank of every url in a web page graph. The result is returned as well, for its iteration count and whether it converged.
+    
+    */
+    let matrix = construct_markov_transition_matrix(graph);
+    let result = power_iteration(&matrix, config);
+
+    (result.scores_by_url(&matrix), result)
+}
+
+pub fn power_iteration(matrix: &SparseMarkovTransitionMatrix, config: &PageRankConfig) -> PageRankResult {
+    /*
+    
+        Find the stationary distribution of the random surfer by repeatedly applying
+
+            x_{n+1} = d * (Px_n + dangling_mass / N) + (1 - d) / N
+
+        starting from the uniform distribution, where d is the damping factor, and dangling_mass is the probability that was at pages with no outgoing links.
+    
+    */
+    let node_count = matrix.node_count();
+    let uniform: Vec<f64> = vec![1.0 / node_count as f64; node_count];
+
+    iterate(matrix, config, &uniform, uniform.clone())
+}
+
+pub fn power_iteration_from(matrix: &SparseMarkovTransitionMatrix, config: &PageRankConfig, initial: Vec<f64>) -> PageRankResult {
+    /*
+    
+        The same as power_iteration, but starting from the given scores instead of the uniform distribution. Starting from the page rank of a similar graph (e.g. before a recrawl) converges in far fewer iterations.
+    
+    */
+    let node_count = matrix.node_count();
+    let uniform: Vec<f64> = vec![1.0 / node_count as f64; node_count];
+
+    iterate(matrix, config, &uniform, initial)
+}
+
+pub fn personalized_power_iteration(matrix: &SparseMarkovTransitionMatrix, config: &PageRankConfig, teleport: &[f64]) -> PageRankResult {
+    /*
+    
+        Personalized page rank. Instead of jumping to any page with equal probability, the random surfer jumps according to the teleport vector (which must sum to 1), so pages close to the pages in the teleport vector get a higher score.
+
+            x_{n+1} = d * (Px_n + dangling_mass * t) + (1 - d) * t
+
+        The probability at dangling nodes is sent through the teleport vector as well, so that none of it ends up on unrelated pages.
+    
+    */
+    iterate(matrix, config, teleport, teleport.to_vec())
+}
+
+fn iterate(matrix: &SparseMarkovTransitionMatrix, config: &PageRankConfig, teleport: &[f64], initial: Vec<f64>) -> PageRankResult {
+    let node_count = matrix.node_count();
+    if node_count == 0 {
+        return PageRankResult { scores: Vec::new(), iterations: 0, residual: 0.0, converged: true };
+    }
+
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+        threads => threads,
+    };
+    let partitions = matrix.partition_rows(threads);
+
+    let mut x: Vec<f64> = initial;
+    let mut next_x: Vec<f64> = vec![0.0; node_count];
+
+    // pages that have stopped changing, when adaptive page rank is used
+    let mut frozen: Vec<bool> = vec![false; node_count];
+
+    let mut iterations = 0;
+    let mut residual = f64::INFINITY;
+    while iterations < config.max_iterations {
+        // the probability at dangling nodes, and the probability of teleporting, are both spread over the pages in the teleport vector
+        let spread = config.damping_factor * matrix.dangling_mass(&x) + (1.0 - config.damping_factor);
+
+        let step = IterationStep {
+            matrix,
+            teleport,
+            damping_factor: config.damping_factor,
+            spread,
+            adaptive_threshold: config.adaptive_threshold,
+        };
 
-        // create the row for the transition matrix
-        let row = SparseTransitionMatrixRow {
-            url: node.url,
-            transition_edges: Vec::new(), 
+        residual = match config.method {
+            PageRankMethod::Jacobi => {
+                let residual = step.jacobi(&partitions, &x, &mut next_x, &mut frozen);
+                std::mem::swap(&mut x, &mut next_x);
+                residual
+            }
+            PageRankMethod::GaussSeidel => step.gauss_seidel(&mut x, &mut frozen),
         };
-        transition_matrix_rows.push(row);
+        iterations += 1;
+
+        if residual < config.tolerance {
+            break;
+        }
+    }
+
+    // rounding error can make the total drift slightly away from 1, and Gauss-Seidel doesn't keep the total at 1 between iterations
+    let total: f64 = x.iter().sum();
+    for value in x.iter_mut() {
+        *value /= total;
+    }
+
+    PageRankResult {
+        scores: x,
+        iterations,
+        residual,
+        converged: residual < config.tolerance,
+    }
+}
+
+struct IterationStep<'a> {
+    /*
+    
+        Everything needed to update the scores for one iteration:
+
+            x_{n+1}[i] = d * (Px_n)[i] + spread * t[i]
+    
+    */
+    matrix: &'a SparseMarkovTransitionMatrix,
+    teleport: &'a [f64],
+    damping_factor: f64,
+    spread: f64,
+    adaptive_threshold: Option<f64>,
+}
+
+impl<'a> IterationStep<'a> {
+    fn update_row(&self, row_index: usize, x: &[f64]) -> f64 {
+        let mut sum = 0.0;
+        for edge in self.matrix.transition_matrix_rows[row_index].transition_edges.iter() {
+            sum += edge.transition_probability * x[edge.outgoing_node];
+        }
+        self.damping_factor * sum + self.spread * self.teleport[row_index]
+    }
+
+    fn has_converged(&self, old_value: f64, new_value: f64) -> bool {
+        match self.adaptive_threshold {
+            Some(threshold) => (new_value - old_value).abs() <= threshold * old_value.abs(),
+            None => false,
+        }
+    }
+
+    fn jacobi_rows(&self, rows: Range<usize>, x: &[f64], next_x: &mut [f64], frozen: &mut [bool]) -> f64 {
+        // next_x and frozen only hold this partition's rows
+        let start = rows.start;
+        let mut residual = 0.0;
+        for row_index in rows {
+            let local = row_index - start;
+            if frozen[local] {
+                next_x[local] = x[row_index];
+                continue;
+            }
+            let new_value = self.update_row(row_index, x);
+            residual += (new_value - x[row_index]).abs();
+            frozen[local] = self.has_converged(x[row_index], new_value);
+            next_x[local] = new_value;
+        }
+        residual
+    }
+
+    fn jacobi(&self, partitions: &[Range<usize>], x: &[f64], next_x: &mut [f64], frozen: &mut [bool]) -> f64 {
+        /*
+        
+            One iteration, with each partition of rows calculated on its own thread. Every thread only writes to its own part of next_x, so no locks are needed.
+        
+        */
+        if partitions.len() == 1 {
+            return self.jacobi_rows(partitions[0].clone(), x, next_x, frozen);
+        }
+
+        thread::scope(|scope| {
+            let mut handles = Vec::with_capacity(partitions.len());
+            let mut rest_x: &mut [f64] = next_x;
+            let mut rest_frozen: &mut [bool] = frozen;
+            for rows in partitions.iter() {
+                let (part_x, remaining_x) = rest_x.split_at_mut(rows.len());
+                let (part_frozen, remaining_frozen) = rest_frozen.split_at_mut(rows.len());
+                rest_x = remaining_x;
+                rest_frozen = remaining_frozen;
+
+                let rows = rows.clone();
+                handles.push(scope.spawn(move || self.jacobi_rows(rows, x, part_x, part_frozen)));
+            }
+            handles.into_iter().map(|handle| handle.join().expect("page rank thread panicked")).sum()
+        })
     }
 
-    // Now, we iterate through the graph nodes again, and add the edges to their existing nodes. 
-    for node in graph.nodes.into_iter() {
-        let outgoing_nodes_i32 = node.outgoing_nodes.len();
-        let outgoing_nodes_f32 = outgoing_nodes_i32 as f32;
+    fn gauss_seidel(&self, x: &mut [f64], frozen: &mut [bool]) -> f64 {
+        // One iteration, updating the scores in place
+        let mut residual = 0.0;
+        for row_index in 0..x.len() {
+            if frozen[row_index] {
+                continue;
+            }
+            let old_value = x[row_index];
+            let new_value = self.update_row(row_index, x);
+            residual += (new_value - old_value).abs();
+            frozen[row_index] = self.has_converged(old_value, new_value);
+            x[row_index] = new_value;
+        }
+        residual
+    }
+}
+
+pub fn synthetic_power_law_edges(node_count: usize, average_degree: usize, seed: u64) -> Vec<(u32, u32)> {
+    /*
+    
+        Generate a random graph where the number of links to each page follows a power law, like the web. Each link goes to the target of a random earlier link (preferential attachment) most of the time, so pages that are already linked to a lot get linked to even more, and to a random page the rest of the time.
+    
+    */
+    let mut random = XorShift64::new(seed);
+    let mut next_random = move || random.next_u64();
 
-        // iterate through the outgoing edges of the node:
-        for outgoing_edge_url in node.outgoing_node_urls {
-            let edge = TransitionMatrixEdge{
-                outgoing_node: *node,
-                incoming_node: node_from_url(outgoing_edge_url),
-                transition_probability: 1/outgoing_nodes_f32,
+    let mut edges: Vec<(u32, u32)> = Vec::with_capacity(node_count * average_degree);
+    for source in 0..node_count {
+        // the number of links out of a page varies between 0 and twice the average
+        let degree = (next_random() % (2 * average_degree as u64 + 1)) as usize;
+        for _ in 0..degree {
+            let target = if !edges.is_empty() && next_random() % 4 != 0 {
+                edges[(next_random() % edges.len() as u64) as usize].1
+            } else {
+                (next_random() % node_count as u64) as u32
             };
+            if target as usize != source {
+                edges.push((source as u32, target));
+            }
         }
+    }
+    edges
+}
+
+pub fn teleport_vector(matrix: &SparseMarkovTransitionMatrix, seed_urls: &[String]) -> Option<Vec<f64>> {
+    /*
+    
+        A teleport vector that jumps to each of the seed pages with equal probability. Returns None if none of the seed pages are in the graph.
+    
+    */
+    let index = matrix.url_index();
+    let seeds: HashSet<usize> = seed_urls.iter().filter_map(|url| index.get(url.as_str()).copied()).collect();
+    if seeds.is_empty() {
+        return None;
+    }
 
+    let mut teleport: Vec<f64> = vec![0.0; matrix.node_count()];
+    for seed in seeds.iter() {
+        teleport[*seed] = 1.0 / seeds.len() as f64;
     }
+    Some(teleport)
 }
 
-*/
+pub enum PersonalizationTarget {
+    /*
+    
+        What the random surfer teleports to in personalized page rank
+    
+    */
+    Page(String),
+    Pages(Vec<String>),
+
+    // every page listed under a category in the topics file
+    Category(String),
+}
 
+pub fn personalized_page_rank(matrix: &SparseMarkovTransitionMatrix, config: &PageRankConfig, target: &PersonalizationTarget, topics: &Topics) -> Option<PageRankResult> {
+    /*
+    
+        Calculate personalized page rank for a page, a set of pages, or a category. Returns None if none of the target's pages are in the graph.
+    
+    */
+    let seed_urls: Vec<String> = match target {
+        PersonalizationTarget::Page(url) => vec![url.clone()],
+        PersonalizationTarget::Pages(urls) => urls.clone(),
+        PersonalizationTarget::Category(category) => topics.categories.get(category)?.clone(),
+    };
 
-struct URLPayload {
-    page_rank: f32,
+    let teleport = teleport_vector(matrix, &seed_urls)?;
+    Some(personalized_power_iteration(matrix, config, &teleport))
 }
-struct URLLookupHashMap {
+
+#[derive(Deserialize)]
+pub struct Topics {
     /*
     
-        This will be for getting information about a URL.
+        The pages that make up each topic (or category). This is loaded from a json file that maps each topic name to a list of page urls:
 
-        Ideally, this can store the page rank for the entire index, and then be stored to a binary file. The binary file can then be opened, and used whenever the page rank of a url needs to be determined. 
+            { "Biology": ["https://wikipedia.org/wiki/Cell_(biology)", ...], ... }
+    
+    */
+    #[serde(flatten)]
+    pub categories: BTreeMap<String, Vec<String>>,
+}
+
+impl Topics {
+    pub fn load_json(topics_path: &str) -> Topics {
+        let json = fs::read_to_string(topics_path).expect("Unable to read topics file");
+        serde_json::from_str(&json).expect("Unable to parse topics file")
+    }
+}
+
+#[derive(Decode, Encode)]
+pub struct TopicSensitivePageRank {
+    /*
+    
+        Topic-sensitive page rank (Haveliwala, 2002). One personalized page rank vector is calculated ahead of time for each topic, with the random surfer teleporting to that topic's pages. At search time, the vectors are blended together using how relevant each topic is to the query, which gives pages that are authoritative for the query's topics a higher score than hub pages that are linked to from everywhere.
     
     */
 
-    hashmap: HashMap<String, WebPagePayload>,
+    pub topics: Vec<String>,
+    pub urls: Vec<String>,
+
+    // scores[t][i] is the page rank of urls[i] for topics[t]
+    pub scores: Vec<Vec<f32>>,
 }
 
+impl TopicSensitivePageRank {
+    pub fn compute(matrix: &SparseMarkovTransitionMatrix, config: &PageRankConfig, topics: &Topics) -> TopicSensitivePageRank {
+        let mut topic_names: Vec<String> = Vec::new();
+        let mut scores: Vec<Vec<f32>> = Vec::new();
+
+        for (topic, seed_urls) in topics.categories.iter() {
+            match teleport_vector(matrix, seed_urls) {
+                Some(teleport) => {
+                    let result = personalized_power_iteration(matrix, config, &teleport);
+                    println!("topic {}: {} iterations, converged: {}", topic, result.iterations, result.converged);
+
+                    topic_names.push(topic.clone());
+                    scores.push(result.scores.iter().map(|score| *score as f32).collect());
+                }
+                None => println!("topic {} has none of its pages in the graph, skipping it", topic),
+            }
+        }
+
+        TopicSensitivePageRank {
+            topics: topic_names,
+            urls: matrix.urls.clone(),
+            scores,
+        }
+    }
+
+    pub fn blend(&self, topic_weights: &HashMap<String, f64>) -> HashMap<String, f64> {
+        /*
+        
+            Combine the topic vectors, weighting each one by how relevant its topic is to the query. The weights are normalized, so they don't need to sum to 1.
+        
+        */
+        let total_weight: f64 = self.topics.iter().filter_map(|topic| topic_weights.get(topic)).sum();
+        let mut blended: Vec<f64> = vec![0.0; self.urls.len()];
+        if total_weight > 0.0 {
+            for (topic, topic_scores) in self.topics.iter().zip(self.scores.iter()) {
+                let weight = topic_weights.get(topic).copied().unwrap_or(0.0) / total_weight;
+                if weight == 0.0 {
+                    continue;
+                }
+                for (blended_score, score) in blended.iter_mut().zip(topic_scores.iter()) {
+                    *blended_score += weight * *score as f64;
+                }
+            }
+        }
+
+        self.urls.iter().cloned().zip(blended).collect()
+    }
+
+    pub fn bincode_save(&self, topic_rank_path: &str) {
+        let bincode_config = config::standard();
+
+        let encoded : Vec<u8> = bincode::encode_to_vec(self, bincode_config).unwrap();
+        write_atomic(topic_rank_path, &encoded);
+
+        println!("Topic-sensitive page rank written to disk.")
+    }
+
+    pub fn bincode_load(topic_rank_path: &str) -> TopicSensitivePageRank {
+        let bincode_config = config::standard();
+
+        let binary = fs::read(topic_rank_path).expect("Unable to read topic-sensitive page rank from disk");
+        let (topic_rank, _) : (TopicSensitivePageRank, usize) = bincode::decode_from_slice(&binary[..], bincode_config).expect("Unable to decode topic-sensitive page rank");
+
+        topic_rank
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exact_config() -> PageRankConfig {
+        PageRankConfig { tolerance: 1e-10, max_iterations: 1000, ..Default::default() }
+    }
+
+    #[test]
+    fn page_rank_of_small_cycle() {
+        // A -> B, A -> C, B -> C, C -> A with a damping factor of 0.85
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("A", &["B".to_string(), "C".to_string()]);
+        graph.insert_page("B", &["C".to_string()]);
+        graph.insert_page("C", &["A".to_string()]);
+        let (scores, result) = page_rank(&graph, &exact_config());
+        assert!(result.converged);
+        assert!((scores["A"] - 0.38779).abs() < 1e-4);
+        assert!((scores["B"] - 0.21481).abs() < 1e-4);
+        assert!((scores["C"] - 0.39740).abs() < 1e-4);
+    }
+
+    #[test]
+    fn page_rank_with_dangling_page() {
+        // A -> B -> C, where C is a dangling node
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("A", &["B".to_string()]);
+        graph.insert_page("B", &["C".to_string()]);
+        let (scores, result) = page_rank(&graph, &exact_config());
+        assert!(result.converged);
+        assert!((scores["A"] - 0.18442).abs() < 1e-4);
+        assert!((scores["B"] - 0.34117).abs() < 1e-4);
+        assert!((scores["C"] - 0.47441).abs() < 1e-4);
+        assert!((scores.values().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn personalized_page_rank_follows_the_teleport_vector() {
+        // 0 -> 1 -> 2, where 2 is a dangling node, and 3 has no links at all. The surfer teleports to 0 or 1, and never to 2 or 3.
+        let matrix = SparseMarkovTransitionMatrix::from_edges(4, &[(0, 1), (1, 2)]);
+        let teleport = vec![0.8, 0.2, 0.0, 0.0];
+        let result = personalized_power_iteration(&matrix, &exact_config(), &teleport);
+        assert!(result.converged);
+
+        // With s = d * x[2] + (1 - d), the probability of teleporting: x[0] = 0.8s, x[1] = d * x[0] + 0.2s, x[2] = d * x[1], which sum to 1 when s = 0.15 / 0.3642
+        assert!((result.scores[0] - 0.32949).abs() < 1e-4);
+        assert!((result.scores[1] - 0.36244).abs() < 1e-4);
+        assert!((result.scores[2] - 0.30807).abs() < 1e-4);
+
+        // the probability at the dangling nodes goes back through the teleport vector, not to every page, so a page that nothing links to and that isn't teleported to gets nothing
+        assert_eq!(result.scores[3], 0.0);
+        assert!((result.scores.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn topic_blend_normalizes_the_weights() {
+        let topic_rank = TopicSensitivePageRank {
+            topics: vec!["art".to_string(), "science".to_string()],
+            urls: vec!["a".to_string(), "b".to_string()],
+            scores: vec![vec![0.75, 0.25], vec![0.25, 0.75]],
+        };
+
+        let weights: HashMap<String, f64> = [("art".to_string(), 3.0), ("science".to_string(), 1.0), ("unknown".to_string(), 10.0)].into_iter().collect();
+        let blended = topic_rank.blend(&weights);
+        assert!((blended["a"] - 0.625).abs() < 1e-9);
+        assert!((blended["b"] - 0.375).abs() < 1e-9);
+
+        let no_known_topics: HashMap<String, f64> = [("unknown".to_string(), 1.0)].into_iter().collect();
+        assert!(topic_rank.blend(&no_known_topics).values().all(|score| *score == 0.0));
+    }
+
+    #[test]
+    fn synthetic_graph_is_reproducible() {
+        let edges = synthetic_power_law_edges(1000, 8, 7);
+        assert_eq!(edges, synthetic_power_law_edges(1000, 8, 7));
+        assert!(edges.iter().all(|&(source, target)| source != target && (target as usize) < 1000));
+    }
+
+    #[test]
+    fn every_method_matches_single_threaded_jacobi() {
+        let matrix = SparseMarkovTransitionMatrix::from_edges(5000, &synthetic_power_law_edges(5000, 8, 11));
+        let jacobi = power_iteration(&matrix, &PageRankConfig { threads: 1, ..exact_config() });
+        assert!(jacobi.converged);
+
+        let l1_distance = |result: &PageRankResult| jacobi.scores.iter().zip(result.scores.iter()).map(|(a, b)| (a - b).abs()).sum::<f64>();
+
+        let threaded = power_iteration(&matrix, &PageRankConfig { threads: 4, ..exact_config() });
+        assert!(threaded.converged);
+        assert!(l1_distance(&threaded) < 1e-9);
+
+        let gauss_seidel = power_iteration(&matrix, &PageRankConfig { method: PageRankMethod::GaussSeidel, threads: 1, ..exact_config() });
+        assert!(gauss_seidel.converged);
+        assert!(l1_distance(&gauss_seidel) < 1e-8);
+
+        // adaptive page rank stops updating pages early, so it is only close
+        for method in [PageRankMethod::Jacobi, PageRankMethod::GaussSeidel] {
+            let adaptive = power_iteration(&matrix, &PageRankConfig { method, threads: 4, adaptive_threshold: Some(1e-6), ..exact_config() });
+            assert!(l1_distance(&adaptive) < 1e-4);
+        }
+    }
+}
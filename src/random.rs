@@ -0,0 +1,38 @@
+/*
+
+    Random Numbers:
+    A small seeded xorshift64* generator, used wherever the search engine needs random numbers that come out the same on every run: the synthetic benchmark graphs, the HNSW levels, the k-means seeding, and the random vectors in the tests.
+
+*/
+
+pub struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    pub fn new(seed: u64) -> XorShift64 {
+        // xorshift gets stuck at 0, so a seed of 0 is treated as 1
+        XorShift64 { state: seed.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    pub fn next_unit_f64(&mut self) -> f64 {
+        // uniform in (0, 1], so that its logarithm is always finite
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn next_signed_f32(&mut self) -> f32 {
+        // uniform in [-1, 1)
+        (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+
+    pub fn vector(&mut self, dimension: usize) -> Vec<f32> {
+        (0..dimension).map(|_| self.next_signed_f32()).collect()
+    }
+}
@@ -0,0 +1,49 @@
+/*
+
+    Helpers shared by the tests of the crawler (main.rs), the embedding server (bin/embedding_server.rs) and the library (lib.rs). Each crate includes this file as its own test_support module.
+
+*/
+
+use std::env;
+use std::fs;
+use std::process;
+
+pub struct TempPath {
+    /*
+
+        A path in the temporary directory that is unique to the test that made it. Every file whose name starts with it (like an index and its log) is removed when it is dropped, even if the test fails.
+
+    */
+    path: String,
+}
+
+impl TempPath {
+    pub fn new(name: &str) -> TempPath {
+        let path = env::temp_dir().join(format!("balene_test_{}_{}", process::id(), name)).to_string_lossy().into_owned();
+        let temp_path = TempPath { path };
+        temp_path.remove_files();
+        temp_path
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn remove_files(&self) {
+        let path = std::path::Path::new(&self.path);
+        let prefix = path.file_name().unwrap().to_string_lossy().into_owned();
+        if let Ok(entries) = fs::read_dir(path.parent().unwrap()) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove_files();
+    }
+}
@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::compact_graph::CompactGraph;
+use crate::page_rank::{self, PageRankConfig, PageRankResult, SparseMarkovTransitionMatrix, SparseTransitionMatrixRow, TransitionMatrixEdge};
+
+/*
+
+    Trust Rank:
+    Link farms can give a page a high page rank, by making lots of pages that all link to it. Trust rank (Gyongyi, Garcia-Molina and Pedersen, 2004) works around this by starting from a small set of pages that a person has checked are good, and spreading trust outwards along their links. Good pages rarely link to spam, so spam pages end up with very little trust, no matter how many links they have.
+
+    This is personalized page rank, with the random surfer teleporting to the trusted seed pages.
+
+    Anti-trust rank does the opposite, starting from pages that are known to be spam and following links backwards, because a page that links to spam is probably spam too.
+
+    The seeds are listed in a json file:
+
+        { "trusted": ["https://wikipedia.org/wiki/Main_Page", ...], "spam": [...] }
+
+*/
+
+// the hand picked trusted and spam pages
+pub const TRUST_SEEDS_PATH: &str = "crawl_history/trust_seeds.json";
+
+// place where the spam suspect report is saved
+pub const SPAM_REPORT_PATH: &str = "crawl_history/spam_suspects.json";
+
+// Only pages with one of the highest page ranks are considered for the spam suspect report, since spam with a low page rank isn't a problem for the ranking
+const SUSPECT_CANDIDATE_COUNT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct TrustSeeds {
+    #[serde(default)]
+    pub trusted: Vec<String>,
+
+    #[serde(default)]
+    pub spam: Vec<String>,
+}
+
+impl TrustSeeds {
+    pub fn load_json(seeds_path: &str) -> TrustSeeds {
+        let json = fs::read_to_string(seeds_path).expect("Unable to read trust seeds file");
+        serde_json::from_str(&json).expect("Unable to parse trust seeds file")
+    }
+}
+
+pub struct TrustScores {
+    // The trust rank and anti-trust rank of each page, in the same order as the graph's ids. Each of these sums to 1. The anti-trust rank is all zeros if there were no spam seeds.
+    pub trust: Vec<f64>,
+    pub distrust: Vec<f64>,
+}
+
+impl TrustScores {
+    pub fn compute(graph: &CompactGraph, config: &PageRankConfig, seeds: &TrustSeeds) -> Option<TrustScores> {
+        /*
+
+            Calculate trust rank, and anti-trust rank if there are any spam seeds. Returns None if none of the trusted pages are in the graph.
+
+        */
+        let trust = trust_rank(graph, config, &seeds.trusted)?;
+        println!("trust rank: {} iterations, converged: {}", trust.iterations, trust.converged);
+
+        let distrust = match anti_trust_rank(graph, config, &seeds.spam) {
+            Some(distrust) => {
+                println!("anti-trust rank: {} iterations, converged: {}", distrust.iterations, distrust.converged);
+                distrust.scores
+            }
+            None => vec![0.0; graph.node_count()],
+        };
+
+        Some(TrustScores {
+            trust: trust.scores,
+            distrust,
+        })
+    }
+
+    pub fn scores_by_url(&self, graph: &CompactGraph) -> (HashMap<String, f64>, HashMap<String, f64>) {
+        /*
+            Returns the trust scores and the distrust scores by url
+        */
+        let mut trust: HashMap<String, f64> = HashMap::with_capacity(graph.node_count());
+        let mut distrust: HashMap<String, f64> = HashMap::with_capacity(graph.node_count());
+        for id in 0..graph.node_count() as u32 {
+            trust.insert(graph.url(id).to_string(), self.trust[id as usize]);
+            distrust.insert(graph.url(id).to_string(), self.distrust[id as usize]);
+        }
+        (trust, distrust)
+    }
+}
+
+pub fn trust_rank(graph: &CompactGraph, config: &PageRankConfig, trusted_urls: &[String]) -> Option<PageRankResult> {
+    /*
+
+        Spread trust from the trusted pages along their outgoing links
+
+    */
+    let matrix = page_rank::construct_markov_transition_matrix_compact(graph);
+    let teleport = page_rank::teleport_vector(&matrix, trusted_urls)?;
+    Some(page_rank::personalized_power_iteration(&matrix, config, &teleport))
+}
+
+pub fn anti_trust_rank(graph: &CompactGraph, config: &PageRankConfig, spam_urls: &[String]) -> Option<PageRankResult> {
+    /*
+
+        Spread distrust from the spam pages backwards along the links that point to them
+
+    */
+    let matrix = construct_reversed_transition_matrix(graph);
+    let teleport = page_rank::teleport_vector(&matrix, spam_urls)?;
+    Some(page_rank::personalized_power_iteration(&matrix, config, &teleport))
+}
+
+fn construct_reversed_transition_matrix(graph: &CompactGraph) -> SparseMarkovTransitionMatrix {
+    /*
+
+        The transition matrix of a random surfer that follows links backwards, picking one of the links into its page at random
+
+    */
+    let node_count = graph.node_count();
+
+    let mut urls: Vec<String> = Vec::with_capacity(node_count);
+    let mut transition_matrix_rows: Vec<SparseTransitionMatrixRow> = Vec::with_capacity(node_count);
+    let mut dangling_nodes: Vec<usize> = Vec::new();
+
+    for id in 0..node_count as u32 {
+        urls.push(graph.url(id).to_string());
+
+        let transition_edges = graph.outgoing(id).map(|target| TransitionMatrixEdge {
+            outgoing_node: target as usize,
+            transition_probability: 1.0 / graph.in_degree(target) as f64,
+        }).collect();
+        transition_matrix_rows.push(SparseTransitionMatrixRow { transition_edges });
+
+        if graph.in_degree(id) == 0 {
+            dangling_nodes.push(id as usize);
+        }
+    }
+
+    SparseMarkovTransitionMatrix {
+        urls,
+        transition_matrix_rows,
+        dangling_nodes,
+    }
+}
+
+#[derive(Serialize)]
+pub struct SpamSuspect {
+    pub url: String,
+    pub page_rank: f64,
+    pub trust: f64,
+    pub distrust: f64,
+
+    // trust / page_rank. Both of these sum to 1, so a page with an ordinary amount of trust for its page rank has about 1, and a page that got its page rank from untrusted pages has close to 0.
+    pub relative_trust: f64,
+}
+
+#[derive(Serialize)]
+pub struct SpamSuspectReport {
+    pub candidate_count: usize,
+    pub suspects: Vec<SpamSuspect>,
+}
+
+impl SpamSuspectReport {
+    pub fn from_scores(graph: &CompactGraph, page_rank: &[f64], trust_scores: &TrustScores, suspect_count: usize) -> SpamSuspectReport {
+        /*
+
+            Find the pages with a high page rank, but a low trust rank. Out of the pages with the highest page rank, the ones with the lowest relative trust are reported, with the highest page rank first when they are tied (e.g. when neither has any trust at all). The distrust of each page is included, so that pages near known spam stand out.
+
+        */
+        let mut candidates: Vec<u32> = (0..graph.node_count() as u32).collect();
+        candidates.sort_by(|a, b| page_rank[*b as usize].total_cmp(&page_rank[*a as usize]).then(a.cmp(b)));
+        candidates.truncate(SUSPECT_CANDIDATE_COUNT);
+        let candidate_count = candidates.len();
+
+        let mut suspects: Vec<SpamSuspect> = candidates.into_iter().map(|id| {
+            let index = id as usize;
+            SpamSuspect {
+                url: graph.url(id).to_string(),
+                page_rank: page_rank[index],
+                trust: trust_scores.trust[index],
+                distrust: trust_scores.distrust[index],
+                relative_trust: if page_rank[index] > 0.0 { trust_scores.trust[index] / page_rank[index] } else { 0.0 },
+            }
+        }).collect();
+        suspects.sort_by(|a, b| a.relative_trust.total_cmp(&b.relative_trust).then(b.page_rank.total_cmp(&a.page_rank)));
+        suspects.truncate(suspect_count);
+
+        SpamSuspectReport {
+            candidate_count,
+            suspects,
+        }
+    }
+
+    pub fn print_report(&self) {
+        println!("Spam suspects (out of the {} pages with the highest page rank):", self.candidate_count);
+        println!("\trelative trust  page rank  distrust  url");
+        for suspect in self.suspects.iter() {
+            println!("\t{:.6}  {:.6}  {:.6}  {}", suspect.relative_trust, suspect.page_rank, suspect.distrust, suspect.url);
+        }
+    }
+
+    pub fn save_json(&self, report_path: &str) {
+        let json = serde_json::to_string_pretty(self).expect("Unable to serialize spam suspect report");
+        fs::write(report_path, json).expect("Unable to write spam suspect report to disk");
+
+        println!("Spam suspect report written to disk.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web_graph::WebPageGraph;
+
+    fn exact_config() -> PageRankConfig {
+        PageRankConfig { tolerance: 1e-12, max_iterations: 1000, ..Default::default() }
+    }
+
+    fn compact_graph(pages: &[(&str, &[&str])]) -> CompactGraph {
+        let mut graph = WebPageGraph::new();
+        for (url, links) in pages.iter() {
+            graph.insert_page(url, &links.iter().map(|link| link.to_string()).collect::<Vec<String>>());
+        }
+        CompactGraph::from_web_graph(&graph)
+    }
+
+    #[test]
+    fn trust_decays_along_a_chain_from_the_seed() {
+        // seed -> one -> two -> three, and two farm pages that link to spam, which nothing trusted links to
+        let graph = compact_graph(&[("seed", &["one"]), ("one", &["two"]), ("two", &["three"]), ("farm_1", &["spam"]), ("farm_2", &["spam"])]);
+        let config = exact_config();
+        let result = trust_rank(&graph, &config, &["seed".to_string()]).unwrap();
+        assert!(result.converged);
+        let trust = |url: &str| result.scores[graph.id(url).unwrap() as usize];
+
+        // every page in the chain has one link in, so each one keeps the damping factor of the trust of the page before it
+        assert!(trust("seed") > 0.0);
+        for (from, to) in [("seed", "one"), ("one", "two"), ("two", "three")] {
+            assert!((trust(to) - config.damping_factor * trust(from)).abs() < 1e-9);
+        }
+
+        // the spam page has as many links as the pages in the chain, but none of them are trusted
+        assert_eq!(trust("spam"), 0.0);
+        assert_eq!(trust("farm_1"), 0.0);
+        assert!(trust_rank(&graph, &config, &["not_in_the_graph".to_string()]).is_none());
+    }
+
+    #[test]
+    fn distrust_flows_backwards_from_the_spam_seed() {
+        // first -> second -> spam, third -> spam, and spam -> linked
+        let graph = compact_graph(&[("first", &["second"]), ("second", &["spam"]), ("third", &["spam"]), ("spam", &["linked"])]);
+        let config = exact_config();
+        let seeds = TrustSeeds { trusted: vec!["first".to_string()], spam: vec!["spam".to_string()] };
+        let scores = TrustScores::compute(&graph, &config, &seeds).unwrap();
+        let distrust = |url: &str| scores.distrust[graph.id(url).unwrap() as usize];
+
+        // the two pages linking to spam share its distrust, and first gets its distrust through second
+        assert!(distrust("spam") > 0.0);
+        assert!((distrust("second") - config.damping_factor / 2.0 * distrust("spam")).abs() < 1e-9);
+        assert!((distrust("third") - distrust("second")).abs() < 1e-9);
+        assert!((distrust("first") - config.damping_factor * distrust("second")).abs() < 1e-9);
+
+        // spam linking to a page doesn't make that page spam
+        assert_eq!(distrust("linked"), 0.0);
+        assert!((scores.distrust.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+}
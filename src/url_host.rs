@@ -0,0 +1,48 @@
+/*
+
+    The host of a url, the way the rest of the search engine groups pages by host: host rank, the host in a vector payload, and the host filter of the embedding server all use these, so that www.example.com and example.com are always the same host.
+
+*/
+
+pub fn host_of(url: &str) -> Option<String> {
+    /*
+
+        The host of a url, in lower case, without the port or a leading "www.", e.g. https://www.Example.com:8080/a/b -> example.com
+
+    */
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority.rsplit('@').next()?;
+    let host = match authority.rfind(':') {
+        // ipv6 addresses contain colons too, but those are inside of brackets
+        Some(colon) if !authority[colon..].contains(']') => &authority[..colon],
+        _ => authority,
+    };
+
+    let host = normalize_host(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some(host)
+}
+
+pub fn normalize_host(host: &str) -> String {
+    // a host as it is written by hand, e.g. in a search filter: lower case, without a leading "www."
+    let host = host.trim().to_lowercase();
+    host.strip_prefix("www.").map(|host| host.to_string()).unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_are_normalized() {
+        assert_eq!(host_of("https://www.Example.com:8080/a/b").as_deref(), Some("example.com"));
+        assert_eq!(host_of("http://user@en.wikipedia.org?q=1").as_deref(), Some("en.wikipedia.org"));
+        assert_eq!(host_of("http://[::1]:8000/").as_deref(), Some("[::1]"));
+        assert_eq!(host_of("https:///path"), None);
+        assert_eq!(host_of("not a url"), None);
+        assert_eq!(normalize_host(" WWW.Example.com"), "example.com");
+    }
+}
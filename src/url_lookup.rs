@@ -0,0 +1,374 @@
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+
+use crate::atomic_file::write_atomic;
+use crate::compact_graph::CompactGraph;
+use crate::crawl::FetchRecord;
+use crate::host_rank::HostRank;
+
+/*
+
+    URL Lookup:
+    Stores the signals that are known about each url (page rank, hub and authority scores, the host rank of its host, trust and distrust, how many links go in and out of it, and when and how deep it was crawled), so that search ranking can look them up.
+
+    URLLookupHashMap is used to put the signals together, and is then saved to a binary file. URLLookupTable memory maps that file, so that it can be opened right away no matter how large the index is. The file contains fixed size records, sorted by url, followed by the bytes of every url. All of the numbers are little endian.
+
+        header:   magic (8 bytes), version: u32, record_size: u32, record_count: u64, url_bytes_start: u64
+        records:  record_count x record_size bytes
+        urls:     the utf-8 bytes of every url
+
+        record:   url_offset: u64, url_len: u32, page_rank: f32, in_degree: u32, out_degree: u32, crawl_depth: u32, (unused): u32, last_fetched: u64,
+                  hub: f32, authority: f32, host_rank: f32, trust: f32, distrust: f32
+
+    The table is rebuilt from the crawl by the rank command, so a file with a different version or record size is rejected rather than converted; running rank again replaces it. It is written to a temporary file and renamed into place, so a crash while saving leaves the previous table intact.
+
+*/
+
+// place where the url lookup table is stored
+pub const URL_LOOKUP_PATH: &str = "crawl_history/url_lookup_1.bin";
+
+const MAGIC: &[u8; 8] = b"BALURLLK";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 32;
+const RECORD_SIZE: usize = 60;
+
+// stored in place of a field that has no value
+const NONE_U32: u32 = u32::MAX;
+const NONE_U64: u64 = u64::MAX;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct URLPayload {
+    pub page_rank: f32,
+    pub in_degree: u32,
+    pub out_degree: u32,
+
+    // the number of links between the start page and this page. None if the page was never crawled.
+    pub crawl_depth: Option<u32>,
+
+    // the time that the page was last fetched, in seconds since the unix epoch. None if the page was never crawled.
+    pub last_fetched: Option<u64>,
+
+    // HITS scores over the whole graph
+    pub hub: f32,
+    pub authority: f32,
+
+    // the host rank of the host that the url is on
+    pub host_rank: f32,
+
+    // trust rank and anti-trust rank
+    pub trust: f32,
+    pub distrust: f32,
+}
+
+pub struct URLLookupHashMap {
+    /*
+
+        This will be for getting information about a URL.
+
+        Ideally, this can store the page rank for the entire index, and then be stored to a binary file. The binary file can then be opened, and used whenever the page rank of a url needs to be determined.
+
+    */
+
+    pub hashmap: HashMap<String, URLPayload>,
+}
+
+impl URLLookupHashMap {
+    pub fn new() -> URLLookupHashMap {
+        URLLookupHashMap {
+            hashmap: HashMap::new()
+        }
+    }
+
+    pub fn from_crawl(graph: &CompactGraph, page_rank: &HashMap<String, f64>, fetch_records: &HashMap<String, FetchRecord>) -> URLLookupHashMap {
+        /*
+
+            Put together the signals for every url in a crawled graph
+
+        */
+        let mut lookup = URLLookupHashMap::new();
+
+        for id in 0..graph.node_count() as u32 {
+            let url = graph.url(id);
+            let fetch_record = fetch_records.get(url);
+
+            lookup.hashmap.insert(url.to_string(), URLPayload {
+                page_rank: page_rank.get(url).copied().unwrap_or(0.0) as f32,
+                in_degree: graph.in_degree(id) as u32,
+                out_degree: graph.out_degree(id) as u32,
+                crawl_depth: fetch_record.map(|record| record.crawl_depth),
+                last_fetched: fetch_record.map(|record| record.fetched_at),
+                hub: 0.0,
+                authority: 0.0,
+                host_rank: 0.0,
+                trust: 0.0,
+                distrust: 0.0,
+            });
+        }
+
+        lookup
+    }
+
+    pub fn add_hits_scores(&mut self, hub: &HashMap<String, f64>, authority: &HashMap<String, f64>) {
+        for (url, payload) in self.hashmap.iter_mut() {
+            payload.hub = hub.get(url).copied().unwrap_or(0.0) as f32;
+            payload.authority = authority.get(url).copied().unwrap_or(0.0) as f32;
+        }
+    }
+
+    pub fn add_host_rank(&mut self, host_rank: &HostRank) {
+        for (url, payload) in self.hashmap.iter_mut() {
+            payload.host_rank = host_rank.url_score(url);
+        }
+    }
+
+    pub fn add_trust_scores(&mut self, trust: &HashMap<String, f64>, distrust: &HashMap<String, f64>) {
+        for (url, payload) in self.hashmap.iter_mut() {
+            payload.trust = trust.get(url).copied().unwrap_or(0.0) as f32;
+            payload.distrust = distrust.get(url).copied().unwrap_or(0.0) as f32;
+        }
+    }
+
+    pub fn save(&self, lookup_path: &str) {
+        /*
+
+            Write the signals to disk in the format read by URLLookupTable
+
+        */
+        let mut urls: Vec<&String> = self.hashmap.keys().collect();
+        urls.sort();
+
+        let url_bytes_start = HEADER_LEN + RECORD_SIZE * urls.len();
+        let url_bytes_len: usize = urls.iter().map(|url| url.len()).sum();
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(url_bytes_start + url_bytes_len);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+        bytes.extend_from_slice(&(urls.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(url_bytes_start as u64).to_le_bytes());
+
+        let mut url_offset: u64 = 0;
+        for url in urls.iter() {
+            let payload = &self.hashmap[*url];
+            bytes.extend_from_slice(&url_offset.to_le_bytes());
+            bytes.extend_from_slice(&(url.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&payload.page_rank.to_le_bytes());
+            bytes.extend_from_slice(&payload.in_degree.to_le_bytes());
+            bytes.extend_from_slice(&payload.out_degree.to_le_bytes());
+            bytes.extend_from_slice(&payload.crawl_depth.unwrap_or(NONE_U32).to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&payload.last_fetched.unwrap_or(NONE_U64).to_le_bytes());
+            bytes.extend_from_slice(&payload.hub.to_le_bytes());
+            bytes.extend_from_slice(&payload.authority.to_le_bytes());
+            bytes.extend_from_slice(&payload.host_rank.to_le_bytes());
+            bytes.extend_from_slice(&payload.trust.to_le_bytes());
+            bytes.extend_from_slice(&payload.distrust.to_le_bytes());
+            url_offset += url.len() as u64;
+        }
+        for url in urls.iter() {
+            bytes.extend_from_slice(url.as_bytes());
+        }
+
+        write_atomic(lookup_path, &bytes);
+
+        println!("URL lookup table written to disk.")
+    }
+}
+
+pub struct URLLookupTable {
+    mmap: Mmap,
+    record_count: usize,
+    url_bytes_start: usize,
+}
+
+impl URLLookupTable {
+    pub fn load_mmap(lookup_path: &str) -> URLLookupTable {
+        /*
+
+            Memory map a saved url lookup table
+
+        */
+        let file = File::open(lookup_path).expect("Unable to open url lookup table");
+
+        // The file must not be modified while it is mapped
+        let mmap = unsafe { Mmap::map(&file) }.expect("Unable to memory map url lookup table");
+
+        assert!(mmap.len() >= HEADER_LEN, "url lookup table is too short to contain a header");
+        assert_eq!(&mmap[0..8], MAGIC, "not a url lookup table");
+        let version = read_u32(&mmap, 8);
+        assert_eq!(version, VERSION, "unsupported url lookup table version {}, run rank again to rebuild it", version);
+        assert_eq!(read_u32(&mmap, 12) as usize, RECORD_SIZE, "url lookup table has the wrong record size");
+
+        let record_count = read_u64(&mmap, 16) as usize;
+        let url_bytes_start = read_u64(&mmap, 24) as usize;
+        assert!(url_bytes_start == HEADER_LEN + RECORD_SIZE * record_count && url_bytes_start <= mmap.len(), "url lookup table is truncated");
+
+        URLLookupTable {
+            mmap,
+            record_count,
+            url_bytes_start,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.record_count
+    }
+
+    pub fn get(&self, url: &str) -> Option<URLPayload> {
+        /*
+
+            Look up the signals for a single url
+
+        */
+        self.find(url, 0).ok().map(|index| self.payload(index))
+    }
+
+    pub fn get_batch(&self, urls: &[&str]) -> Vec<Option<URLPayload>> {
+        /*
+
+            Look up the signals for many urls at once. The urls are looked up in sorted order, so each search only has to look through the records after the previous one.
+
+        */
+        let mut order: Vec<usize> = (0..urls.len()).collect();
+        order.sort_by(|a, b| urls[*a].cmp(urls[*b]));
+
+        let mut payloads: Vec<Option<URLPayload>> = vec![None; urls.len()];
+        let mut low = 0;
+        for index in order {
+            match self.find(urls[index], low) {
+                Ok(found) => {
+                    payloads[index] = Some(self.payload(found));
+                    low = found;
+                }
+                Err(insert_at) => low = insert_at,
+            }
+        }
+        payloads
+    }
+
+    pub fn page_rank(&self, url: &str) -> f32 {
+        // The page rank of a url, for use as a ranking signal. Urls that aren't in the table have none.
+        self.get(url).map(|payload| payload.page_rank).unwrap_or(0.0)
+    }
+
+    fn find(&self, url: &str, low: usize) -> Result<usize, usize> {
+        // binary search over the records from low onwards
+        let mut low = low;
+        let mut high = self.record_count;
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.url(middle).cmp(url) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Ok(middle),
+            }
+        }
+        Err(low)
+    }
+
+    fn url(&self, index: usize) -> &str {
+        let start = self.url_bytes_start + self.field_u64(index, 0) as usize;
+        let end = start + self.field_u32(index, 8) as usize;
+        std::str::from_utf8(&self.mmap[start..end]).expect("url lookup table contains a url that is not valid utf-8")
+    }
+
+    fn payload(&self, index: usize) -> URLPayload {
+        URLPayload {
+            page_rank: f32::from_bits(self.field_u32(index, 12)),
+            in_degree: self.field_u32(index, 16),
+            out_degree: self.field_u32(index, 20),
+            crawl_depth: Some(self.field_u32(index, 24)).filter(|depth| *depth != NONE_U32),
+            last_fetched: Some(self.field_u64(index, 32)).filter(|time| *time != NONE_U64),
+            hub: f32::from_bits(self.field_u32(index, 40)),
+            authority: f32::from_bits(self.field_u32(index, 44)),
+            host_rank: f32::from_bits(self.field_u32(index, 48)),
+            trust: f32::from_bits(self.field_u32(index, 52)),
+            distrust: f32::from_bits(self.field_u32(index, 56)),
+        }
+    }
+
+    fn field_u32(&self, index: usize, field_offset: usize) -> u32 {
+        read_u32(&self.mmap, HEADER_LEN + RECORD_SIZE * index + field_offset)
+    }
+
+    fn field_u64(&self, index: usize, field_offset: usize) -> u64 {
+        read_u64(&self.mmap, HEADER_LEN + RECORD_SIZE * index + field_offset)
+    }
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(bytes[position..position + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempPath;
+
+    fn payload(page_rank: f32, crawl_depth: Option<u32>) -> URLPayload {
+        URLPayload {
+            page_rank,
+            in_degree: 3,
+            out_degree: 7,
+            crawl_depth,
+            last_fetched: crawl_depth.map(|depth| 1_700_000_000 + depth as u64),
+            hub: 0.5,
+            authority: 0.25,
+            host_rank: 0.125,
+            trust: 0.0625,
+            distrust: 0.03125,
+        }
+    }
+
+    fn saved_table(temp_path: &TempPath) -> URLLookupHashMap {
+        let mut lookup = URLLookupHashMap::new();
+        lookup.hashmap.insert("https://b.org/".to_string(), payload(0.2, Some(1)));
+        lookup.hashmap.insert("https://a.org/page".to_string(), payload(0.3, Some(0)));
+        lookup.hashmap.insert("https://c.org/ünïcode".to_string(), payload(0.1, None));
+        lookup.save(temp_path.path());
+        lookup
+    }
+
+    #[test]
+    fn memory_mapped_table_matches_saved_signals() {
+        let temp_path = TempPath::new("url_lookup_round_trip");
+        let lookup = saved_table(&temp_path);
+
+        let table = URLLookupTable::load_mmap(temp_path.path());
+        assert_eq!(table.len(), 3);
+        for (url, payload) in lookup.hashmap.iter() {
+            assert_eq!(table.get(url), Some(*payload));
+        }
+        assert_eq!(table.get("https://a.org/"), None);
+        assert_eq!(table.page_rank("https://b.org/"), 0.2);
+        assert_eq!(table.page_rank("https://missing.org/"), 0.0);
+    }
+
+    #[test]
+    fn batch_lookup_keeps_the_order_of_the_urls() {
+        let temp_path = TempPath::new("url_lookup_batch");
+        let lookup = saved_table(&temp_path);
+        let table = URLLookupTable::load_mmap(temp_path.path());
+
+        let urls = ["https://c.org/ünïcode", "https://zzz.org/", "https://a.org/page", "https://0.org/", "https://b.org/"];
+        let expected: Vec<Option<URLPayload>> = urls.iter().map(|url| lookup.hashmap.get(*url).copied()).collect();
+        assert_eq!(table.get_batch(&urls), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported url lookup table version")]
+    fn rejects_other_versions() {
+        let temp_path = TempPath::new("url_lookup_version");
+        saved_table(&temp_path);
+
+        let mut bytes = std::fs::read(temp_path.path()).unwrap();
+        bytes[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        std::fs::write(temp_path.path(), bytes).unwrap();
+        URLLookupTable::load_mmap(temp_path.path());
+    }
+}
@@ -1,45 +1,154 @@
+use bincode::{Decode, Encode};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 
+pub mod distance;
+pub mod filter;
+pub mod hnsw;
+pub mod ivf_pq;
+pub mod point_ids;
+pub mod quantization;
+pub mod storage;
+pub mod wal;
 
-struct QueryVector {
+pub use filter::{FilterValue, PayloadField, PayloadFilter};
+pub use point_ids::{url_point_id, PointId};
+use filter::matches_filter;
+use point_ids::PointSlots;
+use quantization::{QuantizationConfig, QuantizedVectors};
+use storage::{PayloadStore, VectorBlock};
+
+// places where the embeddings of the pages and images that the crawler finds are stored. The crawler writes both when it embeds them itself (see crawl/embed.rs), and otherwise the embedding server writes the images (see bin/embedding_server.rs).
+pub const PAGE_VECTOR_INDEX_PATH: &str = "crawl_history/page_vector_index_1.bin";
+pub const IMAGE_VECTOR_INDEX_PATH: &str = "crawl_history/image_vector_index_1.bin";
+
+// how many points are scored at a time with the batched distance kernels
+const SEARCH_BLOCK_SIZE: usize = 1024;
+
+pub struct QueryVector {
     /*
-    
-        A QueryVector contains the vector that will be used to search the index. 
-    
+
+        A QueryVector contains the vector that will be used to search the index.
+
     */
-    
-    vec: [f32],
+
+    pub vec: Vec<f32>,
 }
 
-struct PointVector {
+pub struct PointVector {
     /*
-    
+
         This represents a point in the index. It holds a vector payload
 
     */
-    vec: [f32],
-    payload: VectorPayload,
+    pub vec: Vec<f32>,
+    pub payload: VectorPayload,
 }
 
-struct VectorPayload {
+#[derive(Clone, Debug, Default, PartialEq, Decode, Encode)]
+pub struct VectorPayload {
     /*
-    
-        This stores the important information about a point in the data. Since most points will be web pages, this will simply store the web pages. 
-    
+
+        This stores the important information about a point in the data. Since most points will be web pages, this stores the web page and what is known about it, so that searches can be filtered on it (see filter.rs).
+
     */
-    page_url: String
+    pub page_url: String,
+
+    // the image that the point is for, or empty if the point is for the page itself
+    pub image_url: String,
+    pub host: String,
+
+    // language code, like "en"
+    pub language: String,
+
+    // mime type, like "text/html" or "image/jpeg"
+    pub content_type: String,
+    pub page_rank: f32,
+
+    // unix time in seconds of when the page was crawled
+    pub crawl_date: u64,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct VectorSearchResult<'a> {
+    // The id that the point was added with, which stays the same when the point is upserted or the index is compacted
+    pub id: PointId,
+
+    // Indexes that keep their payloads in memory lend them out, and indexes that are memory mapped decode them
+    pub payload: Cow<'a, VectorPayload>,
+
+    // How close the point is to the query, using the client's metric. For dot product and cosine, higher is closer, and for L2 it is the distance, so lower is closer.
+    pub score: f32,
 }
 
-struct VectorSearchResult {
-    payload: &VectorPayload,
-    score: f32, 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DistanceMetric {
+    DotProduct,
+    Cosine,
+
+    // euclidean distance
+    L2,
 }
 
+impl DistanceMetric {
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            DistanceMetric::DotProduct => dot_product(a, b),
+            DistanceMetric::Cosine => cosine_similarity(a, b),
+            DistanceMetric::L2 => l2_distance(a, b),
+        }
+    }
+
+    pub fn higher_is_closer(&self) -> bool {
+        match self {
+            DistanceMetric::DotProduct | DistanceMetric::Cosine => true,
+            DistanceMetric::L2 => false,
+        }
+    }
+
+    fn closeness(&self, score: f32) -> f32 {
+        // turns a score into something where higher is always closer, so every metric can be ranked the same way
+        if self.higher_is_closer() { score } else { -score }
+    }
+}
 
-trait VectorSearchClient {
-    // Perform a vector search
-    fn search(&self, query: QueryVector) -> Vec<VectorSearchResult;
-    // Return the number of points that the search client has
-    fn point_count(&self) -> i32; 
+
+pub trait VectorSearchClient {
+    // Perform a vector search, returning the top_k closest points, closest first
+    fn search(&self, query: &QueryVector, top_k: usize) -> Vec<VectorSearchResult<'_>> {
+        self.search_filtered(query, top_k, None)
+    }
+    // Perform a vector search that only returns points whose payloads match the filter. The filter is checked while searching, so this still returns top_k points if that many match.
+    fn search_filtered(&self, query: &QueryVector, top_k: usize, filter: Option<&PayloadFilter>) -> Vec<VectorSearchResult<'_>>;
+    // Return the number of points that the search client has, not counting deleted ones
+    fn point_count(&self) -> usize;
+
+    // Add a point with the given id, replacing the point that already has that id, if there is one
+    fn upsert(&mut self, id: PointId, point: PointVector);
+    // Remove the point with the given id, returning whether it was in the index. Its space isn't reclaimed until the index is compacted.
+    fn delete(&mut self, id: PointId) -> bool;
+    // Return the number of deleted or replaced points that are still taking up space in the index
+    fn tombstone_count(&self) -> usize;
+    // Reclaim the space taken up by deleted and replaced points
+    fn compact(&mut self);
+
+    fn maybe_compact(&mut self, max_tombstone_fraction: f32) -> bool {
+        /*
+
+            Compact the index if more than max_tombstone_fraction of its slots are tombstones. This is cheap to call after every batch of changes, so that the index compacts itself once enough has been deleted.
+
+        */
+        let tombstones = self.tombstone_count();
+        let slots = tombstones + self.point_count();
+        if tombstones > 0 && tombstones as f32 > max_tombstone_fraction * slots as f32 {
+            self.compact();
+            true
+        } else {
+            false
+        }
+    }
 }
 
 
@@ -52,7 +161,7 @@ trait VectorSearchClient {
     2) perform search querys
     3) upsert vectors
     4) remove points
-    
+
 
 */
 
@@ -62,54 +171,401 @@ trait VectorSearchClient {
 
 
 
-struct simpleSearch {
+pub struct SimpleSearch {
     /*
-    
-        This is a simple vector search client. It will store all of the PointVectors in a single Vec, and then in order to search, it will find the dot product of the QueryVector with each one of the PointVectors, sort the results, and then return the highest of the sorted results. 
-    
+
+        This is a simple vector search client. It will store all of the vectors one after the other in a single block, and then in order to search, it will score the QueryVector against each one of them, keeping the best top_k in a heap, and then return them sorted. This is exact, so it is also what the approximate indexes are checked against.
+
+        The index can be saved to disk, and memory mapped when it is loaded (see storage.rs).
+
+        Upserting a point appends it to the end of the block and deleting one leaves a tombstone in its slot (see point_ids.rs), so that upserting into a memory mapped index only keeps the new points in memory (see storage.rs). Compacting is what moves the points, and it copies a mapped block into memory.
+
+        The index can also be quantized, so that only compact int8 and binary codes are kept in memory, and searches only read the full precision vectors of the few best candidates from the index file (see quantization.rs).
+
     */
-    index: Vec<PointVector>
+    vectors: VectorBlock,
+    payloads: PayloadStore,
+    ids: PointSlots,
+    quantized: Option<QuantizedVectors>,
+    metric: DistanceMetric,
+    dimension: usize,
+}
+
+impl SimpleSearch {
+    pub fn new(dimension: usize, metric: DistanceMetric) -> SimpleSearch {
+        SimpleSearch {
+            vectors: VectorBlock::Owned(Vec::new()),
+            payloads: PayloadStore::Owned(Vec::new()),
+            ids: PointSlots::new(),
+            quantized: None,
+            metric,
+            dimension,
+        }
+    }
+
+    pub fn add_point(&mut self, point: PointVector) -> PointId {
+        // adds the point with a new id, which is returned
+        let id = self.ids.new_id();
+        self.upsert(id, point);
+        id
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    pub fn quantize(&mut self, config: QuantizationConfig, index_path: &str) {
+        /*
+
+            Calibrate and encode the int8 and binary codes of every point, so that searches use them from now on, and then save the index with its codes to index_path and map it again from there, so that the full precision vectors are no longer kept in memory. An index without any points has nothing to calibrate on, so it is left as it is, and isn't saved.
+
+        */
+        let live_slots: Vec<usize> = self.ids.live_slots().collect();
+        if live_slots.is_empty() {
+            return;
+        }
+        self.quantized = Some(QuantizedVectors::build(&self.vectors, &live_slots, self.dimension, config));
+        self.save(index_path);
+        *self = SimpleSearch::load_mmap(index_path);
+    }
+
+    pub fn remove_quantization(&mut self) {
+        // go back to scoring the full precision vectors. The codes are still in the index file until it is saved again.
+        self.quantized = None;
+    }
+
+    pub fn quantized_memory_bytes(&self) -> Option<usize> {
+        // how much memory the quantized codes take up, if the index is quantized. The full precision vectors are read from the index file instead, apart from the ones upserted since it was saved.
+        self.quantized.as_ref().map(|quantized| quantized.memory_bytes())
+    }
+
+    pub fn vector_bytes(&self) -> usize {
+        // how much the full precision vectors take up, in memory or on disk if they are memory mapped
+        4 * self.vectors.len()
+    }
+}
+
+struct HeapEntry {
+    // higher is closer
+    closeness: f32,
+    index: usize,
+}
+
+// The heap entries are ordered so that the farthest point is at the top of the heap, and is the one that gets removed when the heap is full
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.closeness.total_cmp(&self.closeness).then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
 }
 
-fn sortVectorSearchResults(results: &mut Vec<VectorSearchResult>) {
+impl Eq for HeapEntry {}
+
+pub fn select_top_k<I: Iterator<Item = (usize, f32)>>(scores: I, top_k: usize, metric: DistanceMetric) -> Vec<(usize, f32)> {
     /*
-    
-        Given a vec containing references to search results, sort them in descending order
-    
+
+        Given the score of every point, find the top_k closest, closest first. Only top_k points are kept in the heap at a time, so this takes O(n log k) time instead of sorting everything.
+
     */
+    if top_k == 0 {
+        return Vec::new();
+    }
+
+    let mut heap: BinaryHeap<HeapEntry> = BinaryHeap::with_capacity(top_k + 1);
+    for (index, score) in scores {
+        let closeness = metric.closeness(score);
+        if heap.len() < top_k {
+            heap.push(HeapEntry { closeness, index });
+        } else if closeness > heap.peek().unwrap().closeness {
+            heap.pop();
+            heap.push(HeapEntry { closeness, index });
+        }
+    }
+
+    // the heap puts the farthest point first, so sorting it in order puts the closest first
+    heap.into_sorted_vec().into_iter().map(|entry| (entry.index, metric.closeness(entry.closeness))).collect()
+}
+
+impl VectorSearchClient for SimpleSearch {
+    fn search_filtered(&self, query: &QueryVector, top_k: usize, filter: Option<&PayloadFilter>) -> Vec<VectorSearchResult<'_>> {
+        assert_eq!(query.vec.len(), self.dimension, "query has the wrong number of dimensions");
+
+        if let Some(quantized) = &self.quantized {
+            let keep = |slot: usize| !self.ids.is_deleted(slot) && (filter.is_none() || matches_filter(filter, &self.payloads.get(slot)));
+            return quantized.search(&query.vec, top_k, self.metric, &self.vectors, &keep).into_iter().map(|(index, score)| VectorSearchResult {
+                id: self.ids.id(index),
+                payload: self.payloads.get(index),
+                score,
+            }).collect();
+        }
 
-    let len = results.len()
+        // the points are scored a block at a time, so that the scores of every point never have to be in memory at once
+        let [saved, appended] = self.vectors.segments();
+        let block_floats = SEARCH_BLOCK_SIZE * self.dimension.max(1);
+        let saved_blocks = saved.chunks(block_floats).enumerate().map(|(block, block_vectors)| (block * SEARCH_BLOCK_SIZE, block_vectors));
+        let appended_blocks = appended.chunks(block_floats).enumerate().map(|(block, block_vectors)| (saved.len() / self.dimension.max(1) + block * SEARCH_BLOCK_SIZE, block_vectors));
+        let scores = saved_blocks.chain(appended_blocks).flat_map(|(first_slot, block_vectors)| {
+            let mut block_scores: Vec<f32> = vec![0.0; block_vectors.len() / self.dimension.max(1)];
+            match self.metric {
+                DistanceMetric::DotProduct => distance::dot_batch(&query.vec, block_vectors, &mut block_scores),
+                DistanceMetric::Cosine => distance::cosine_batch(&query.vec, block_vectors, &mut block_scores),
+                DistanceMetric::L2 => {
+                    distance::l2_squared_batch(&query.vec, block_vectors, &mut block_scores);
+                    block_scores.iter_mut().for_each(|score| *score = score.sqrt());
+                }
+            }
+            block_scores.into_iter().enumerate().map(move |(index, score)| (first_slot + index, score))
+        });
+        // mapped payloads have to be decoded to be filtered, so they are only looked at when there is a filter
+        let scores = scores.filter(|(index, _)| !self.ids.is_deleted(*index) && (filter.is_none() || matches_filter(filter, &self.payloads.get(*index))));
+
+        select_top_k(scores, top_k, self.metric).into_iter().map(|(index, score)| VectorSearchResult {
+            id: self.ids.id(index),
+            payload: self.payloads.get(index),
+            score,
+        }).collect()
+    }
+    fn point_count(&self) -> usize {
+        self.ids.live_count()
+    }
+    fn upsert(&mut self, id: PointId, point: PointVector) {
+        assert_eq!(point.vec.len(), self.dimension, "point has the wrong number of dimensions");
+        self.ids.push(id);
+        if let Some(quantized) = &mut self.quantized {
+            quantized.push(&point.vec);
+        }
+        self.vectors.push(&point.vec);
+        self.payloads.push(point.payload);
+    }
+    fn delete(&mut self, id: PointId) -> bool {
+        self.ids.delete(id).is_some()
+    }
+    fn tombstone_count(&self) -> usize {
+        self.ids.tombstone_count()
+    }
+    fn compact(&mut self) {
+        if self.ids.tombstone_count() == 0 {
+            return;
+        }
 
-    for i in 0..len {
-        let mut max_idx = i;
-        for j in (i+1)..len {
-            if results[j] > results[max_idx] {
-                max_idx = j; 
+        // the live points are copied down in order, so the block never needs more memory than it already has
+        let dimension = self.dimension;
+        let new_slots = self.ids.compact();
+        let vectors = self.vectors.to_mut();
+        let payloads = self.payloads.to_mut();
+        for (old_slot, new_slot) in new_slots.iter().enumerate() {
+            if let Some(new_slot) = new_slot {
+                if *new_slot != old_slot {
+                    vectors.copy_within(old_slot * dimension..(old_slot + 1) * dimension, new_slot * dimension);
+                    payloads.swap(old_slot, *new_slot);
+                }
             }
         }
-        let tmp = results[max_idx];
-        results[max_idx] = results[i];
-        results[i] = tmp;
+        let live_count = self.ids.live_count();
+        vectors.truncate(live_count * dimension);
+        vectors.shrink_to_fit();
+        payloads.truncate(live_count);
+        payloads.shrink_to_fit();
+        if let Some(quantized) = &mut self.quantized {
+            quantized.compact(&new_slots);
+        }
     }
+}
+
+
+pub fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    distance::dot(a, b)
+}
 
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    distance::cosine(a, b)
+}
 
+pub fn l2_distance(a: &[f32], b: &[f32]) -> f32 {
+    distance::l2_squared(a, b).sqrt()
 }
 
-impl VectorSearchClient for simpleSearch {
-    fn search(&self, query: QueryVector) -> Vec<VectorSearchResult> {
-       
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::XorShift64;
+    use crate::test_support::TempPath;
+    use hnsw::{HnswConfig, HnswIndex};
+    use ivf_pq::{IvfPqConfig, IvfPqIndex};
+
+    // the ids don't start at 0, so that they can't be mixed up with the slots
+    const FIRST_ID: PointId = 1000;
+
+    fn is_deleted(id: PointId) -> bool {
+        id.is_multiple_of(3)
     }
-    fn point_count(&self) -> i32 {
-        return self.index.len();
+
+    fn point_vector(vec: &[f32], id: PointId) -> PointVector {
+        PointVector { vec: vec.to_vec(), payload: VectorPayload { page_url: id.to_string(), ..Default::default() } }
     }
-}
 
+    #[test]
+    fn search_matches_sorting_every_score() {
+        // the brute force search against a naive reference that scores every point and sorts all of them, for every metric
+        let mut random = XorShift64::new(42);
+        let dimension = 16;
+        let points: Vec<Vec<f32>> = (0..500).map(|_| random.vector(dimension)).collect();
+        let queries: Vec<Vec<f32>> = (0..20).map(|_| random.vector(dimension)).collect();
+
+        for metric in [DistanceMetric::DotProduct, DistanceMetric::Cosine, DistanceMetric::L2] {
+            let mut client = SimpleSearch::new(dimension, metric);
+            for (index, point) in points.iter().enumerate() {
+                client.add_point(PointVector { vec: point.clone(), payload: VectorPayload { page_url: index.to_string(), ..Default::default() } });
+            }
+            assert_eq!(client.point_count(), points.len());
+
+            for query in queries.iter() {
+                for top_k in [0, 1, 10, 500, 600] {
+                    let results = client.search(&QueryVector { vec: query.clone() }, top_k);
 
-fn dot_product(a: &[f32], b: &[f32]) -> f32 {
-    assert_eq!(a.len(), b.len());
-    let mut product = 0.0;
-    for i in 0..a.len() {
-        product += a[i] * b[i];
+                    let mut reference: Vec<(usize, f32)> = points.iter().map(|point| metric.score(query, point)).enumerate().collect();
+                    reference.sort_by(|a, b| if metric.higher_is_closer() { b.1.total_cmp(&a.1) } else { a.1.total_cmp(&b.1) }.then(a.0.cmp(&b.0)));
+                    reference.truncate(top_k);
+
+                    assert_eq!(results.len(), reference.len());
+                    for (result, (index, score)) in results.iter().zip(reference.iter()) {
+                        assert_eq!(result.payload.page_url, index.to_string());
+                        assert_eq!(result.score, *score);
+                    }
+                }
+            }
+        }
     }
-    return product;
-}
\ No newline at end of file
+
+    #[test]
+    fn upsert_delete_and_compact_every_index() {
+        // deleted points are never returned, upserted points replace the old ones, and compacting keeps the same points
+        let mut random = XorShift64::new(11);
+        let dimension = 16;
+        let points: Vec<Vec<f32>> = (0..2000).map(|_| random.vector(dimension)).collect();
+        let queries: Vec<Vec<f32>> = (0..20).map(|_| random.vector(dimension)).collect();
+
+        let mut ivf_pq = IvfPqIndex::new(dimension, DistanceMetric::L2, IvfPqConfig { nlist: 16, nprobe: 16, ..Default::default() });
+        ivf_pq.train(&points.iter().map(|point| point_vector(point, 0)).collect::<Vec<PointVector>>());
+        let indexes: Vec<(&str, Box<dyn VectorSearchClient>)> = vec![
+            ("simple", Box::new(SimpleSearch::new(dimension, DistanceMetric::L2))),
+            ("hnsw", Box::new(HnswIndex::new(dimension, DistanceMetric::L2, HnswConfig::default()))),
+            ("ivf-pq", Box::new(ivf_pq)),
+        ];
+
+        let moved_id: PointId = FIRST_ID + 501;
+        let moved_vec: Vec<f32> = (0..dimension).map(|d| if d == 0 { 50.0 } else { 0.0 }).collect();
+
+        for (name, mut index) in indexes.into_iter() {
+            for (offset, point) in points.iter().enumerate() {
+                index.upsert(FIRST_ID + offset as PointId, point_vector(point, FIRST_ID + offset as PointId));
+            }
+            assert_eq!(index.point_count(), points.len());
+
+            let deleted_count = (FIRST_ID..FIRST_ID + points.len() as PointId).filter(|id| is_deleted(*id)).count();
+            for id in FIRST_ID..FIRST_ID + points.len() as PointId {
+                if is_deleted(id) {
+                    assert!(index.delete(id));
+                    assert!(!index.delete(id));
+                }
+            }
+            assert!(!index.delete(FIRST_ID + points.len() as PointId));
+            index.upsert(moved_id, PointVector { vec: moved_vec.clone(), payload: VectorPayload { page_url: "moved".to_string(), ..Default::default() } });
+            assert_eq!(index.point_count(), points.len() - deleted_count);
+            assert_eq!(index.tombstone_count(), deleted_count + 1);
+
+            let check = |index: &dyn VectorSearchClient| {
+                for query in queries.iter() {
+                    let results = index.search(&QueryVector { vec: query.clone() }, 10);
+                    assert_eq!(results.len(), 10);
+                    for result in results.iter() {
+                        assert!(!is_deleted(result.id), "{} returned a deleted point", name);
+                        if result.id != moved_id {
+                            assert_eq!(result.payload.page_url, result.id.to_string());
+                        }
+                    }
+                }
+                let results = index.search(&QueryVector { vec: moved_vec.clone() }, 1);
+                assert_eq!(results[0].id, moved_id, "{} didn't find the upserted point", name);
+                assert_eq!(results[0].payload.page_url, "moved");
+            };
+            check(index.as_ref());
+
+            let before: Vec<Vec<PointId>> = queries.iter().map(|query| index.search(&QueryVector { vec: query.clone() }, 10).iter().map(|result| result.id).collect()).collect();
+            assert!(!index.maybe_compact(0.5));
+            assert!(index.maybe_compact(0.25));
+            assert_eq!(index.tombstone_count(), 0);
+            assert_eq!(index.point_count(), points.len() - deleted_count);
+            check(index.as_ref());
+
+            // compacting doesn't change which points are in the exact index, so it gives the same results
+            if name == "simple" {
+                let after: Vec<Vec<PointId>> = queries.iter().map(|query| index.search(&QueryVector { vec: query.clone() }, 10).iter().map(|result| result.id).collect()).collect();
+                assert_eq!(before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn ids_are_saved_and_never_reused() {
+        // the ids are saved with the index, and deleted points aren't
+        let mut random = XorShift64::new(12);
+        let dimension = 16;
+        let temp_path = TempPath::new("vector_index_ids.bin");
+        let points: Vec<Vec<f32>> = (0..2000).map(|_| random.vector(dimension)).collect();
+        let queries: Vec<Vec<f32>> = (0..20).map(|_| random.vector(dimension)).collect();
+
+        let mut index = SimpleSearch::new(dimension, DistanceMetric::Cosine);
+        for (offset, point) in points.iter().enumerate() {
+            index.upsert(FIRST_ID + offset as PointId, point_vector(point, FIRST_ID + offset as PointId));
+        }
+        for id in FIRST_ID..FIRST_ID + points.len() as PointId {
+            if is_deleted(id) {
+                index.delete(id);
+            }
+        }
+        // the point with the highest id isn't saved, but its id still can't be given to a new point
+        let highest_id = FIRST_ID + points.len() as PointId - 1;
+        assert!(index.delete(highest_id));
+        index.save(temp_path.path());
+
+        let mut loaded = SimpleSearch::load_mmap(temp_path.path());
+        assert_eq!(loaded.point_count(), index.point_count());
+        assert_eq!(loaded.tombstone_count(), 0);
+        for query in queries.iter() {
+            let query = QueryVector { vec: query.clone() };
+            let expected: Vec<(PointId, f32)> = index.search(&query, 10).iter().map(|result| (result.id, result.score)).collect();
+            let results: Vec<(PointId, f32)> = loaded.search(&query, 10).iter().map(|result| (result.id, result.score)).collect();
+            assert_eq!(results, expected);
+        }
+
+        // new points get ids that have never been used, even after compacting
+        assert_eq!(loaded.add_point(point_vector(&points[0], 0)), highest_id + 1);
+        assert!(loaded.delete(highest_id + 1));
+        loaded.compact();
+        assert_eq!(loaded.add_point(point_vector(&points[0], 0)), highest_id + 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "point id 18446744073709551615 is reserved")]
+    fn the_highest_id_is_reserved() {
+        // one more than it would overflow, so no point can have it
+        let mut index = SimpleSearch::new(4, DistanceMetric::L2);
+        index.upsert(PointId::MAX, point_vector(&[1.0, 2.0, 3.0, 4.0], 0));
+    }
+}
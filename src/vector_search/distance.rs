@@ -0,0 +1,452 @@
+/*
+
+    This script contains the distance kernels used by vector search: dot product, cosine similarity and squared L2 distance, for one pair of vectors or for one query against many vectors stored one after the other.
+
+    The fastest kernel that the CPU supports is picked the first time one is used: AVX-512 or AVX2 with FMA on x86_64, NEON on aarch64, and otherwise portable scalar code. The AVX-512 intrinsics need Rust 1.89 or newer, which is the rust-version in Cargo.toml. The scalar code keeps 8 separate sums, which the compiler can turn into SIMD instructions on its own, so it is not much slower on CPUs that it knows about at compile time.
+
+    The lengths of the vectors are checked once per call, so the batched forms only check them once for the whole batch.
+
+*/
+
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    Scalar,
+    Avx2,
+    Avx512,
+    Neon,
+}
+
+impl Kernel {
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Kernel::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx512 => is_x86_feature_detected!("avx512f"),
+            #[cfg(target_arch = "aarch64")]
+            Kernel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            _ => false,
+        }
+    }
+}
+
+pub fn supported_kernels() -> Vec<Kernel> {
+    [Kernel::Scalar, Kernel::Avx2, Kernel::Avx512, Kernel::Neon].into_iter().filter(|kernel| kernel.is_supported()).collect()
+}
+
+pub fn detected_kernel() -> Kernel {
+    /*
+        The fastest kernel that this CPU supports. It is only detected once.
+    */
+    static DETECTED: OnceLock<Kernel> = OnceLock::new();
+    *DETECTED.get_or_init(|| {
+        for kernel in [Kernel::Avx512, Kernel::Avx2, Kernel::Neon] {
+            if kernel.is_supported() {
+                return kernel;
+            }
+        }
+        Kernel::Scalar
+    })
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    dot_with(detected_kernel(), a, b)
+}
+
+pub fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+    l2_squared_with(detected_kernel(), a, b)
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    cosine_with(detected_kernel(), a, b)
+}
+
+pub fn dot_with(kernel: Kernel, a: &[f32], b: &[f32]) -> f32 {
+    /*
+        The dot product using a specific kernel, which must be supported by the CPU. This is for comparing the kernels against each other.
+    */
+    assert_eq!(a.len(), b.len(), "vectors have different numbers of dimensions");
+    assert!(kernel.is_supported(), "{:?} is not supported by this CPU", kernel);
+
+    // Safety: the lengths are equal, and the CPU supports the kernel's instructions
+    unsafe { dot_unchecked(kernel, a, b) }
+}
+
+pub fn l2_squared_with(kernel: Kernel, a: &[f32], b: &[f32]) -> f32 {
+    assert_eq!(a.len(), b.len(), "vectors have different numbers of dimensions");
+    assert!(kernel.is_supported(), "{:?} is not supported by this CPU", kernel);
+
+    // Safety: the lengths are equal, and the CPU supports the kernel's instructions
+    unsafe { l2_squared_unchecked(kernel, a, b) }
+}
+
+pub fn cosine_with(kernel: Kernel, a: &[f32], b: &[f32]) -> f32 {
+    // A zero vector isn't pointing in any direction, so it isn't similar to anything
+    // the norms are multiplied the same way as in cosine_batch, so that both give exactly the same answer
+    let norms = dot_with(kernel, a, a).sqrt() * dot_with(kernel, b, b).sqrt();
+    if norms == 0.0 {
+        return 0.0;
+    }
+    dot_with(kernel, a, b) / norms
+}
+
+pub fn dot_batch(query: &[f32], vectors: &[f32], out: &mut [f32]) {
+    /*
+        The dot product of the query with each of the vectors, which are stored one after the other in a single slice. out must have one value for each vector.
+    */
+    let dimension = check_batch(query, vectors, out);
+    if dimension == 0 {
+        out.iter_mut().for_each(|value| *value = 0.0);
+        return;
+    }
+    let kernel = detected_kernel();
+    for (vector, value) in vectors.chunks_exact(dimension).zip(out.iter_mut()) {
+        // Safety: every chunk has the query's length, and the kernel was detected as supported
+        *value = unsafe { dot_unchecked(kernel, query, vector) };
+    }
+}
+
+pub fn l2_squared_batch(query: &[f32], vectors: &[f32], out: &mut [f32]) {
+    let dimension = check_batch(query, vectors, out);
+    if dimension == 0 {
+        out.iter_mut().for_each(|value| *value = 0.0);
+        return;
+    }
+    let kernel = detected_kernel();
+    for (vector, value) in vectors.chunks_exact(dimension).zip(out.iter_mut()) {
+        // Safety: every chunk has the query's length, and the kernel was detected as supported
+        *value = unsafe { l2_squared_unchecked(kernel, query, vector) };
+    }
+}
+
+pub fn cosine_batch(query: &[f32], vectors: &[f32], out: &mut [f32]) {
+    let dimension = check_batch(query, vectors, out);
+    let kernel = detected_kernel();
+
+    // the query's norm is only calculated once. Safety: the query is compared with itself, and the kernel was detected as supported
+    let query_norm = unsafe { dot_unchecked(kernel, query, query) }.sqrt();
+    if dimension == 0 || query_norm == 0.0 {
+        out.iter_mut().for_each(|value| *value = 0.0);
+        return;
+    }
+    for (vector, value) in vectors.chunks_exact(dimension).zip(out.iter_mut()) {
+        // Safety: every chunk has the query's length, and the kernel was detected as supported
+        let (product, vector_norm) = unsafe { (dot_unchecked(kernel, query, vector), dot_unchecked(kernel, vector, vector).sqrt()) };
+        *value = if vector_norm == 0.0 { 0.0 } else { product / (query_norm * vector_norm) };
+    }
+}
+
+fn check_batch(query: &[f32], vectors: &[f32], out: &[f32]) -> usize {
+    let dimension = query.len();
+    if dimension == 0 {
+        assert!(vectors.is_empty(), "an empty query can only be compared with empty vectors");
+    } else {
+        assert_eq!(vectors.len() % dimension, 0, "the vectors don't have the query's number of dimensions");
+        assert_eq!(vectors.len() / dimension, out.len(), "out must have one value for each vector");
+    }
+    dimension
+}
+
+unsafe fn dot_unchecked(kernel: Kernel, a: &[f32], b: &[f32]) -> f32 {
+    match kernel {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx512 => x86::dot_avx512(a, b),
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => x86::dot_avx2(a, b),
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => neon::dot_neon(a, b),
+        _ => scalar::dot(a, b),
+    }
+}
+
+unsafe fn l2_squared_unchecked(kernel: Kernel, a: &[f32], b: &[f32]) -> f32 {
+    match kernel {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx512 => x86::l2_squared_avx512(a, b),
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => x86::l2_squared_avx2(a, b),
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => neon::l2_squared_neon(a, b),
+        _ => scalar::l2_squared(a, b),
+    }
+}
+
+mod scalar {
+    // 8 separate sums, so that the additions don't all have to wait on each other
+    const LANES: usize = 8;
+
+    pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let mut sums = [0.0f32; LANES];
+        let a_chunks = a.chunks_exact(LANES);
+        let b_chunks = b.chunks_exact(LANES);
+        let tail: f32 = a_chunks.remainder().iter().zip(b_chunks.remainder().iter()).map(|(x, y)| x * y).sum();
+        for (a_chunk, b_chunk) in a_chunks.zip(b_chunks) {
+            for lane in 0..LANES {
+                sums[lane] += a_chunk[lane] * b_chunk[lane];
+            }
+        }
+        sums.iter().sum::<f32>() + tail
+    }
+
+    pub fn l2_squared(a: &[f32], b: &[f32]) -> f32 {
+        let mut sums = [0.0f32; LANES];
+        let a_chunks = a.chunks_exact(LANES);
+        let b_chunks = b.chunks_exact(LANES);
+        let tail: f32 = a_chunks.remainder().iter().zip(b_chunks.remainder().iter()).map(|(x, y)| (x - y) * (x - y)).sum();
+        for (a_chunk, b_chunk) in a_chunks.zip(b_chunks) {
+            for lane in 0..LANES {
+                let difference = a_chunk[lane] - b_chunk[lane];
+                sums[lane] += difference * difference;
+            }
+        }
+        sums.iter().sum::<f32>() + tail
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    // Every function here needs a and b to have the same length, and the CPU to support the enabled features
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot_avx2(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+
+        // two sums of 8, so that each fused multiply add doesn't wait on the one before it
+        let mut sum_0 = _mm256_setzero_ps();
+        let mut sum_1 = _mm256_setzero_ps();
+        let mut i = 0;
+        while i + 16 <= len {
+            sum_0 = _mm256_fmadd_ps(_mm256_loadu_ps(a_ptr.add(i)), _mm256_loadu_ps(b_ptr.add(i)), sum_0);
+            sum_1 = _mm256_fmadd_ps(_mm256_loadu_ps(a_ptr.add(i + 8)), _mm256_loadu_ps(b_ptr.add(i + 8)), sum_1);
+            i += 16;
+        }
+        if i + 8 <= len {
+            sum_0 = _mm256_fmadd_ps(_mm256_loadu_ps(a_ptr.add(i)), _mm256_loadu_ps(b_ptr.add(i)), sum_0);
+            i += 8;
+        }
+
+        let mut sum = horizontal_sum_256(_mm256_add_ps(sum_0, sum_1));
+        while i < len {
+            sum += *a_ptr.add(i) * *b_ptr.add(i);
+            i += 1;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn l2_squared_avx2(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+
+        let mut sum_0 = _mm256_setzero_ps();
+        let mut sum_1 = _mm256_setzero_ps();
+        let mut i = 0;
+        while i + 16 <= len {
+            let difference_0 = _mm256_sub_ps(_mm256_loadu_ps(a_ptr.add(i)), _mm256_loadu_ps(b_ptr.add(i)));
+            let difference_1 = _mm256_sub_ps(_mm256_loadu_ps(a_ptr.add(i + 8)), _mm256_loadu_ps(b_ptr.add(i + 8)));
+            sum_0 = _mm256_fmadd_ps(difference_0, difference_0, sum_0);
+            sum_1 = _mm256_fmadd_ps(difference_1, difference_1, sum_1);
+            i += 16;
+        }
+        if i + 8 <= len {
+            let difference = _mm256_sub_ps(_mm256_loadu_ps(a_ptr.add(i)), _mm256_loadu_ps(b_ptr.add(i)));
+            sum_0 = _mm256_fmadd_ps(difference, difference, sum_0);
+            i += 8;
+        }
+
+        let mut sum = horizontal_sum_256(_mm256_add_ps(sum_0, sum_1));
+        while i < len {
+            let difference = *a_ptr.add(i) - *b_ptr.add(i);
+            sum += difference * difference;
+            i += 1;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn horizontal_sum_256(sum: __m256) -> f32 {
+        let halves = _mm_add_ps(_mm256_castps256_ps128(sum), _mm256_extractf128_ps(sum, 1));
+        let pairs = _mm_add_ps(halves, _mm_movehl_ps(halves, halves));
+        let single = _mm_add_ss(pairs, _mm_shuffle_ps(pairs, pairs, 1));
+        _mm_cvtss_f32(single)
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn dot_avx512(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+
+        let mut sum_0 = _mm512_setzero_ps();
+        let mut sum_1 = _mm512_setzero_ps();
+        let mut i = 0;
+        while i + 32 <= len {
+            sum_0 = _mm512_fmadd_ps(_mm512_loadu_ps(a_ptr.add(i)), _mm512_loadu_ps(b_ptr.add(i)), sum_0);
+            sum_1 = _mm512_fmadd_ps(_mm512_loadu_ps(a_ptr.add(i + 16)), _mm512_loadu_ps(b_ptr.add(i + 16)), sum_1);
+            i += 32;
+        }
+        if i + 16 <= len {
+            sum_0 = _mm512_fmadd_ps(_mm512_loadu_ps(a_ptr.add(i)), _mm512_loadu_ps(b_ptr.add(i)), sum_0);
+            i += 16;
+        }
+        // the last few values are loaded with a mask, so they don't need a scalar loop
+        if i < len {
+            let mask: __mmask16 = (1u16 << (len - i)) - 1;
+            sum_1 = _mm512_fmadd_ps(_mm512_maskz_loadu_ps(mask, a_ptr.add(i)), _mm512_maskz_loadu_ps(mask, b_ptr.add(i)), sum_1);
+        }
+
+        _mm512_reduce_add_ps(_mm512_add_ps(sum_0, sum_1))
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn l2_squared_avx512(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+
+        let mut sum_0 = _mm512_setzero_ps();
+        let mut sum_1 = _mm512_setzero_ps();
+        let mut i = 0;
+        while i + 32 <= len {
+            let difference_0 = _mm512_sub_ps(_mm512_loadu_ps(a_ptr.add(i)), _mm512_loadu_ps(b_ptr.add(i)));
+            let difference_1 = _mm512_sub_ps(_mm512_loadu_ps(a_ptr.add(i + 16)), _mm512_loadu_ps(b_ptr.add(i + 16)));
+            sum_0 = _mm512_fmadd_ps(difference_0, difference_0, sum_0);
+            sum_1 = _mm512_fmadd_ps(difference_1, difference_1, sum_1);
+            i += 32;
+        }
+        if i + 16 <= len {
+            let difference = _mm512_sub_ps(_mm512_loadu_ps(a_ptr.add(i)), _mm512_loadu_ps(b_ptr.add(i)));
+            sum_0 = _mm512_fmadd_ps(difference, difference, sum_0);
+            i += 16;
+        }
+        if i < len {
+            let mask: __mmask16 = (1u16 << (len - i)) - 1;
+            let difference = _mm512_sub_ps(_mm512_maskz_loadu_ps(mask, a_ptr.add(i)), _mm512_maskz_loadu_ps(mask, b_ptr.add(i)));
+            sum_1 = _mm512_fmadd_ps(difference, difference, sum_1);
+        }
+
+        _mm512_reduce_add_ps(_mm512_add_ps(sum_0, sum_1))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    // Every function here needs a and b to have the same length
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn dot_neon(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+
+        let mut sum_0 = vdupq_n_f32(0.0);
+        let mut sum_1 = vdupq_n_f32(0.0);
+        let mut i = 0;
+        while i + 8 <= len {
+            sum_0 = vfmaq_f32(sum_0, vld1q_f32(a_ptr.add(i)), vld1q_f32(b_ptr.add(i)));
+            sum_1 = vfmaq_f32(sum_1, vld1q_f32(a_ptr.add(i + 4)), vld1q_f32(b_ptr.add(i + 4)));
+            i += 8;
+        }
+
+        let mut sum = vaddvq_f32(vaddq_f32(sum_0, sum_1));
+        while i < len {
+            sum += *a_ptr.add(i) * *b_ptr.add(i);
+            i += 1;
+        }
+        sum
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn l2_squared_neon(a: &[f32], b: &[f32]) -> f32 {
+        let len = a.len();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+
+        let mut sum_0 = vdupq_n_f32(0.0);
+        let mut sum_1 = vdupq_n_f32(0.0);
+        let mut i = 0;
+        while i + 8 <= len {
+            let difference_0 = vsubq_f32(vld1q_f32(a_ptr.add(i)), vld1q_f32(b_ptr.add(i)));
+            let difference_1 = vsubq_f32(vld1q_f32(a_ptr.add(i + 4)), vld1q_f32(b_ptr.add(i + 4)));
+            sum_0 = vfmaq_f32(sum_0, difference_0, difference_0);
+            sum_1 = vfmaq_f32(sum_1, difference_1, difference_1);
+            i += 8;
+        }
+
+        let mut sum = vaddvq_f32(vaddq_f32(sum_0, sum_1));
+        while i < len {
+            let difference = *a_ptr.add(i) - *b_ptr.add(i);
+            sum += difference * difference;
+            i += 1;
+        }
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::XorShift64;
+
+    // the kernels add in a different order, so they only agree up to rounding, relative to the size of the terms being added
+    fn close(a: f32, b: f32, scale: f32) -> bool {
+        (a - b).abs() <= 1e-5 * scale.max(1.0)
+    }
+
+    fn random_vector(random: &mut XorShift64, length: usize) -> Vec<f32> {
+        (0..length).map(|_| 10.0 * random.next_signed_f32()).collect()
+    }
+
+    #[test]
+    fn every_kernel_matches_scalar() {
+        // every kernel that this CPU supports, on vectors of every length up to 300, so that every remainder of every SIMD width is covered
+        let mut random = XorShift64::new(3);
+        let kernels = supported_kernels();
+        assert!(kernels.contains(&Kernel::Scalar));
+        assert!(kernels.contains(&detected_kernel()));
+
+        for length in 0..=300 {
+            for _ in 0..5 {
+                let a = random_vector(&mut random, length);
+                let b = random_vector(&mut random, length);
+                let dot_scale: f32 = a.iter().zip(b.iter()).map(|(x, y)| (x * y).abs()).sum();
+                let l2_scale: f32 = a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum();
+
+                let dot = dot_with(Kernel::Scalar, &a, &b);
+                let l2 = l2_squared_with(Kernel::Scalar, &a, &b);
+                let cosine = cosine_with(Kernel::Scalar, &a, &b);
+                for kernel in kernels.iter() {
+                    assert!(close(dot_with(*kernel, &a, &b), dot, dot_scale), "{:?} dot product differs at length {}", kernel, length);
+                    assert!(close(l2_squared_with(*kernel, &a, &b), l2, l2_scale), "{:?} L2 distance differs at length {}", kernel, length);
+                    assert!(close(cosine_with(*kernel, &a, &b), cosine, 1.0), "{:?} cosine similarity differs at length {}", kernel, length);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn batches_match_one_vector_at_a_time() {
+        let mut random = XorShift64::new(5);
+        let dimension = 37;
+        let query = random_vector(&mut random, dimension);
+        let vectors = random_vector(&mut random, dimension * 50);
+        let mut out: Vec<f32> = vec![0.0; 50];
+
+        dot_batch(&query, &vectors, &mut out);
+        for (vector, value) in vectors.chunks(dimension).zip(out.iter()) {
+            assert_eq!(*value, dot(&query, vector));
+        }
+        l2_squared_batch(&query, &vectors, &mut out);
+        for (vector, value) in vectors.chunks(dimension).zip(out.iter()) {
+            assert_eq!(*value, l2_squared(&query, vector));
+        }
+        cosine_batch(&query, &vectors, &mut out);
+        for (vector, value) in vectors.chunks(dimension).zip(out.iter()) {
+            assert!(close(*value, cosine(&query, vector), 1.0));
+        }
+    }
+}
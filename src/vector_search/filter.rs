@@ -0,0 +1,162 @@
+/*
+
+    This script contains the filters that a vector search can be restricted with.
+
+    A filter is checked against the payload of each point while the index is being searched, not against the top_k results afterwards, so a filtered search still returns top_k points as long as that many points match. Points that don't match are still used to get around an HNSW graph, they just aren't returned.
+
+    Filters can check a field for equality, a range, or membership in a set, and can be combined with And, Or and Not. Tags have several values, so a filter on tags matches if any of the tags match.
+
+*/
+
+use super::VectorPayload;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PayloadField {
+    PageUrl,
+    ImageUrl,
+    Host,
+    Language,
+    ContentType,
+    PageRank,
+    CrawlDate,
+    Tags,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterValue {
+    Text(String),
+    Number(f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PayloadFilter {
+    Equals(PayloadField, FilterValue),
+
+    // min and max are inclusive, and either can be left out. Only numeric fields can be in a range.
+    Range { field: PayloadField, min: Option<f64>, max: Option<f64> },
+
+    In(PayloadField, Vec<FilterValue>),
+
+    And(Vec<PayloadFilter>),
+    Or(Vec<PayloadFilter>),
+    Not(Box<PayloadFilter>),
+}
+
+impl PayloadFilter {
+    pub fn matches(&self, payload: &VectorPayload) -> bool {
+        match self {
+            PayloadFilter::Equals(field, value) => field_matches(payload, *field, |field_value| field_value == *value),
+            PayloadFilter::Range { field, min, max } => field_matches(payload, *field, |field_value| match field_value {
+                FilterValue::Number(number) => min.is_none_or(|min| number >= min) && max.is_none_or(|max| number <= max),
+                FilterValue::Text(_) => false,
+            }),
+            PayloadFilter::In(field, values) => field_matches(payload, *field, |field_value| values.contains(&field_value)),
+            PayloadFilter::And(filters) => filters.iter().all(|filter| filter.matches(payload)),
+            PayloadFilter::Or(filters) => filters.iter().any(|filter| filter.matches(payload)),
+            PayloadFilter::Not(filter) => !filter.matches(payload),
+        }
+    }
+}
+
+pub fn matches_filter(filter: Option<&PayloadFilter>, payload: &VectorPayload) -> bool {
+    // no filter matches every point
+    filter.is_none_or(|filter| filter.matches(payload))
+}
+
+fn field_matches<F: Fn(FilterValue) -> bool>(payload: &VectorPayload, field: PayloadField, check: F) -> bool {
+    match field {
+        PayloadField::PageUrl => check(FilterValue::Text(payload.page_url.clone())),
+        PayloadField::ImageUrl => check(FilterValue::Text(payload.image_url.clone())),
+        PayloadField::Host => check(FilterValue::Text(payload.host.clone())),
+        PayloadField::Language => check(FilterValue::Text(payload.language.clone())),
+        PayloadField::ContentType => check(FilterValue::Text(payload.content_type.clone())),
+        PayloadField::PageRank => check(FilterValue::Number(payload.page_rank as f64)),
+        PayloadField::CrawlDate => check(FilterValue::Number(payload.crawl_date as f64)),
+        PayloadField::Tags => payload.tags.iter().any(|tag| check(FilterValue::Text(tag.clone()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::XorShift64;
+    use crate::vector_search::hnsw::{HnswConfig, HnswIndex};
+    use crate::vector_search::ivf_pq::{IvfPqConfig, IvfPqIndex};
+    use crate::vector_search::{DistanceMetric, PointVector, QueryVector, SimpleSearch, VectorSearchClient};
+
+    fn copy(point: &PointVector) -> PointVector {
+        PointVector { vec: point.vec.clone(), payload: point.payload.clone() }
+    }
+
+    fn text(value: &str) -> FilterValue {
+        FilterValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn every_index_returns_only_matching_points() {
+        // every result matches the filter, each search still returns top_k results when enough points match, and the exact index finds the same points as filtering every point by hand
+        let mut random = XorShift64::new(13);
+        let dimension = 16;
+        let top_k = 10;
+        let points: Vec<PointVector> = (0..3000).map(|index| PointVector {
+            vec: random.vector(dimension),
+            payload: VectorPayload {
+                page_url: format!("https://host{}.org/{}", index % 5, index),
+                image_url: String::new(),
+                host: format!("host{}.org", index % 5),
+                language: if index % 7 == 0 { "de".to_string() } else { "en".to_string() },
+                content_type: "text/html".to_string(),
+                page_rank: (random.next_signed_f32() + 1.0) / 2.0,
+                crawl_date: 1_700_000_000 + index as u64,
+                tags: if index % 11 == 0 { vec!["news".to_string(), "sports".to_string()] } else { vec!["news".to_string()] },
+            },
+        }).collect();
+        let queries: Vec<Vec<f32>> = (0..20).map(|_| random.vector(dimension)).collect();
+
+        let filters = [
+            PayloadFilter::Equals(PayloadField::Host, text("host3.org")),
+            PayloadFilter::And(vec![
+                PayloadFilter::In(PayloadField::Language, vec![text("de"), text("fr")]),
+                PayloadFilter::Range { field: PayloadField::PageRank, min: Some(0.5), max: None },
+            ]),
+            PayloadFilter::Or(vec![
+                PayloadFilter::Equals(PayloadField::Tags, text("sports")),
+                PayloadFilter::Range { field: PayloadField::CrawlDate, min: Some(1_700_000_000.0), max: Some(1_700_000_004.0) },
+            ]),
+            PayloadFilter::Not(Box::new(PayloadFilter::Equals(PayloadField::Tags, text("news")))),
+            PayloadFilter::Equals(PayloadField::PageUrl, text("https://host2.org/1337")),
+        ];
+
+        let mut simple = SimpleSearch::new(dimension, DistanceMetric::L2);
+        let mut hnsw = HnswIndex::new(dimension, DistanceMetric::L2, HnswConfig::default());
+        let mut ivf_pq = IvfPqIndex::new(dimension, DistanceMetric::L2, IvfPqConfig { nlist: 32, ..Default::default() });
+        ivf_pq.train(&points.iter().map(copy).collect::<Vec<PointVector>>());
+        for point in points.iter() {
+            simple.add_point(copy(point));
+            hnsw.add_point(copy(point));
+            ivf_pq.add_point(copy(point));
+        }
+
+        for filter in filters.iter() {
+            let matching: Vec<&PointVector> = points.iter().filter(|point| filter.matches(&point.payload)).collect();
+            let expected_count = matching.len().min(top_k);
+
+            for query in queries.iter() {
+                let query = QueryVector { vec: query.clone() };
+                let indexes: [(&str, &dyn VectorSearchClient); 3] = [("simple", &simple), ("hnsw", &hnsw), ("ivf-pq", &ivf_pq)];
+                for (name, index) in indexes.iter() {
+                    let results = index.search_filtered(&query, top_k, Some(filter));
+                    assert_eq!(results.len(), expected_count, "{} returned too few results for {:?}", name, filter);
+                    assert!(results.iter().all(|result| filter.matches(&result.payload)), "{} returned a point that doesn't match {:?}", name, filter);
+                }
+
+                let mut by_hand: Vec<(f32, &str)> = matching.iter().map(|point| (DistanceMetric::L2.score(&query.vec, &point.vec), point.payload.page_url.as_str())).collect();
+                by_hand.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let results = simple.search_filtered(&query, top_k, Some(filter));
+                for (result, (_, page_url)) in results.iter().zip(by_hand.iter()) {
+                    assert_eq!(result.payload.page_url, *page_url);
+                }
+            }
+        }
+    }
+}
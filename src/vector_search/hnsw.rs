@@ -0,0 +1,385 @@
+/*
+
+    This script contains an HNSW (hierarchical navigable small world) index, an approximate nearest neighbor index that can search millions of points while only scoring a few thousand of them.
+
+    Every point is a node in a graph, linked to the points closest to it. A search starts somewhere in the graph and keeps moving to whichever neighbor is closest to the query. To get across the graph quickly, there are several layers: every point is in layer 0, and each layer above has exponentially fewer points (about 1 in M), so the top layers have long links that get the search close to the query in a few steps, and the lower layers refine it.
+
+    Points are inserted one at a time, by searching for their neighbors in the index, so the index never has to be rebuilt (Malkov and Yashunin, 2016).
+
+    Removing a point from the graph would cut off the points that are only reachable through it, so deleted points are left in the graph as tombstones: searches still move through them, but never return them. Compacting rebuilds the graph from the points that are left. Points that don't match a search's filter are skipped the same way.
+
+*/
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+use super::{matches_filter, select_top_k, DistanceMetric, HeapEntry, PayloadFilter, PointId, PointSlots, PointVector, QueryVector, VectorSearchClient, VectorSearchResult};
+use crate::random::XorShift64;
+
+pub struct HnswConfig {
+    // The number of neighbors that each point is linked to in every layer above 0. Layer 0 has twice as many. More neighbors give better recall, but use more memory and make inserts slower.
+    pub m: usize,
+
+    // How many candidate neighbors are kept while searching for the neighbors of a new point. Higher builds a better graph, but makes inserts slower.
+    pub ef_construction: usize,
+
+    // How many candidates are kept while searching. Higher gives better recall, but makes searches slower. This can be changed after the index is built, and is raised to top_k if it is lower.
+    pub ef_search: usize,
+
+    // Seed for picking the layer of each point, so that building the same index twice gives the same graph
+    pub seed: u64,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            seed: 42,
+        }
+    }
+}
+
+pub struct HnswIndex {
+    config: HnswConfig,
+    metric: DistanceMetric,
+    dimension: usize,
+
+    points: Vec<PointVector>,
+    ids: PointSlots,
+
+    // neighbors[point][layer] is the list of points that the point is linked to in that layer. A point is in every layer from 0 up to its own level.
+    neighbors: Vec<Vec<Vec<u32>>>,
+
+    // the point that every search starts from, which is in the highest layer
+    entry_point: Option<u32>,
+
+    random: XorShift64,
+}
+
+impl HnswIndex {
+    pub fn new(dimension: usize, metric: DistanceMetric, config: HnswConfig) -> HnswIndex {
+        assert!(config.m >= 2, "HNSW needs at least 2 neighbors per point");
+        let random = XorShift64::new(config.seed);
+        HnswIndex {
+            config,
+            metric,
+            dimension,
+            points: Vec::new(),
+            ids: PointSlots::new(),
+            neighbors: Vec::new(),
+            entry_point: None,
+            random,
+        }
+    }
+
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.config.ef_search = ef_search;
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    pub fn add_point(&mut self, point: PointVector) -> PointId {
+        // adds the point with a new id, which is returned
+        let id = self.ids.new_id();
+        self.upsert(id, point);
+        id
+    }
+
+    fn insert(&mut self, point: PointVector) {
+        /*
+
+            Insert a point into the index. Its level is picked at random, and then in every layer from its level down to 0, it is linked to the closest points that can be found, and they are linked back to it.
+
+        */
+        let node = self.points.len() as u32;
+        let level = self.random_level();
+        self.points.push(point);
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                self.entry_point = Some(node);
+                return;
+            }
+        };
+        let top_level = self.level(entry_point);
+
+        // greedily move towards the new point through the layers above its level
+        let query = self.points[node as usize].vec.clone();
+        let mut nearest = entry_point;
+        for layer in (level + 1..=top_level).rev() {
+            nearest = self.greedy_closest(&query, nearest, layer);
+        }
+
+        // link the point in each of its layers
+        let mut entry_points = vec![nearest];
+        for layer in (0..=level.min(top_level)).rev() {
+            let candidates = self.search_layer(&query, &entry_points, self.config.ef_construction, layer, &|_| true);
+            let selected = self.select_neighbors(&candidates, self.max_neighbors(layer));
+            self.neighbors[node as usize][layer] = selected.iter().map(|entry| entry.index as u32).collect();
+
+            for entry in selected.iter() {
+                let neighbor = entry.index as u32;
+                self.neighbors[neighbor as usize][layer].push(node);
+                if self.neighbors[neighbor as usize][layer].len() > self.max_neighbors(layer) {
+                    self.shrink_neighbors(neighbor, layer);
+                }
+            }
+
+            entry_points = candidates.iter().map(|entry| entry.index as u32).collect();
+        }
+
+        if level > top_level {
+            self.entry_point = Some(node);
+        }
+    }
+
+    fn level(&self, point: u32) -> usize {
+        self.neighbors[point as usize].len() - 1
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 { 2 * self.config.m } else { self.config.m }
+    }
+
+    fn random_level(&mut self) -> usize {
+        // the level follows a geometric distribution, so that each layer has about 1/M of the points of the layer below it
+        let uniform = self.random.next_unit_f64();
+        (-uniform.ln() / (self.config.m as f64).ln()) as usize
+    }
+
+    fn closeness(&self, query: &[f32], point: u32) -> f32 {
+        self.metric.closeness(self.metric.score(query, &self.points[point as usize].vec))
+    }
+
+    fn greedy_closest(&self, query: &[f32], start: u32, layer: usize) -> u32 {
+        // keep moving to the closest neighbor until none of the neighbors are closer
+        let mut current = start;
+        let mut current_closeness = self.closeness(query, current);
+        loop {
+            let mut moved = false;
+            for neighbor in self.neighbors[current as usize][layer].iter() {
+                let closeness = self.closeness(query, *neighbor);
+                if closeness > current_closeness {
+                    current = *neighbor;
+                    current_closeness = closeness;
+                    moved = true;
+                }
+            }
+            if !moved {
+                return current;
+            }
+        }
+    }
+
+    fn search_layer(&self, query: &[f32], entry_points: &[u32], ef: usize, layer: usize, keep: &dyn Fn(u32) -> bool) -> Vec<HeapEntry> {
+        /*
+
+            Best first search through one layer, keeping the ef closest points found so far. The search stops once the closest candidate that hasn't been expanded is farther than the farthest of the ef closest points. Returns the closest points, closest first.
+
+            Points that keep rejects (deleted points, and points that don't match the filter) are still expanded, but are never kept as one of the closest points, so the search keeps going until it has found ef points that are kept, or has run out of points to expand. Inserts keep every point, so that new points can still be linked through deleted ones.
+
+        */
+        let mut visited: HashSet<u32> = entry_points.iter().copied().collect();
+
+        // candidates has the closest point on top, and found has the farthest point on top
+        let mut candidates: BinaryHeap<Reverse<HeapEntry>> = BinaryHeap::new();
+        let mut found: BinaryHeap<HeapEntry> = BinaryHeap::new();
+        for entry_point in entry_points.iter() {
+            let closeness = self.closeness(query, *entry_point);
+            candidates.push(Reverse(HeapEntry { closeness, index: *entry_point as usize }));
+            if !keep(*entry_point) {
+                continue;
+            }
+            found.push(HeapEntry { closeness, index: *entry_point as usize });
+            if found.len() > ef {
+                found.pop();
+            }
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            if found.len() >= ef && candidate.closeness < found.peek().unwrap().closeness {
+                break;
+            }
+
+            for neighbor in self.neighbors[candidate.index][layer].iter() {
+                if !visited.insert(*neighbor) {
+                    continue;
+                }
+                let closeness = self.closeness(query, *neighbor);
+                if found.len() < ef || closeness > found.peek().unwrap().closeness {
+                    candidates.push(Reverse(HeapEntry { closeness, index: *neighbor as usize }));
+                    if !keep(*neighbor) {
+                        continue;
+                    }
+                    found.push(HeapEntry { closeness, index: *neighbor as usize });
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+
+    fn select_neighbors(&self, candidates: &[HeapEntry], max_neighbors: usize) -> Vec<HeapEntry> {
+        /*
+
+            Pick the neighbors of a point from candidates sorted closest first. A candidate is skipped if it is closer to a neighbor that was already picked than it is to the point, since the search can already get to it through that neighbor. This spreads the links out in different directions, which keeps clusters of points connected to each other. If that leaves room, the closest skipped candidates fill it.
+
+        */
+        let mut selected: Vec<HeapEntry> = Vec::with_capacity(max_neighbors);
+        let mut skipped: Vec<&HeapEntry> = Vec::new();
+        for candidate in candidates.iter() {
+            if selected.len() >= max_neighbors {
+                break;
+            }
+            let candidate_vec = &self.points[candidate.index].vec;
+            let closer_to_selected = selected.iter().any(|neighbor| self.closeness(candidate_vec, neighbor.index as u32) > candidate.closeness);
+            if closer_to_selected {
+                skipped.push(candidate);
+            } else {
+                selected.push(HeapEntry { closeness: candidate.closeness, index: candidate.index });
+            }
+        }
+
+        for candidate in skipped.into_iter() {
+            if selected.len() >= max_neighbors {
+                break;
+            }
+            selected.push(HeapEntry { closeness: candidate.closeness, index: candidate.index });
+        }
+        selected
+    }
+
+    fn shrink_neighbors(&mut self, point: u32, layer: usize) {
+        // a point has too many neighbors after new points were linked to it, so its neighbors are picked again from the ones it has
+        let point_vec = &self.points[point as usize].vec;
+        let mut candidates: Vec<HeapEntry> = self.neighbors[point as usize][layer].iter()
+            .map(|neighbor| HeapEntry { closeness: self.closeness(point_vec, *neighbor), index: *neighbor as usize })
+            .collect();
+        candidates.sort();
+
+        let selected = self.select_neighbors(&candidates, self.max_neighbors(layer));
+        self.neighbors[point as usize][layer] = selected.iter().map(|entry| entry.index as u32).collect();
+    }
+}
+
+impl VectorSearchClient for HnswIndex {
+    fn search_filtered(&self, query: &QueryVector, top_k: usize, filter: Option<&PayloadFilter>) -> Vec<VectorSearchResult<'_>> {
+        assert_eq!(query.vec.len(), self.dimension, "query has the wrong number of dimensions");
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => return Vec::new(),
+        };
+
+        let mut nearest = entry_point;
+        for layer in (1..=self.level(entry_point)).rev() {
+            nearest = self.greedy_closest(&query.vec, nearest, layer);
+        }
+        let keep = |point: u32| !self.ids.is_deleted(point as usize) && matches_filter(filter, &self.points[point as usize].payload);
+        let found = self.search_layer(&query.vec, &[nearest], self.config.ef_search.max(top_k), 0, &keep);
+
+        // the closeness is turned back into the metric's score
+        let scores = found.into_iter().map(|entry| (entry.index, self.metric.closeness(entry.closeness)));
+        select_top_k(scores, top_k, self.metric).into_iter().map(|(index, score)| VectorSearchResult {
+            id: self.ids.id(index),
+            payload: Cow::Borrowed(&self.points[index].payload),
+            score,
+        }).collect()
+    }
+    fn point_count(&self) -> usize {
+        self.ids.live_count()
+    }
+    fn upsert(&mut self, id: PointId, point: PointVector) {
+        assert_eq!(point.vec.len(), self.dimension, "point has the wrong number of dimensions");
+        self.ids.push(id);
+        self.insert(point);
+    }
+    fn delete(&mut self, id: PointId) -> bool {
+        self.ids.delete(id).is_some()
+    }
+    fn tombstone_count(&self) -> usize {
+        self.ids.tombstone_count()
+    }
+    fn compact(&mut self) {
+        /*
+
+            Rebuild the graph from the points that haven't been deleted. The tombstones can't just be unlinked, because their neighbors would lose the links that connected them to the rest of the graph.
+
+        */
+        if self.ids.tombstone_count() == 0 {
+            return;
+        }
+
+        // the points are inserted again in the order of their new slots, so each one ends up in its new slot
+        let new_slots = self.ids.compact();
+        let points = std::mem::take(&mut self.points);
+        let live: Vec<PointVector> = points.into_iter().zip(new_slots)
+            .filter_map(|(point, new_slot)| new_slot.map(|_| point))
+            .collect();
+
+        self.neighbors = Vec::new();
+        self.entry_point = None;
+        for point in live.into_iter() {
+            self.insert(point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_search::{SimpleSearch, VectorPayload};
+
+    fn point(id: usize, vector: &[f32]) -> PointVector {
+        PointVector { vec: vector.to_vec(), payload: VectorPayload { page_url: id.to_string(), ..Default::default() } }
+    }
+
+    #[test]
+    fn recall_against_exact_search() {
+        // the HNSW index should find most of the same points as the exact search, and more of them as ef_search grows
+        let mut random = XorShift64::new(7);
+        let dimension = 16;
+        let top_k = 10;
+        let points: Vec<Vec<f32>> = (0..1500).map(|_| random.vector(dimension)).collect();
+        let queries: Vec<Vec<f32>> = (0..50).map(|_| random.vector(dimension)).collect();
+
+        for metric in [DistanceMetric::Cosine, DistanceMetric::L2] {
+            let mut exact = SimpleSearch::new(dimension, metric);
+            let mut index = HnswIndex::new(dimension, metric, HnswConfig::default());
+            for (id, vector) in points.iter().enumerate() {
+                exact.add_point(point(id, vector));
+                index.add_point(point(id, vector));
+            }
+            assert_eq!(index.point_count(), points.len());
+
+            let mut previous_recall = 0.0;
+            for ef_search in [16, 64, 256] {
+                index.set_ef_search(ef_search);
+
+                let mut found = 0;
+                for query in queries.iter() {
+                    let query = QueryVector { vec: query.clone() };
+                    let expected: HashSet<String> = exact.search(&query, top_k).into_iter().map(|result| result.payload.page_url.clone()).collect();
+                    found += index.search(&query, top_k).into_iter().filter(|result| expected.contains(&result.payload.page_url)).count();
+                }
+                let recall = found as f64 / (queries.len() * top_k) as f64;
+
+                assert!(recall >= previous_recall, "{:?}: recall fell from {:.3} to {:.3} at ef_search {}", metric, previous_recall, recall, ef_search);
+                if ef_search >= 64 {
+                    assert!(recall >= 0.95, "{:?}: HNSW recall@{} is {:.3} at ef_search {}", metric, top_k, recall, ef_search);
+                }
+                previous_recall = recall;
+            }
+        }
+    }
+}
@@ -0,0 +1,461 @@
+/*
+
+    This script contains an IVF-PQ (inverted file with product quantization) index, for searching more vectors than can be kept in memory at full precision (Jegou, Douze and Schmid, 2011).
+
+    IVF: the vectors are split into nlist clusters using k-means, and each cluster has a list of the vectors in it. A search only looks through the nprobe clusters whose centroids are closest to the query.
+
+    PQ: instead of the vector itself, each list stores a short code for the vector's residual (the vector minus its cluster's centroid). The residual is cut into sub_quantizers pieces, and each piece is replaced by the index of the closest of 256 centroids learned for that piece, so every vector takes sub_quantizers bytes instead of 4 bytes per dimension.
+
+    Searching uses asymmetric distances: the query is not quantized, and for each probed cluster a table of the distance from each piece of the query residual to each of the 256 centroids of that piece is made once, so the distance to every vector in the list is just sub_quantizers table lookups.
+
+    The index has to be trained on a sample of the vectors before anything can be added to it.
+
+    Deleted points stay in their lists as tombstones that searches skip over, until compacting removes their codes from the lists. Points that don't match a search's filter are skipped too, and if the nprobe closest clusters don't have top_k points that match, the search keeps probing the next closest clusters until they do.
+
+*/
+
+use std::borrow::Cow;
+
+use super::{distance, dot_product, matches_filter, select_top_k, DistanceMetric, PayloadFilter, PointId, PointSlots, PointVector, QueryVector, VectorPayload, VectorSearchClient, VectorSearchResult};
+use crate::random::XorShift64;
+
+// Each piece of a vector is encoded in one byte
+const CODEBOOK_SIZE: usize = 256;
+
+pub struct IvfPqConfig {
+    // The number of clusters (inverted lists)
+    pub nlist: usize,
+
+    // The number of clusters that are searched for each query. More clusters give better recall, but make searches slower. This can be changed after the index is built.
+    pub nprobe: usize,
+
+    // The number of pieces that each vector is cut into, which is also the number of bytes each vector takes. This must divide the dimension.
+    pub sub_quantizers: usize,
+
+    // The number of iterations of k-means used for training the clusters and the codebooks
+    pub kmeans_iterations: usize,
+
+    // Seed for picking the starting centroids of k-means, so that training on the same sample gives the same index
+    pub seed: u64,
+}
+
+impl Default for IvfPqConfig {
+    fn default() -> Self {
+        IvfPqConfig {
+            nlist: 256,
+            nprobe: 8,
+            sub_quantizers: 8,
+            kmeans_iterations: 20,
+            seed: 42,
+        }
+    }
+}
+
+struct InvertedList {
+    // the slot of each point in the list, which is its index into the index's payloads
+    points: Vec<u32>,
+
+    // sub_quantizers bytes for each point
+    codes: Vec<u8>,
+}
+
+pub struct IvfPqIndex {
+    config: IvfPqConfig,
+    metric: DistanceMetric,
+    dimension: usize,
+
+    // nlist x dimension
+    coarse_centroids: Vec<f32>,
+
+    // sub_quantizers x CODEBOOK_SIZE x (dimension / sub_quantizers)
+    codebooks: Vec<f32>,
+
+    lists: Vec<InvertedList>,
+    payloads: Vec<VectorPayload>,
+    ids: PointSlots,
+    trained: bool,
+}
+
+impl IvfPqIndex {
+    pub fn new(dimension: usize, metric: DistanceMetric, config: IvfPqConfig) -> IvfPqIndex {
+        assert!(config.nlist >= 1, "IVF-PQ needs at least one list");
+        assert!(config.sub_quantizers >= 1 && dimension.is_multiple_of(config.sub_quantizers), "the number of sub-quantizers must divide the dimension");
+        IvfPqIndex {
+            config,
+            metric,
+            dimension,
+            coarse_centroids: Vec::new(),
+            codebooks: Vec::new(),
+            lists: Vec::new(),
+            payloads: Vec::new(),
+            ids: PointSlots::new(),
+            trained: false,
+        }
+    }
+
+    pub fn set_nprobe(&mut self, nprobe: usize) {
+        self.config.nprobe = nprobe;
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    pub fn is_trained(&self) -> bool {
+        self.trained
+    }
+
+    pub fn train(&mut self, sample: &[PointVector]) {
+        /*
+
+            Learn the clusters from a sample of the vectors, and then the codebook of each piece from the residuals of the sample. The sample should be big enough to have many vectors in each cluster, and at least 256 vectors for the codebooks. Training again throws away every point in the index.
+
+        */
+        assert!(!sample.is_empty(), "IVF-PQ can't be trained without a sample");
+        let dimension = self.dimension;
+        let mut random = XorShift64::new(self.config.seed);
+
+        let mut vectors: Vec<f32> = Vec::with_capacity(sample.len() * dimension);
+        for point in sample.iter() {
+            assert_eq!(point.vec.len(), dimension, "sample point has the wrong number of dimensions");
+            vectors.extend(self.prepare(&point.vec));
+        }
+
+        let nlist = self.config.nlist.min(sample.len());
+        self.coarse_centroids = kmeans(&vectors, dimension, nlist, self.config.kmeans_iterations, &mut random);
+
+        // the residual of every sample vector from its cluster's centroid
+        let mut residuals: Vec<f32> = Vec::with_capacity(vectors.len());
+        for vector in vectors.chunks(dimension) {
+            let list = nearest_centroid(&self.coarse_centroids, dimension, vector);
+            let centroid = &self.coarse_centroids[list * dimension..(list + 1) * dimension];
+            residuals.extend(vector.iter().zip(centroid.iter()).map(|(value, center)| value - center));
+        }
+
+        // each piece gets its own codebook. If the sample has fewer than 256 vectors, the rest of the codebook is left at zero.
+        let sub_dimension = self.sub_dimension();
+        self.codebooks = vec![0.0; self.config.sub_quantizers * CODEBOOK_SIZE * sub_dimension];
+        for piece in 0..self.config.sub_quantizers {
+            let mut sub_vectors: Vec<f32> = Vec::with_capacity(sample.len() * sub_dimension);
+            for residual in residuals.chunks(dimension) {
+                sub_vectors.extend_from_slice(&residual[piece * sub_dimension..(piece + 1) * sub_dimension]);
+            }
+            let codebook = kmeans(&sub_vectors, sub_dimension, CODEBOOK_SIZE.min(sample.len()), self.config.kmeans_iterations, &mut random);
+            let start = piece * CODEBOOK_SIZE * sub_dimension;
+            self.codebooks[start..start + codebook.len()].copy_from_slice(&codebook);
+        }
+
+        self.lists = (0..nlist).map(|_| InvertedList { points: Vec::new(), codes: Vec::new() }).collect();
+        self.payloads.clear();
+        self.ids = PointSlots::new();
+        self.trained = true;
+    }
+
+    pub fn add_point(&mut self, point: PointVector) -> PointId {
+        // adds the point with a new id, which is returned
+        let id = self.ids.new_id();
+        self.upsert(id, point);
+        id
+    }
+
+    fn insert(&mut self, point: PointVector) {
+        /*
+
+            Add a point to the list of its closest cluster. Only the code of the vector is kept, not the vector itself.
+
+        */
+
+        let dimension = self.dimension;
+        let sub_dimension = self.sub_dimension();
+        let vector = self.prepare(&point.vec);
+        let list = nearest_centroid(&self.coarse_centroids, dimension, &vector);
+        let centroid = &self.coarse_centroids[list * dimension..(list + 1) * dimension];
+        let residual: Vec<f32> = vector.iter().zip(centroid.iter()).map(|(value, center)| value - center).collect();
+
+        let mut codes: Vec<u8> = Vec::with_capacity(self.config.sub_quantizers);
+        for piece in 0..self.config.sub_quantizers {
+            let codebook = self.codebook(piece);
+            codes.push(nearest_centroid(codebook, sub_dimension, &residual[piece * sub_dimension..(piece + 1) * sub_dimension]) as u8);
+        }
+
+        self.lists[list].points.push(self.payloads.len() as u32);
+        self.lists[list].codes.extend(codes);
+        self.payloads.push(point.payload);
+    }
+
+    fn sub_dimension(&self) -> usize {
+        self.dimension / self.config.sub_quantizers
+    }
+
+    fn codebook(&self, piece: usize) -> &[f32] {
+        let size = CODEBOOK_SIZE * self.sub_dimension();
+        &self.codebooks[piece * size..(piece + 1) * size]
+    }
+
+    fn prepare(&self, vector: &[f32]) -> Vec<f32> {
+        // For cosine, every vector is normalized, so that cosine similarity is the same as the dot product, and the clusters are made by direction
+        if self.metric != DistanceMetric::Cosine {
+            return vector.to_vec();
+        }
+        let norm = dot_product(vector, vector).sqrt();
+        if norm == 0.0 {
+            return vector.to_vec();
+        }
+        vector.iter().map(|value| value / norm).collect()
+    }
+
+    fn distance_table(&self, query: &[f32], centroid: &[f32]) -> Vec<f32> {
+        /*
+
+            table[piece * 256 + code] is the part of the score that comes from that piece of a vector with that code. For L2 and cosine this is the squared distance from the piece of the query's residual to the codebook centroid, and for dot products it is the dot product of the piece of the query with the codebook centroid.
+
+            Cosine uses distances too, because the vectors are normalized, so cosine similarity is 1 - distance^2 / 2. The decoded vectors aren't exactly normalized, and ranking them by distance loses less than ranking them by dot product.
+
+        */
+        let sub_dimension = self.sub_dimension();
+        let mut table: Vec<f32> = Vec::with_capacity(self.config.sub_quantizers * CODEBOOK_SIZE);
+        for piece in 0..self.config.sub_quantizers {
+            let range = piece * sub_dimension..(piece + 1) * sub_dimension;
+            let query_piece: Vec<f32> = match self.metric {
+                DistanceMetric::L2 | DistanceMetric::Cosine => query[range.clone()].iter().zip(centroid[range].iter()).map(|(value, center)| value - center).collect(),
+                DistanceMetric::DotProduct => query[range].to_vec(),
+            };
+            for code_centroid in self.codebook(piece).chunks(sub_dimension) {
+                table.push(match self.metric {
+                    DistanceMetric::L2 | DistanceMetric::Cosine => distance::l2_squared(&query_piece, code_centroid),
+                    DistanceMetric::DotProduct => dot_product(&query_piece, code_centroid),
+                });
+            }
+        }
+        table
+    }
+}
+
+impl VectorSearchClient for IvfPqIndex {
+    fn search_filtered(&self, query: &QueryVector, top_k: usize, filter: Option<&PayloadFilter>) -> Vec<VectorSearchResult<'_>> {
+        assert_eq!(query.vec.len(), self.dimension, "query has the wrong number of dimensions");
+        if !self.trained {
+            return Vec::new();
+        }
+
+        let dimension = self.dimension;
+        let sub_quantizers = self.config.sub_quantizers;
+        let query_vec = self.prepare(&query.vec);
+
+        // every cluster, closest centroid first. At least nprobe of them are searched.
+        let centroid_distances = self.coarse_centroids.chunks(dimension).enumerate().map(|(list, centroid)| (list, distance::l2_squared(&query_vec, centroid)));
+        let probe_order = select_top_k(centroid_distances, self.lists.len(), DistanceMetric::L2);
+
+        let mut scores: Vec<(usize, f32)> = Vec::new();
+        for (probed, (list, _)) in probe_order.iter().enumerate() {
+            if probed >= self.config.nprobe && scores.len() >= top_k {
+                break;
+            }
+
+            let centroid = &self.coarse_centroids[list * dimension..(list + 1) * dimension];
+            let table = self.distance_table(&query_vec, centroid);
+
+            // for dot products, the score of a vector is the query's dot product with its centroid plus the dot product with its residual
+            let base = match self.metric {
+                DistanceMetric::L2 | DistanceMetric::Cosine => 0.0,
+                DistanceMetric::DotProduct => dot_product(&query_vec, centroid),
+            };
+
+            let inverted_list = &self.lists[*list];
+            for (point, codes) in inverted_list.points.iter().zip(inverted_list.codes.chunks(sub_quantizers)) {
+                let mut score = base;
+                for (piece, code) in codes.iter().enumerate() {
+                    score += table[piece * CODEBOOK_SIZE + *code as usize];
+                }
+                match self.metric {
+                    DistanceMetric::L2 => score = score.max(0.0).sqrt(),
+                    DistanceMetric::Cosine => score = 1.0 - score / 2.0,
+                    DistanceMetric::DotProduct => {}
+                }
+                if !self.ids.is_deleted(*point as usize) && matches_filter(filter, &self.payloads[*point as usize]) {
+                    scores.push((*point as usize, score));
+                }
+            }
+        }
+
+        select_top_k(scores.into_iter(), top_k, self.metric).into_iter().map(|(index, score)| VectorSearchResult {
+            id: self.ids.id(index),
+            payload: Cow::Borrowed(&self.payloads[index]),
+            score,
+        }).collect()
+    }
+    fn point_count(&self) -> usize {
+        self.ids.live_count()
+    }
+    fn upsert(&mut self, id: PointId, point: PointVector) {
+        assert!(self.trained, "IVF-PQ index must be trained before points are added");
+        assert_eq!(point.vec.len(), self.dimension, "point has the wrong number of dimensions");
+        self.ids.push(id);
+        self.insert(point);
+    }
+    fn delete(&mut self, id: PointId) -> bool {
+        self.ids.delete(id).is_some()
+    }
+    fn tombstone_count(&self) -> usize {
+        self.ids.tombstone_count()
+    }
+    fn compact(&mut self) {
+        // the codes of deleted points are removed from their lists, and the rest of the points are given their new slots
+        if self.ids.tombstone_count() == 0 {
+            return;
+        }
+
+        let sub_quantizers = self.config.sub_quantizers;
+        let new_slots = self.ids.compact();
+        for inverted_list in self.lists.iter_mut() {
+            let mut points: Vec<u32> = Vec::new();
+            let mut codes: Vec<u8> = Vec::new();
+            for (point, point_codes) in inverted_list.points.iter().zip(inverted_list.codes.chunks(sub_quantizers)) {
+                if let Some(new_slot) = new_slots[*point as usize] {
+                    points.push(new_slot as u32);
+                    codes.extend_from_slice(point_codes);
+                }
+            }
+            *inverted_list = InvertedList { points, codes };
+        }
+
+        let payloads = std::mem::take(&mut self.payloads);
+        self.payloads = payloads.into_iter().zip(new_slots.iter())
+            .filter_map(|(payload, new_slot)| new_slot.map(|_| payload))
+            .collect();
+    }
+}
+
+fn nearest_centroid(centroids: &[f32], dimension: usize, vector: &[f32]) -> usize {
+    let mut nearest = 0;
+    let mut nearest_distance = f32::INFINITY;
+    for (index, centroid) in centroids.chunks(dimension).enumerate() {
+        let distance = distance::l2_squared(vector, centroid);
+        if distance < nearest_distance {
+            nearest = index;
+            nearest_distance = distance;
+        }
+    }
+    nearest
+}
+
+fn kmeans(vectors: &[f32], dimension: usize, k: usize, iterations: usize, random: &mut XorShift64) -> Vec<f32> {
+    /*
+
+        Lloyd's k-means. The centroids start at k different random vectors, and a centroid that ends up with no vectors is moved to a random vector.
+
+    */
+    let count = vectors.len() / dimension;
+    let mut next_random = || random.next_u64();
+
+    // a partial Fisher-Yates shuffle picks k different starting vectors
+    let mut order: Vec<usize> = (0..count).collect();
+    for i in 0..k {
+        let j = i + (next_random() % (count - i) as u64) as usize;
+        order.swap(i, j);
+    }
+    let mut centroids: Vec<f32> = Vec::with_capacity(k * dimension);
+    for index in order[..k].iter() {
+        centroids.extend_from_slice(&vectors[index * dimension..(index + 1) * dimension]);
+    }
+
+    let mut assignment: Vec<usize> = vec![0; count];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (index, vector) in vectors.chunks(dimension).enumerate() {
+            let nearest = nearest_centroid(&centroids, dimension, vector);
+            if nearest != assignment[index] {
+                assignment[index] = nearest;
+                changed = true;
+            }
+        }
+
+        let mut sums: Vec<f32> = vec![0.0; k * dimension];
+        let mut sizes: Vec<usize> = vec![0; k];
+        for (index, vector) in vectors.chunks(dimension).enumerate() {
+            let cluster = assignment[index];
+            sizes[cluster] += 1;
+            for (sum, value) in sums[cluster * dimension..(cluster + 1) * dimension].iter_mut().zip(vector.iter()) {
+                *sum += value;
+            }
+        }
+        for cluster in 0..k {
+            let centroid = &mut centroids[cluster * dimension..(cluster + 1) * dimension];
+            if sizes[cluster] == 0 {
+                let index = (next_random() % count as u64) as usize;
+                centroid.copy_from_slice(&vectors[index * dimension..(index + 1) * dimension]);
+                continue;
+            }
+            for (value, sum) in centroid.iter_mut().zip(sums[cluster * dimension..(cluster + 1) * dimension].iter()) {
+                *value = sum / sizes[cluster] as f32;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_search::SimpleSearch;
+    use std::collections::HashSet;
+
+    fn point(id: usize, vector: &[f32]) -> PointVector {
+        PointVector { vec: vector.to_vec(), payload: VectorPayload { page_url: id.to_string(), ..Default::default() } }
+    }
+
+    #[test]
+    fn recall_against_exact_search() {
+        // Like real embeddings, the vectors are made in clusters, and only vary in a few directions within each cluster, instead of being spread evenly
+        let mut random = XorShift64::new(11);
+        let dimension = 32;
+        let top_k = 10;
+        let centers: Vec<Vec<f32>> = (0..20).map(|_| random.vector(dimension)).collect();
+        let directions: Vec<Vec<f32>> = (0..4).map(|_| random.vector(dimension)).collect();
+        let mut clustered_vector = |center: usize| -> Vec<f32> {
+            let offsets: Vec<f32> = directions.iter().map(|_| 0.3 * random.next_signed_f32()).collect();
+            (0..dimension).map(|d| {
+                let variation: f32 = directions.iter().zip(offsets.iter()).map(|(direction, offset)| offset * direction[d]).sum();
+                centers[center % centers.len()][d] + variation + 0.01 * random.next_signed_f32()
+            }).collect()
+        };
+        let points: Vec<Vec<f32>> = (0..2000).map(&mut clustered_vector).collect();
+        let queries: Vec<Vec<f32>> = (0..50).map(|index| clustered_vector(index * 7)).collect();
+
+        for metric in [DistanceMetric::Cosine, DistanceMetric::L2] {
+            let mut exact = SimpleSearch::new(dimension, metric);
+            let mut index = IvfPqIndex::new(dimension, metric, IvfPqConfig { nlist: 16, sub_quantizers: 16, ..Default::default() });
+
+            let sample: Vec<PointVector> = points.iter().step_by(4).enumerate().map(|(id, vector)| point(id, vector)).collect();
+            index.train(&sample);
+
+            for (id, vector) in points.iter().enumerate() {
+                exact.add_point(point(id, vector));
+                index.add_point(point(id, vector));
+            }
+            assert_eq!(index.point_count(), points.len());
+
+            for nprobe in [1, 8, 16] {
+                index.set_nprobe(nprobe);
+
+                let mut found = 0;
+                for query in queries.iter() {
+                    let query = QueryVector { vec: query.clone() };
+                    let expected: HashSet<String> = exact.search(&query, top_k).into_iter().map(|result| result.payload.page_url.clone()).collect();
+                    found += index.search(&query, top_k).into_iter().filter(|result| expected.contains(&result.payload.page_url)).count();
+                }
+                let recall = found as f64 / (queries.len() * top_k) as f64;
+
+                // probing more lists can also bring in more points whose quantized distance is a little off, so the recall doesn't always go up with nprobe
+                if nprobe >= 8 {
+                    assert!(recall >= 0.7, "{:?}: IVF-PQ recall@{} is {:.3} at nprobe {}", metric, top_k, recall, nprobe);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+/*
+
+    This script keeps track of the stable ids of the points in a vector index.
+
+    Each index stores its points in slots, numbered in the order they were added. A point's id stays the same for as long as it is in the index, but its slot can change: upserting a point that is already in the index adds the new version in a new slot, and deleting a point only marks its slot as deleted (a tombstone), which searches skip. The space taken up by tombstones is reclaimed by compacting the index, which moves the remaining points down into the empty slots.
+
+    Points added without an id get one more than the highest id that has ever been used, which is saved with the index, so that the id of a deleted point is never given to a new one. The highest id, u64::MAX, is never used, so that there is always room for one more.
+
+*/
+
+use std::collections::HashMap;
+
+use crate::hash::fnv1a;
+
+pub type PointId = u64;
+
+// the one id that points can't have
+const RESERVED_POINT_ID: PointId = PointId::MAX;
+
+pub fn url_point_id(url: &str) -> PointId {
+    // a hash, so that a url gets the same id every time, and upserting it again replaces it
+    fnv1a(url.as_bytes()).min(RESERVED_POINT_ID - 1)
+}
+
+pub(super) struct PointSlots {
+    // the id of the point in each slot, including deleted ones
+    ids: Vec<PointId>,
+
+    // the slot of every point that hasn't been deleted
+    slots: HashMap<PointId, usize>,
+
+    deleted: Vec<bool>,
+    deleted_count: usize,
+
+    // one more than the highest id that has been used, including by points that have since been deleted
+    next_id: PointId,
+}
+
+impl PointSlots {
+    pub(super) fn new() -> PointSlots {
+        PointSlots {
+            ids: Vec::new(),
+            slots: HashMap::new(),
+            deleted: Vec::new(),
+            deleted_count: 0,
+            next_id: 0,
+        }
+    }
+
+    pub(super) fn from_ids(ids: Vec<PointId>, next_id: PointId) -> PointSlots {
+        // Every slot is live, so every id must be different. next_id is kept from before, so that the ids of deleted points aren't given out again.
+        let mut point_slots = PointSlots::new();
+        point_slots.next_id = next_id;
+        for id in ids.into_iter() {
+            assert!(point_slots.push(id).is_none(), "vector index contains point {} twice", id);
+        }
+        point_slots
+    }
+
+    pub(super) fn next_id(&self) -> PointId {
+        self.next_id
+    }
+
+    pub(super) fn new_id(&self) -> PointId {
+        // the id for a point that is added without one
+        assert!(self.next_id != RESERVED_POINT_ID, "every point id has been used");
+        self.next_id
+    }
+
+    pub(super) fn push(&mut self, id: PointId) -> Option<usize> {
+        /*
+
+            Give a point the next slot. If the point was already in the index, its old slot becomes a tombstone, and is returned.
+
+        */
+        assert!(id != RESERVED_POINT_ID, "point id {} is reserved", id);
+        let slot = self.ids.len();
+        self.ids.push(id);
+        self.deleted.push(false);
+        self.next_id = self.next_id.max(id + 1);
+
+        let old_slot = self.slots.insert(id, slot);
+        if let Some(old_slot) = old_slot {
+            self.deleted[old_slot] = true;
+            self.deleted_count += 1;
+        }
+        old_slot
+    }
+
+    pub(super) fn delete(&mut self, id: PointId) -> Option<usize> {
+        // Returns the slot that the point was in, or None if it wasn't in the index
+        let slot = self.slots.remove(&id)?;
+        self.deleted[slot] = true;
+        self.deleted_count += 1;
+        Some(slot)
+    }
+
+    pub(super) fn is_deleted(&self, slot: usize) -> bool {
+        self.deleted[slot]
+    }
+
+    pub(super) fn id(&self, slot: usize) -> PointId {
+        self.ids[slot]
+    }
+
+    pub(super) fn live_count(&self) -> usize {
+        self.ids.len() - self.deleted_count
+    }
+
+    pub(super) fn tombstone_count(&self) -> usize {
+        self.deleted_count
+    }
+
+    pub(super) fn live_slots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.ids.len()).filter(move |slot| !self.deleted[*slot])
+    }
+
+    pub(super) fn compact(&mut self) -> Vec<Option<usize>> {
+        /*
+
+            Remove every tombstone, keeping the remaining points in the same order. Returns the new slot of every old slot, which is None for the ones that were deleted.
+
+        */
+        let mut new_slots: Vec<Option<usize>> = Vec::with_capacity(self.ids.len());
+        let mut ids: Vec<PointId> = Vec::with_capacity(self.live_count());
+        for (slot, id) in self.ids.iter().enumerate() {
+            if self.deleted[slot] {
+                new_slots.push(None);
+            } else {
+                new_slots.push(Some(ids.len()));
+                ids.push(*id);
+            }
+        }
+
+        *self = PointSlots::from_ids(ids, self.next_id);
+        new_slots
+    }
+}
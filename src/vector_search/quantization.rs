@@ -0,0 +1,535 @@
+/*
+
+    This script contains the quantized search mode of SimpleSearch, which keeps a compact copy of every vector in memory and searches that instead of the full f32 vectors, which stay on disk.
+
+    Two codes are kept for every point:
+
+        int8 codes     every dimension is scaled into 0..255 and stored in one byte, a quarter of the size of an f32. The range of each dimension is found by calibrating on a sample of the points, clipping the few most extreme values so that one outlier doesn't stretch the range for everyone else.
+        binary codes   one bit per dimension, set if the value is above that dimension's mean. These are 32 times smaller than the f32 vectors, and are compared with the Hamming distance (how many bits differ), which is a few instructions for every 64 dimensions.
+
+    A search goes through three steps, each one scoring fewer points more precisely:
+
+        1) if the binary pre-filter is on, every point's binary code is compared with the query's, keeping the binary_oversample x top_k with the smallest Hamming distance
+        2) those candidates (or every point, without the pre-filter) are scored with their int8 codes, keeping rescore_oversample x top_k
+        3) those are rescored against the full precision vectors, and the top_k are returned with exact scores
+
+    Only step 3 reads the full precision vectors, and only a few of them. With rescore_oversample = 0, step 3 is skipped and the int8 scores are returned.
+
+    The codes are kept in memory instead of the full precision vectors: quantizing saves the index, codes and all, and maps it again from the file (see storage.rs), so the vectors are only read from disk for the candidates that are rescored. A quantized index that is loaded is still quantized, with the same codes and config.
+
+    Points that are upserted after quantizing are encoded with the same calibration, so values outside its range are clipped. Their vectors are kept in memory until the index is saved and mapped again, the same as for an index that isn't quantized, and compacting copies every vector into memory, so a large quantized index should be compacted before it is saved rather than while it is being searched.
+
+*/
+
+use std::io::Write;
+
+use super::{select_top_k, DistanceMetric};
+use super::storage::{read_u32, read_u64, VectorBlock};
+
+// the config at the start of the saved codes: binary_prefilter, binary_oversample, rescore_oversample and calibration_sample as u64s, and calibration_quantile as an f32 followed by 4 bytes of padding
+const CONFIG_LEN: usize = 40;
+
+pub struct QuantizationConfig {
+    // Whether the binary codes are used to pick the candidates that are scored with the int8 codes. This makes searches much faster, but can miss points whose binary codes are far from the query's.
+    pub binary_prefilter: bool,
+
+    // How many candidates the binary pre-filter keeps, as a multiple of top_k. Higher gives better recall, but scores more int8 codes.
+    pub binary_oversample: usize,
+
+    // How many of the closest int8 candidates are rescored against the full precision vectors, as a multiple of top_k. 0 returns the int8 scores without rescoring.
+    pub rescore_oversample: usize,
+
+    // How many points are looked at to find the range of every dimension
+    pub calibration_sample: usize,
+
+    // The fraction of values that are clipped at each end of every dimension's range
+    pub calibration_quantile: f32,
+}
+
+impl Default for QuantizationConfig {
+    fn default() -> Self {
+        QuantizationConfig {
+            binary_prefilter: true,
+            binary_oversample: 64,
+            rescore_oversample: 4,
+            calibration_sample: 10_000,
+            calibration_quantile: 0.001,
+        }
+    }
+}
+
+pub struct ScalarQuantizer {
+    /*
+
+        Maps every dimension from its calibrated range onto 0..255, so value = min + scale x code
+
+    */
+    mins: Vec<f32>,
+    scales: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    pub fn calibrate(samples: &[&[f32]], dimension: usize, quantile: f32) -> ScalarQuantizer {
+        assert!(!samples.is_empty(), "quantizing needs at least one point to calibrate on");
+
+        let mut mins: Vec<f32> = Vec::with_capacity(dimension);
+        let mut scales: Vec<f32> = Vec::with_capacity(dimension);
+        let mut values: Vec<f32> = Vec::with_capacity(samples.len());
+        let clipped = ((samples.len() as f32 * quantile) as usize).min((samples.len() - 1) / 2);
+        for dimension_index in 0..dimension {
+            values.clear();
+            values.extend(samples.iter().map(|sample| sample[dimension_index]));
+            values.sort_unstable_by(|a, b| a.total_cmp(b));
+            let min = values[clipped];
+            let max = values[values.len() - 1 - clipped];
+
+            // a dimension that is the same in every sample still needs a scale that isn't zero
+            mins.push(min);
+            scales.push(if max > min { (max - min) / 255.0 } else { 1.0 });
+        }
+        ScalarQuantizer { mins, scales }
+    }
+
+    pub fn encode(&self, vector: &[f32], codes: &mut Vec<u8>) {
+        for ((value, min), scale) in vector.iter().zip(self.mins.iter()).zip(self.scales.iter()) {
+            codes.push(((value - min) / scale).round().clamp(0.0, 255.0) as u8);
+        }
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes.iter().zip(self.mins.iter()).zip(self.scales.iter()).map(|((code, min), scale)| min + scale * *code as f32).collect()
+    }
+}
+
+pub struct BinaryQuantizer {
+    /*
+
+        Sets one bit for every dimension that is above its mean, packed 64 dimensions to a word
+
+    */
+    means: Vec<f32>,
+}
+
+impl BinaryQuantizer {
+    pub fn calibrate(samples: &[&[f32]], dimension: usize) -> BinaryQuantizer {
+        assert!(!samples.is_empty(), "quantizing needs at least one point to calibrate on");
+
+        let mut means: Vec<f32> = vec![0.0; dimension];
+        for sample in samples.iter() {
+            for (mean, value) in means.iter_mut().zip(sample.iter()) {
+                *mean += value;
+            }
+        }
+        means.iter_mut().for_each(|mean| *mean /= samples.len() as f32);
+        BinaryQuantizer { means }
+    }
+
+    pub fn words(&self) -> usize {
+        self.means.len().div_ceil(64)
+    }
+
+    pub fn encode(&self, vector: &[f32], bits: &mut Vec<u64>) {
+        let start = bits.len();
+        bits.resize(start + self.words(), 0);
+        for (dimension_index, (value, mean)) in vector.iter().zip(self.means.iter()).enumerate() {
+            if value > mean {
+                bits[start + dimension_index / 64] |= 1 << (dimension_index % 64);
+            }
+        }
+    }
+}
+
+pub fn hamming_distance(a: &[u64], b: &[u64]) -> u32 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a ^ b).count_ones()).sum()
+}
+
+pub(super) struct QuantizedVectors {
+    /*
+
+        The int8 and binary codes of every slot of a SimpleSearch, in the same order as its vectors
+
+    */
+    config: QuantizationConfig,
+    scalar: ScalarQuantizer,
+    binary: BinaryQuantizer,
+    dimension: usize,
+
+    // slot count x dimension bytes
+    codes: Vec<u8>,
+
+    // the norm of every slot's decoded int8 code, for cosine scores
+    norms: Vec<f32>,
+
+    // slot count x words
+    bits: Vec<u64>,
+}
+
+impl QuantizedVectors {
+    pub(super) fn build(vectors: &VectorBlock, live_slots: &[usize], dimension: usize, config: QuantizationConfig) -> QuantizedVectors {
+        /*
+
+            Calibrate on an evenly spaced sample of the live points, and then encode every slot, including tombstones, so that slots line up with the vectors
+
+        */
+        let step = (live_slots.len() / config.calibration_sample.max(1)).max(1);
+        let samples: Vec<&[f32]> = live_slots.iter().step_by(step).map(|slot| vectors.vector(*slot, dimension)).collect();
+        let scalar = ScalarQuantizer::calibrate(&samples, dimension, config.calibration_quantile);
+        let binary = BinaryQuantizer::calibrate(&samples, dimension);
+
+        let slot_count = vectors.len() / dimension.max(1);
+        let mut quantized = QuantizedVectors {
+            codes: Vec::with_capacity(slot_count * dimension),
+            norms: Vec::with_capacity(slot_count),
+            bits: Vec::with_capacity(slot_count * binary.words()),
+            config,
+            scalar,
+            binary,
+            dimension,
+        };
+        for segment in vectors.segments() {
+            for vector in segment.chunks(dimension.max(1)) {
+                quantized.push(vector);
+            }
+        }
+        quantized
+    }
+
+    pub(super) fn push(&mut self, vector: &[f32]) {
+        let start = self.codes.len();
+        self.scalar.encode(vector, &mut self.codes);
+        let decoded = self.scalar.decode(&self.codes[start..]);
+        self.norms.push(decoded.iter().map(|value| value * value).sum::<f32>().sqrt());
+        self.binary.encode(vector, &mut self.bits);
+    }
+
+    pub(super) fn compact(&mut self, new_slots: &[Option<usize>]) {
+        // the same as compacting the vectors: the live codes are copied down in order
+        let dimension = self.dimension;
+        let words = self.binary.words();
+        let mut live_count = 0;
+        for (old_slot, new_slot) in new_slots.iter().enumerate() {
+            if let Some(new_slot) = *new_slot {
+                if new_slot != old_slot {
+                    self.codes.copy_within(old_slot * dimension..(old_slot + 1) * dimension, new_slot * dimension);
+                    self.bits.copy_within(old_slot * words..(old_slot + 1) * words, new_slot * words);
+                    self.norms[new_slot] = self.norms[old_slot];
+                }
+                live_count += 1;
+            }
+        }
+        self.codes.truncate(live_count * dimension);
+        self.codes.shrink_to_fit();
+        self.bits.truncate(live_count * words);
+        self.bits.shrink_to_fit();
+        self.norms.truncate(live_count);
+        self.norms.shrink_to_fit();
+    }
+
+    pub(super) fn byte_len(dimension: usize, point_count: usize) -> usize {
+        // how many bytes write_to writes for point_count points
+        CONFIG_LEN + 12 * dimension + point_count * (dimension + 4 + 8 * dimension.div_ceil(64))
+    }
+
+    pub(super) fn write_to<W: Write>(&self, writer: &mut W, live_slots: &[usize]) -> std::io::Result<()> {
+        /*
+
+            Write the config, the calibration, and then the int8 codes, norms and binary codes of the live slots, in the order they are given
+
+        */
+        let config = &self.config;
+        for value in [config.binary_prefilter as u64, config.binary_oversample as u64, config.rescore_oversample as u64, config.calibration_sample as u64] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&config.calibration_quantile.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        for value in self.scalar.mins.iter().chain(self.scalar.scales.iter()).chain(self.binary.means.iter()) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        let dimension = self.dimension;
+        let words = self.binary.words();
+        for slot in live_slots.iter() {
+            writer.write_all(&self.codes[slot * dimension..(slot + 1) * dimension])?;
+        }
+        for slot in live_slots.iter() {
+            writer.write_all(&self.norms[*slot].to_le_bytes())?;
+        }
+        for slot in live_slots.iter() {
+            for word in self.bits[slot * words..(slot + 1) * words].iter() {
+                writer.write_all(&word.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn read_from(bytes: &[u8], dimension: usize, point_count: usize) -> QuantizedVectors {
+        /*
+
+            Read codes that write_to wrote into memory
+
+        */
+        assert_eq!(bytes.len(), QuantizedVectors::byte_len(dimension, point_count), "quantized codes are truncated");
+        let read_f32s = |start: usize, count: usize| -> Vec<f32> {
+            (0..count).map(|index| f32::from_bits(read_u32(bytes, start + 4 * index))).collect()
+        };
+
+        let config = QuantizationConfig {
+            binary_prefilter: read_u64(bytes, 0) != 0,
+            binary_oversample: read_u64(bytes, 8) as usize,
+            rescore_oversample: read_u64(bytes, 16) as usize,
+            calibration_sample: read_u64(bytes, 24) as usize,
+            calibration_quantile: f32::from_bits(read_u32(bytes, 32)),
+        };
+        let scalar = ScalarQuantizer { mins: read_f32s(CONFIG_LEN, dimension), scales: read_f32s(CONFIG_LEN + 4 * dimension, dimension) };
+        let binary = BinaryQuantizer { means: read_f32s(CONFIG_LEN + 8 * dimension, dimension) };
+
+        let codes_start = CONFIG_LEN + 12 * dimension;
+        let norms_start = codes_start + point_count * dimension;
+        let bits_start = norms_start + 4 * point_count;
+        let word_count = point_count * binary.words();
+        QuantizedVectors {
+            codes: bytes[codes_start..norms_start].to_vec(),
+            norms: read_f32s(norms_start, point_count),
+            bits: (0..word_count).map(|index| read_u64(bytes, bits_start + 8 * index)).collect(),
+            config,
+            scalar,
+            binary,
+            dimension,
+        }
+    }
+
+    pub(super) fn memory_bytes(&self) -> usize {
+        self.codes.len() + 4 * self.norms.len() + 8 * self.bits.len() + 4 * (self.scalar.mins.len() + self.scalar.scales.len() + self.binary.means.len())
+    }
+
+    pub(super) fn search(&self, query: &[f32], top_k: usize, metric: DistanceMetric, full_vectors: &VectorBlock, keep: &dyn Fn(usize) -> bool) -> Vec<(usize, f32)> {
+        /*
+
+            Find the top_k closest slots that keep accepts, closest first
+
+        */
+        if top_k == 0 {
+            return Vec::new();
+        }
+        let dimension = self.dimension;
+        let slot_count = self.norms.len();
+
+        // 1) binary pre-filter. Lower Hamming distances are closer, the same as L2.
+        let candidates: Vec<usize> = if self.config.binary_prefilter {
+            let words = self.binary.words();
+            let mut query_bits: Vec<u64> = Vec::with_capacity(words);
+            self.binary.encode(query, &mut query_bits);
+            let distances = (0..slot_count).filter(|slot| keep(*slot)).map(|slot| {
+                (slot, hamming_distance(&query_bits, &self.bits[slot * words..(slot + 1) * words]) as f32)
+            });
+            select_top_k(distances, top_k * self.config.binary_oversample.max(1), DistanceMetric::L2).into_iter().map(|(slot, _)| slot).collect()
+        } else {
+            (0..slot_count).filter(|slot| keep(*slot)).collect()
+        };
+
+        // 2) int8 scores. The query is folded into the calibration once, so that each score is one multiply and add per dimension.
+        let scores: Vec<(usize, f32)> = match metric {
+            DistanceMetric::DotProduct | DistanceMetric::Cosine => {
+                let scaled_query: Vec<f32> = query.iter().zip(self.scalar.scales.iter()).map(|(value, scale)| value * scale).collect();
+                let offset: f32 = query.iter().zip(self.scalar.mins.iter()).map(|(value, min)| value * min).sum();
+                let query_norm = query.iter().map(|value| value * value).sum::<f32>().sqrt();
+                candidates.iter().map(|slot| {
+                    let codes = &self.codes[slot * dimension..(slot + 1) * dimension];
+                    let dot = offset + weighted_code_sum(&scaled_query, codes);
+                    let score = if metric == DistanceMetric::DotProduct {
+                        dot
+                    } else if query_norm > 0.0 && self.norms[*slot] > 0.0 {
+                        dot / (query_norm * self.norms[*slot])
+                    } else {
+                        0.0
+                    };
+                    (*slot, score)
+                }).collect()
+            }
+            DistanceMetric::L2 => {
+                let shifted_query: Vec<f32> = query.iter().zip(self.scalar.mins.iter()).map(|(value, min)| value - min).collect();
+                candidates.iter().map(|slot| {
+                    let codes = &self.codes[slot * dimension..(slot + 1) * dimension];
+                    (*slot, code_l2_squared(&shifted_query, &self.scalar.scales, codes).sqrt())
+                }).collect()
+            }
+        };
+
+        if self.config.rescore_oversample == 0 {
+            return select_top_k(scores.into_iter(), top_k, metric);
+        }
+
+        // 3) rescore the closest against the full precision vectors
+        let rescored = select_top_k(scores.into_iter(), top_k * self.config.rescore_oversample, metric).into_iter().map(|(slot, _)| {
+            (slot, metric.score(query, full_vectors.vector(slot, dimension)))
+        });
+        select_top_k(rescored, top_k, metric)
+    }
+}
+
+// The int8 scores are summed in LANES separate accumulators, so that the compiler can keep them in one SIMD register instead of adding every product in order
+const LANES: usize = 8;
+
+fn weighted_code_sum(weights: &[f32], codes: &[u8]) -> f32 {
+    let mut sums = [0.0f32; LANES];
+    let mut weight_chunks = weights.chunks_exact(LANES);
+    let mut code_chunks = codes.chunks_exact(LANES);
+    for (weights, codes) in (&mut weight_chunks).zip(&mut code_chunks) {
+        for lane in 0..LANES {
+            sums[lane] += weights[lane] * codes[lane] as f32;
+        }
+    }
+    let remainder: f32 = weight_chunks.remainder().iter().zip(code_chunks.remainder().iter()).map(|(weight, code)| weight * *code as f32).sum();
+    sums.iter().sum::<f32>() + remainder
+}
+
+fn code_l2_squared(shifted_query: &[f32], scales: &[f32], codes: &[u8]) -> f32 {
+    let mut sums = [0.0f32; LANES];
+    let mut query_chunks = shifted_query.chunks_exact(LANES);
+    let mut scale_chunks = scales.chunks_exact(LANES);
+    let mut code_chunks = codes.chunks_exact(LANES);
+    for ((query, scales), codes) in (&mut query_chunks).zip(&mut scale_chunks).zip(&mut code_chunks) {
+        for lane in 0..LANES {
+            let difference = query[lane] - scales[lane] * codes[lane] as f32;
+            sums[lane] += difference * difference;
+        }
+    }
+    let remainder: f32 = query_chunks.remainder().iter().zip(scale_chunks.remainder().iter()).zip(code_chunks.remainder().iter()).map(|((query, scale), code)| {
+        let difference = query - scale * *code as f32;
+        difference * difference
+    }).sum();
+    sums.iter().sum::<f32>() + remainder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::XorShift64;
+    use crate::test_support::TempPath;
+    use crate::vector_search::{FilterValue, PayloadField, PayloadFilter, PointVector, QueryVector, SimpleSearch, VectorPayload, VectorSearchClient};
+    use std::collections::HashSet;
+
+    fn point(vec: &[f32], host: &str) -> PointVector {
+        PointVector { vec: vec.to_vec(), payload: VectorPayload { host: host.to_string(), ..Default::default() } }
+    }
+
+    #[test]
+    fn quantized_search_finds_most_of_the_exact_results() {
+        // and upserts, deletes, filters and compaction still work after an index is quantized
+        let mut random = XorShift64::new(13);
+        let dimension = 64;
+        let top_k = 10;
+        let points: Vec<Vec<f32>> = (0..5000).map(|_| random.vector(dimension)).collect();
+        let queries: Vec<Vec<f32>> = (0..100).map(|_| random.vector(dimension)).collect();
+        let temp_path = TempPath::new("quantized_search.bin");
+
+        for metric in [DistanceMetric::Cosine, DistanceMetric::DotProduct, DistanceMetric::L2] {
+            let mut exact = SimpleSearch::new(dimension, metric);
+            let mut index = SimpleSearch::new(dimension, metric);
+            for (id, vector) in points.iter().enumerate() {
+                let host = if id % 2 == 0 { "even.com" } else { "odd.com" };
+                exact.upsert(id as u64, point(vector, host));
+                index.upsert(id as u64, point(vector, host));
+            }
+
+            let configs = [
+                ("int8", QuantizationConfig { binary_prefilter: false, rescore_oversample: 0, ..Default::default() }),
+                ("int8, rescored", QuantizationConfig { binary_prefilter: false, ..Default::default() }),
+                ("binary x64 + int8, rescored", QuantizationConfig::default()),
+            ];
+            for (name, config) in configs {
+                index.quantize(config, temp_path.path());
+                assert!(matches!(&index.vectors, VectorBlock::Mapped { appended, .. } if appended.is_empty()), "the vectors should only be in the index file");
+
+                let mut found = 0;
+                for query in queries.iter() {
+                    let query = QueryVector { vec: query.clone() };
+                    let expected: HashSet<u64> = exact.search(&query, top_k).into_iter().map(|result| result.id).collect();
+                    found += index.search(&query, top_k).into_iter().filter(|result| expected.contains(&result.id)).count();
+                }
+                let recall = found as f64 / (queries.len() * top_k) as f64;
+                assert!(recall >= 0.85, "{:?}, {}: recall@{} is {:.3}", metric, name, top_k, recall);
+            }
+            assert!(index.quantized_memory_bytes().unwrap() < index.vector_bytes() / 2, "the codes should be much smaller than the vectors");
+
+            // points that are upserted after quantizing are encoded with the same calibration
+            let query = QueryVector { vec: queries[0].clone() };
+            exact.upsert(999_999, point(&queries[0], "odd.com"));
+            index.upsert(999_999, point(&queries[0], "odd.com"));
+            assert_eq!(index.search(&query, 1)[0].id, 999_999, "an upserted point should be found by the quantized search");
+
+            // deleted points are never returned, and compaction keeps the codes in line with the vectors
+            for id in (0..points.len() as u64).step_by(3) {
+                exact.delete(id);
+                index.delete(id);
+            }
+            exact.delete(999_999);
+            index.delete(999_999);
+            index.compact();
+            assert_eq!(index.tombstone_count(), 0);
+            let odd = PayloadFilter::Equals(PayloadField::Host, FilterValue::Text("odd.com".to_string()));
+            let mut found = 0;
+            for query in queries.iter() {
+                let query = QueryVector { vec: query.clone() };
+                let expected: HashSet<u64> = exact.search_filtered(&query, top_k, Some(&odd)).into_iter().map(|result| result.id).collect();
+                let results = index.search_filtered(&query, top_k, Some(&odd));
+                assert_eq!(results.len(), top_k);
+                assert!(results.iter().all(|result| !result.id.is_multiple_of(3) && result.payload.host == "odd.com"), "deleted or filtered out point was returned");
+                found += results.into_iter().filter(|result| expected.contains(&result.id)).count();
+            }
+            assert!(found as f64 / (queries.len() * top_k) as f64 >= 0.85, "{:?}: quantized recall after compacting is too low", metric);
+        }
+    }
+
+    #[test]
+    fn the_codes_are_saved_with_the_index() {
+        // a loaded index searches the same codes with the same config, without quantizing it again
+        let mut random = XorShift64::new(14);
+        let dimension = 70;
+        let temp_path = TempPath::new("quantized_index.bin");
+        let saved_path = TempPath::new("quantized_index_saved.bin");
+        let mut index = SimpleSearch::new(dimension, DistanceMetric::Cosine);
+        for id in 0..1000 {
+            index.upsert(id, point(&random.vector(dimension), "example.com"));
+        }
+        index.delete(5);
+        let config = QuantizationConfig { binary_oversample: 8, rescore_oversample: 0, ..Default::default() };
+        index.quantize(config, temp_path.path());
+
+        // points upserted and deleted since quantizing are saved too
+        index.upsert(1000, point(&random.vector(dimension), "example.com"));
+        index.delete(6);
+        index.save(saved_path.path());
+        let loaded = SimpleSearch::load_mmap(saved_path.path());
+        assert_eq!(loaded.quantized_memory_bytes(), index.quantized_memory_bytes().map(|bytes| bytes - dimension - 4 - 16));
+        let loaded_quantized = loaded.quantized.as_ref().unwrap();
+        assert!(loaded_quantized.config.binary_prefilter);
+        assert_eq!((loaded_quantized.config.binary_oversample, loaded_quantized.config.rescore_oversample), (8, 0));
+        for _ in 0..20 {
+            let query = QueryVector { vec: random.vector(dimension) };
+            let expected: Vec<(u64, f32)> = index.search(&query, 10).into_iter().map(|result| (result.id, result.score)).collect();
+            let results: Vec<(u64, f32)> = loaded.search(&query, 10).into_iter().map(|result| (result.id, result.score)).collect();
+            assert_eq!(results, expected);
+        }
+
+        // and an index that isn't quantized any more is saved without them
+        let mut unquantized = SimpleSearch::load_mmap(saved_path.path());
+        unquantized.remove_quantization();
+        unquantized.save(temp_path.path());
+        assert_eq!(SimpleSearch::load_mmap(temp_path.path()).quantized_memory_bytes(), None);
+    }
+
+    #[test]
+    fn an_empty_index_is_left_unquantized() {
+        let temp_path = TempPath::new("quantized_empty_index.bin");
+        let mut index = SimpleSearch::new(8, DistanceMetric::Cosine);
+        index.quantize(QuantizationConfig::default(), temp_path.path());
+        assert_eq!(index.quantized_memory_bytes(), None);
+        assert!(!std::path::Path::new(temp_path.path()).exists());
+        assert!(index.search(&QueryVector { vec: vec![1.0; 8] }, 10).is_empty());
+
+        // so points that are added later are searched exactly, until it is quantized again
+        index.upsert(1, point(&[1.0; 8], "example.com"));
+        assert_eq!(index.search(&QueryVector { vec: vec![1.0; 8] }, 10)[0].id, 1);
+    }
+}
@@ -0,0 +1,379 @@
+/*
+
+    This script contains the on-disk format of the vector index, and the storage that lets SimpleSearch work straight from a memory mapped file.
+
+    The vectors are kept in one contiguous block of f32s, in the same layout in memory as on disk, so a saved index can be memory mapped and searched right away without reading the vectors in first. The payloads are stored after them, each one encoded with bincode, so that payloads can get new fields without changing the layout of the vectors. Only the payloads of the results are decoded. All of the numbers are little endian.
+
+        header:           magic (8 bytes), version: u32, metric: u32, dimension: u32, padding: u32, point_count: u64, vectors_start: u64, payload_offsets_start: u64, payload_bytes_start: u64, ids_start: u64, next_id: u64, quantized_start: u64, and then zeros up to 128 bytes
+        vectors:          point_count x dimension x f32, starting 128 bytes in so that the block is aligned
+        payload_offsets:  (point_count + 1) x u64   the payload of point i is payload_bytes[payload_offsets[i]..payload_offsets[i+1]]
+        payload_bytes:    the bincode encoding of every payload
+        ids:              point_count x u64   the id of every point
+        quantized:        only if quantized_start isn't 0, the codes of a quantized index (see quantization.rs):
+                              config: binary_prefilter: u64, binary_oversample: u64, rescore_oversample: u64, calibration_sample: u64, calibration_quantile: f32, padding: u32
+                              calibration: dimension x f32 mins, dimension x f32 scales, dimension x f32 means
+                              codes: point_count x dimension x u8, norms: point_count x f32, bits: point_count x ceil(dimension / 64) x u64
+
+    The codes of a quantized index are read into memory when it is loaded, since they are what every search scores, while its vectors stay mapped. Deleted points aren't saved, so a saved index never has tombstones. next_id is saved instead, so that points added after loading don't get the id of a point that was deleted. Only indexes with the current version can be loaded; an index saved with an older format has to be rebuilt.
+
+    A loaded index stays mapped when points are upserted into it: the new vectors and payloads are kept in memory after the mapped ones until the index is saved again. Compacting moves points, so it copies the vectors into memory and decodes every payload; compact before saving rather than on a large mapped index that is only being searched.
+
+    Saving streams the index into a temporary file next to the index, without building the whole file in memory, flushes it to disk, and then renames it over the old index, so a crash while saving leaves either the old index or the new one, never half of one.
+
+*/
+
+use bincode::config;
+use bincode::enc::write::SizeWriter;
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+use super::{SimpleSearch, DistanceMetric, PointSlots, VectorPayload};
+use super::quantization::QuantizedVectors;
+use crate::atomic_file::write_atomic_with;
+
+const MAGIC: &[u8; 8] = b"BALVECTR";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 128;
+
+pub(super) enum VectorBlock {
+    Owned(Vec<f32>),
+
+    // the saved vectors are float_count f32s, starting at byte start of the file, and the vectors upserted since the index was loaded are kept in memory after them
+    Mapped { mmap: Arc<Mmap>, start: usize, float_count: usize, appended: Vec<f32> },
+}
+
+impl VectorBlock {
+    pub(super) fn segments(&self) -> [&[f32]; 2] {
+        // every vector in slot order, as the saved vectors and then the ones upserted since
+        match self {
+            VectorBlock::Owned(vectors) => [vectors, &[]],
+            VectorBlock::Mapped { mmap, start, float_count, appended } => {
+                // Safety: load_mmap checked that the block is inside of the file and aligned for f32, and the file is little endian like the CPU
+                let saved = unsafe { std::slice::from_raw_parts(mmap.as_ptr().add(*start) as *const f32, *float_count) };
+                [saved, appended]
+            }
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        // the number of f32s, not the number of vectors
+        self.segments().iter().map(|segment| segment.len()).sum()
+    }
+
+    pub(super) fn vector(&self, slot: usize, dimension: usize) -> &[f32] {
+        let [saved, appended] = self.segments();
+        let start = slot * dimension;
+        if start < saved.len() {
+            &saved[start..start + dimension]
+        } else {
+            &appended[start - saved.len()..start - saved.len() + dimension]
+        }
+    }
+
+    pub(super) fn push(&mut self, vector: &[f32]) {
+        // upserts only ever add to the end, so a mapped block stays mapped
+        match self {
+            VectorBlock::Owned(vectors) => vectors.extend_from_slice(vector),
+            VectorBlock::Mapped { appended, .. } => appended.extend_from_slice(vector),
+        }
+    }
+
+    pub(super) fn to_mut(&mut self) -> &mut Vec<f32> {
+        // A mapped block is copied into memory when points have to be moved, which only compacting does
+        if let VectorBlock::Mapped { .. } = self {
+            *self = VectorBlock::Owned(self.segments().concat());
+        }
+        match self {
+            VectorBlock::Owned(vectors) => vectors,
+            VectorBlock::Mapped { .. } => unreachable!(),
+        }
+    }
+}
+
+pub(super) enum PayloadStore {
+    Owned(Vec<VectorPayload>),
+
+    // the count saved payloads are read from the file, and the payloads upserted since the index was loaded are kept in memory after them
+    Mapped { mmap: Arc<Mmap>, offsets_start: usize, bytes_start: usize, count: usize, appended: Vec<VectorPayload> },
+}
+
+impl PayloadStore {
+    pub(super) fn len(&self) -> usize {
+        match self {
+            PayloadStore::Owned(payloads) => payloads.len(),
+            PayloadStore::Mapped { count, appended, .. } => count + appended.len(),
+        }
+    }
+
+    pub(super) fn get(&self, index: usize) -> Cow<'_, VectorPayload> {
+        match self {
+            PayloadStore::Owned(payloads) => Cow::Borrowed(&payloads[index]),
+            PayloadStore::Mapped { count, appended, .. } if index >= *count => Cow::Borrowed(&appended[index - count]),
+            PayloadStore::Mapped { mmap, offsets_start, bytes_start, .. } => {
+                let start = bytes_start + read_u64(mmap, offsets_start + 8 * index) as usize;
+                let end = bytes_start + read_u64(mmap, offsets_start + 8 * (index + 1)) as usize;
+                let (payload, _) : (VectorPayload, usize) = bincode::decode_from_slice(&mmap[start..end], config::standard()).expect("Unable to decode vector payload");
+                Cow::Owned(payload)
+            }
+        }
+    }
+
+    pub(super) fn push(&mut self, payload: VectorPayload) {
+        match self {
+            PayloadStore::Owned(payloads) => payloads.push(payload),
+            PayloadStore::Mapped { appended, .. } => appended.push(payload),
+        }
+    }
+
+    pub(super) fn to_mut(&mut self) -> &mut Vec<VectorPayload> {
+        // Mapped payloads are all decoded when points have to be moved, which only compacting does
+        if let PayloadStore::Mapped { .. } = self {
+            let payloads: Vec<VectorPayload> = (0..self.len()).map(|index| self.get(index).into_owned()).collect();
+            *self = PayloadStore::Owned(payloads);
+        }
+        match self {
+            PayloadStore::Owned(payloads) => payloads,
+            PayloadStore::Mapped { .. } => unreachable!(),
+        }
+    }
+}
+
+fn metric_to_u32(metric: DistanceMetric) -> u32 {
+    match metric {
+        DistanceMetric::DotProduct => 0,
+        DistanceMetric::Cosine => 1,
+        DistanceMetric::L2 => 2,
+    }
+}
+
+fn metric_from_u32(metric: u32) -> DistanceMetric {
+    match metric {
+        0 => DistanceMetric::DotProduct,
+        1 => DistanceMetric::Cosine,
+        2 => DistanceMetric::L2,
+        _ => panic!("vector index has an unknown metric {}", metric),
+    }
+}
+
+impl SimpleSearch {
+    pub fn save(&self, index_path: &str) {
+        /*
+
+            Write the index to disk, replacing any index that is already there
+
+        */
+        let live_slots: Vec<usize> = self.ids.live_slots().collect();
+        let point_count = live_slots.len();
+
+        // The payload offsets come before the payloads, so the size of every encoded payload is worked out first, without keeping the encodings
+        let mut payload_offsets: Vec<u64> = Vec::with_capacity(point_count + 1);
+        let mut payload_bytes_len: u64 = 0;
+        for slot in live_slots.iter() {
+            payload_offsets.push(payload_bytes_len);
+            let mut size_writer = SizeWriter::default();
+            bincode::encode_into_writer(&*self.payloads.get(*slot), &mut size_writer, config::standard()).unwrap();
+            payload_bytes_len += size_writer.bytes_written as u64;
+        }
+        payload_offsets.push(payload_bytes_len);
+
+        let vectors_start = HEADER_LEN;
+        let payload_offsets_start = vectors_start + 4 * self.dimension * point_count;
+        let payload_bytes_start = payload_offsets_start + 8 * payload_offsets.len();
+        let ids_start = payload_bytes_start + payload_bytes_len as usize;
+        let quantized_start = if self.quantized.is_some() { ids_start + 8 * point_count } else { 0 };
+
+        write_atomic_with(index_path, |writer| {
+            let mut header: Vec<u8> = Vec::with_capacity(HEADER_LEN);
+            header.extend_from_slice(MAGIC);
+            header.extend_from_slice(&VERSION.to_le_bytes());
+            header.extend_from_slice(&metric_to_u32(self.metric).to_le_bytes());
+            header.extend_from_slice(&(self.dimension as u32).to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(&(point_count as u64).to_le_bytes());
+            header.extend_from_slice(&(vectors_start as u64).to_le_bytes());
+            header.extend_from_slice(&(payload_offsets_start as u64).to_le_bytes());
+            header.extend_from_slice(&(payload_bytes_start as u64).to_le_bytes());
+            header.extend_from_slice(&(ids_start as u64).to_le_bytes());
+            header.extend_from_slice(&self.ids.next_id().to_le_bytes());
+            header.extend_from_slice(&(quantized_start as u64).to_le_bytes());
+            header.resize(HEADER_LEN, 0);
+            writer.write_all(&header)?;
+
+            for slot in live_slots.iter() {
+                for value in self.vectors.vector(*slot, self.dimension).iter() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            for offset in payload_offsets.iter() {
+                writer.write_all(&offset.to_le_bytes())?;
+            }
+            for slot in live_slots.iter() {
+                bincode::encode_into_std_write(&*self.payloads.get(*slot), writer, config::standard()).map_err(std::io::Error::other)?;
+            }
+            for slot in live_slots.iter() {
+                writer.write_all(&self.ids.id(*slot).to_le_bytes())?;
+            }
+            if let Some(quantized) = &self.quantized {
+                quantized.write_to(writer, &live_slots)?;
+            }
+            Ok(())
+        });
+
+        println!("Vector index written to disk.")
+    }
+
+    pub fn load_mmap(index_path: &str) -> SimpleSearch {
+        /*
+
+            Memory map a saved index. Nothing is read until it is searched, so this returns right away, even for very large indexes.
+
+        */
+        if cfg!(target_endian = "big") {
+            panic!("vector indexes can only be memory mapped on little endian CPUs");
+        }
+
+        let file = File::open(index_path).expect("Unable to open vector index");
+
+        // The file must not be modified while it is mapped. Saving replaces the file instead of writing over it, so this stays valid even if the index is saved again.
+        let mmap = unsafe { Mmap::map(&file) }.expect("Unable to memory map vector index");
+
+        assert!(mmap.len() >= HEADER_LEN, "vector index is too short to contain a header");
+        assert_eq!(&mmap[0..8], MAGIC, "not a vector index");
+        let version = read_u32(&mmap, 8);
+        assert_eq!(version, VERSION, "unsupported vector index version {}", version);
+
+        let metric = metric_from_u32(read_u32(&mmap, 12));
+        let dimension = read_u32(&mmap, 16) as usize;
+        let point_count = read_u64(&mmap, 24) as usize;
+        let vectors_start = read_u64(&mmap, 32) as usize;
+        let payload_offsets_start = read_u64(&mmap, 40) as usize;
+        let payload_bytes_start = read_u64(&mmap, 48) as usize;
+
+        assert!(vectors_start.is_multiple_of(4) && (mmap.as_ptr() as usize).is_multiple_of(4), "vector block is not aligned");
+        assert!(payload_offsets_start == vectors_start + 4 * dimension * point_count, "vector index is corrupted");
+        assert!(payload_bytes_start == payload_offsets_start + 8 * (point_count + 1) && payload_bytes_start <= mmap.len(), "vector index is truncated");
+
+        // The ids are read in up front, since looking a point up by its id needs a map of them anyway
+        let ids_start = read_u64(&mmap, 56) as usize;
+        assert!(ids_start >= payload_bytes_start && ids_start + 8 * point_count <= mmap.len(), "vector index is truncated");
+        let ids: Vec<u64> = (0..point_count).map(|index| read_u64(&mmap, ids_start + 8 * index)).collect();
+        let next_id = read_u64(&mmap, 64);
+
+        // Old indexes have zeros here, so they load without codes
+        let quantized_start = read_u64(&mmap, 72) as usize;
+        let quantized = (quantized_start != 0).then(|| {
+            assert!(quantized_start == ids_start + 8 * point_count && quantized_start <= mmap.len(), "vector index is corrupted");
+            QuantizedVectors::read_from(&mmap[quantized_start..], dimension, point_count)
+        });
+
+        let mmap = Arc::new(mmap);
+        SimpleSearch {
+            vectors: VectorBlock::Mapped { mmap: mmap.clone(), start: vectors_start, float_count: dimension * point_count, appended: Vec::new() },
+            payloads: PayloadStore::Mapped { mmap, offsets_start: payload_offsets_start, bytes_start: payload_bytes_start, count: point_count, appended: Vec::new() },
+            ids: PointSlots::from_ids(ids, next_id),
+            quantized,
+            metric,
+            dimension,
+        }
+    }
+}
+
+pub(super) fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+pub(super) fn read_u64(bytes: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(bytes[position..position + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::random::XorShift64;
+    use crate::test_support::TempPath;
+    use crate::vector_search::{PointVector, QueryVector, VectorSearchClient};
+
+    fn point(page_url: String, vec: Vec<f32>) -> PointVector {
+        PointVector { vec, payload: VectorPayload { page_url, ..Default::default() } }
+    }
+
+    #[test]
+    fn mapped_index_matches_the_saved_one() {
+        // a memory mapped index should give the same results as the index it was saved from, for every metric
+        let mut random = XorShift64::new(5);
+        let dimension = 24;
+        let temp_path = TempPath::new("vector_index_persistence.bin");
+        let queries: Vec<Vec<f32>> = (0..20).map(|_| random.vector(dimension)).collect();
+
+        for metric in [DistanceMetric::DotProduct, DistanceMetric::Cosine, DistanceMetric::L2] {
+            let mut index = SimpleSearch::new(dimension, metric);
+            for id in 0..3000 {
+                index.add_point(point(format!("https://wikipedia.org/wiki/{}", id), random.vector(dimension)));
+            }
+            index.save(temp_path.path());
+
+            let loaded = SimpleSearch::load_mmap(temp_path.path());
+            assert_eq!(loaded.point_count(), index.point_count());
+            assert_eq!(loaded.metric(), metric);
+            for query in queries.iter() {
+                let query = QueryVector { vec: query.clone() };
+                let expected = index.search(&query, 10);
+                let results = loaded.search(&query, 10);
+                assert_eq!(results.len(), expected.len());
+                for (result, expected) in results.iter().zip(expected.iter()) {
+                    assert_eq!(result.payload, expected.payload);
+                    assert_eq!(result.score, expected.score);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn upserts_stay_mapped_and_leave_the_file_alone() {
+        let mut random = XorShift64::new(6);
+        let dimension = 16;
+        let temp_path = TempPath::new("vector_index_mapped_upserts.bin");
+        let mut index = SimpleSearch::new(dimension, DistanceMetric::DotProduct);
+        for id in 0..2000 {
+            index.add_point(point(id.to_string(), random.vector(dimension)));
+        }
+        index.save(temp_path.path());
+
+        let mut loaded = SimpleSearch::load_mmap(temp_path.path());
+        let vec: Vec<f32> = random.vector(dimension).iter().map(|value| value * 100.0).collect();
+        loaded.add_point(point("new".to_string(), vec.clone()));
+        assert!(matches!(loaded.vectors, VectorBlock::Mapped { .. }));
+        assert!(matches!(loaded.payloads, PayloadStore::Mapped { .. }));
+        assert_eq!(loaded.point_count(), index.point_count() + 1);
+        assert_eq!(loaded.search(&QueryVector { vec }, 1)[0].payload.page_url, "new");
+        assert_eq!(SimpleSearch::load_mmap(temp_path.path()).point_count(), index.point_count());
+
+        // saving writes the upserted point after the mapped ones
+        let saved_path = TempPath::new("vector_index_mapped_upserts_saved.bin");
+        loaded.save(saved_path.path());
+        let reloaded = SimpleSearch::load_mmap(saved_path.path());
+        assert_eq!(reloaded.point_count(), loaded.point_count());
+        let query = QueryVector { vec: random.vector(dimension) };
+        let expected: Vec<String> = loaded.search(&query, 10).into_iter().map(|result| result.payload.page_url.clone()).collect();
+        let results: Vec<String> = reloaded.search(&query, 10).into_iter().map(|result| result.payload.page_url.clone()).collect();
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported vector index version")]
+    fn other_versions_are_refused() {
+        let temp_path = TempPath::new("vector_index_version.bin");
+        let mut index = SimpleSearch::new(4, DistanceMetric::L2);
+        index.add_point(point("a".to_string(), vec![1.0, 2.0, 3.0, 4.0]));
+        index.save(temp_path.path());
+
+        let mut bytes = fs::read(temp_path.path()).unwrap();
+        bytes[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(temp_path.path(), &bytes).unwrap();
+        SimpleSearch::load_mmap(temp_path.path());
+    }
+}
@@ -0,0 +1,452 @@
+/*
+
+    This script contains the write-ahead log of the vector index, so that upserts and deletes aren't lost if the process dies before the index is saved.
+
+    Every change is appended to the log before it is applied to the index, and the log is flushed to disk according to its FsyncPolicy. Saving the index is a checkpoint: the index is saved as a snapshot (which replaces the old one atomically, see storage.rs), and then the log is emptied. Opening the index loads the last snapshot and replays the log on top of it.
+
+    Replaying a change twice gives the same index as replaying it once (an upsert replaces the point with its id, and deleting a deleted point does nothing), so a crash between saving the snapshot and emptying the log only means that some changes are replayed again.
+
+    The log is a header followed by records. All of the numbers are little endian.
+
+        header:   magic (8 bytes), version: u32, dimension: u32
+        record:   body_length: u32, checksum: u32 (the low 32 bits of the FNV-1a hash of the body), body
+        body:     kind: u8 (1 = upsert, 2 = delete), id: u64, and for upserts the vector (dimension x f32) followed by the bincode encoding of the payload
+
+    A crash while a record is being written leaves a record that is cut off or doesn't match its checksum at the end of the log. Replaying stops at the first such record and cuts the log off there, so that new records are written after the last complete one.
+
+    Only one process can have an index open at a time, so that the crawler and the embedding server can't both write to the same index. Opening the log takes an advisory lock on it, which is let go of when it is closed, and the log is opened before the snapshot is loaded. The lock is on the log rather than on the snapshot, since saving replaces the snapshot with a new file, but the log stays the same file for as long as the index exists.
+
+*/
+
+use bincode::config;
+use std::env;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{SimpleSearch, DistanceMetric, PayloadFilter, PointId, PointVector, QueryVector, VectorSearchClient, VectorSearchResult};
+use crate::hash::fnv1a;
+
+const MAGIC: &[u8; 8] = b"BALVWAL1";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 8;
+
+const UPSERT: u8 = 1;
+const DELETE: u8 = 2;
+
+// If this environment variable is set to "always", "never" or a number of records, it is the fsync policy of the embedding server and the crawler's logs
+pub const VECTOR_WAL_FSYNC_VAR: &str = "VECTOR_WAL_FSYNC";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    // Flush every record to disk before the change is applied, so that no acknowledged change is ever lost. This is the slowest.
+    Always,
+
+    // Flush after every n records, so at most n changes are lost if the machine crashes. Changes still reach the OS right away, so they survive the process dying.
+    EveryN(usize),
+
+    // Leave flushing to the OS, and only flush when the log is checkpointed
+    Never,
+}
+
+impl FsyncPolicy {
+    pub fn parse(policy: &str) -> Option<FsyncPolicy> {
+        match policy.trim().to_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "never" => Some(FsyncPolicy::Never),
+            records => records.parse::<usize>().ok().filter(|records| *records > 0).map(FsyncPolicy::EveryN),
+        }
+    }
+
+    pub fn from_env() -> FsyncPolicy {
+        // the policy in VECTOR_WAL_FSYNC, or Always if it isn't set
+        match env::var(VECTOR_WAL_FSYNC_VAR) {
+            Ok(policy) => FsyncPolicy::parse(&policy).expect("VECTOR_WAL_FSYNC must be always, never or a number of records"),
+            Err(_) => FsyncPolicy::Always,
+        }
+    }
+}
+
+pub enum WalRecord {
+    Upsert(PointId, PointVector),
+    Delete(PointId),
+}
+
+pub struct WriteAheadLog {
+    file: File,
+    dimension: usize,
+    policy: FsyncPolicy,
+
+    // records written since the log was last flushed to disk
+    unsynced: usize,
+
+    // records in the log, including the ones that were replayed
+    record_count: usize,
+}
+
+impl WriteAheadLog {
+    pub fn open(log_path: &str, dimension: usize, policy: FsyncPolicy) -> (WriteAheadLog, Vec<WalRecord>) {
+        /*
+
+            Open the log, creating it if it doesn't exist, and return the records that are in it, in the order they were written
+
+        */
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(log_path).expect("Unable to open vector index log");
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => panic!("{} is already open in another process, like the crawler or the embedding server", log_path),
+            Err(TryLockError::Error(err)) => panic!("Unable to lock vector index log: {}", err),
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        file.read_to_end(&mut bytes).expect("Unable to read vector index log");
+
+        // a log that was created but whose header never made it to disk is the same as an empty one
+        let mut records: Vec<WalRecord> = Vec::new();
+        let mut valid_len = HEADER_LEN;
+        if bytes.len() < HEADER_LEN {
+            let mut header: Vec<u8> = Vec::with_capacity(HEADER_LEN);
+            header.extend_from_slice(MAGIC);
+            header.extend_from_slice(&VERSION.to_le_bytes());
+            header.extend_from_slice(&(dimension as u32).to_le_bytes());
+            file.set_len(0).expect("Unable to truncate vector index log");
+            file.seek(SeekFrom::Start(0)).expect("Unable to seek in vector index log");
+            file.write_all(&header).expect("Unable to write vector index log");
+            file.sync_all().expect("Unable to flush vector index log to disk");
+        } else {
+            assert_eq!(&bytes[0..8], MAGIC, "not a vector index log");
+            let version = read_u32(&bytes, 8);
+            assert!(version <= VERSION, "unsupported vector index log version {}", version);
+            assert_eq!(read_u32(&bytes, 12) as usize, dimension, "the vector index log has a different number of dimensions than the index");
+
+            while let Some((record, record_len)) = decode_record(&bytes[valid_len..], dimension) {
+                records.push(record);
+                valid_len += record_len;
+            }
+            if valid_len < bytes.len() {
+                println!("Vector index log has {} bytes of an unfinished record at the end, which were dropped", bytes.len() - valid_len);
+                file.set_len(valid_len as u64).expect("Unable to truncate vector index log");
+                file.sync_all().expect("Unable to flush vector index log to disk");
+            }
+        }
+        file.seek(SeekFrom::Start(valid_len as u64)).expect("Unable to seek in vector index log");
+
+        let record_count = records.len();
+        (WriteAheadLog { file, dimension, policy, unsynced: 0, record_count }, records)
+    }
+
+    pub fn append_upsert(&mut self, id: PointId, point: &PointVector) {
+        assert_eq!(point.vec.len(), self.dimension, "point has the wrong number of dimensions");
+        let mut body: Vec<u8> = Vec::with_capacity(9 + 4 * self.dimension);
+        body.push(UPSERT);
+        body.extend_from_slice(&id.to_le_bytes());
+        for value in point.vec.iter() {
+            body.extend_from_slice(&value.to_le_bytes());
+        }
+        body.extend_from_slice(&bincode::encode_to_vec(&point.payload, config::standard()).unwrap());
+        self.append(&body);
+    }
+
+    pub fn append_delete(&mut self, id: PointId) {
+        let mut body: Vec<u8> = Vec::with_capacity(9);
+        body.push(DELETE);
+        body.extend_from_slice(&id.to_le_bytes());
+        self.append(&body);
+    }
+
+    fn append(&mut self, body: &[u8]) {
+        let mut bytes: Vec<u8> = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&checksum(body).to_le_bytes());
+        bytes.extend_from_slice(body);
+
+        // one write for the whole record, so that a crash cuts off at most the last record
+        self.file.write_all(&bytes).expect("Unable to write vector index log");
+        self.record_count += 1;
+        self.unsynced += 1;
+        match self.policy {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::EveryN(records) if self.unsynced >= records => self.sync(),
+            _ => {}
+        }
+    }
+
+    pub fn sync(&mut self) {
+        // only the data needs to be flushed, not the file's modification time
+        self.file.sync_data().expect("Unable to flush vector index log to disk");
+        self.unsynced = 0;
+    }
+
+    pub fn truncate(&mut self) {
+        // drop every record, once the changes they hold have been saved in a snapshot
+        self.file.set_len(HEADER_LEN as u64).expect("Unable to truncate vector index log");
+        self.file.seek(SeekFrom::Start(HEADER_LEN as u64)).expect("Unable to seek in vector index log");
+        self.file.sync_all().expect("Unable to flush vector index log to disk");
+        self.unsynced = 0;
+        self.record_count = 0;
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+}
+
+fn decode_record(bytes: &[u8], dimension: usize) -> Option<(WalRecord, usize)> {
+    /*
+
+        Decode the record at the start of bytes, returning it and its length, or None if it is cut off or corrupted
+
+    */
+    if bytes.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    let body_len = read_u32(bytes, 0) as usize;
+    let body = bytes.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + body_len)?;
+    if body.len() < 9 || checksum(body) != read_u32(bytes, 4) {
+        return None;
+    }
+
+    let id = read_u64(body, 1);
+    let record = match body[0] {
+        UPSERT => {
+            let payload_start = 9 + 4 * dimension;
+            let vector_bytes = body.get(9..payload_start)?;
+            let vec: Vec<f32> = vector_bytes.chunks_exact(4).map(|value| f32::from_le_bytes(value.try_into().unwrap())).collect();
+            let (payload, _) = bincode::decode_from_slice(&body[payload_start..], config::standard()).ok()?;
+            WalRecord::Upsert(id, PointVector { vec, payload })
+        }
+        DELETE => WalRecord::Delete(id),
+        _ => return None,
+    };
+    Some((record, RECORD_HEADER_LEN + body_len))
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    // the low 32 bits of the FNV-1a hash
+    fnv1a(bytes) as u32
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], position: usize) -> u64 {
+    u64::from_le_bytes(bytes[position..position + 8].try_into().unwrap())
+}
+
+pub struct DurableIndex {
+    /*
+
+        A SimpleSearch whose changes are written to a log before they are applied, so that opening it again after a crash gets back every logged change
+
+    */
+    index: SimpleSearch,
+    log: WriteAheadLog,
+    index_path: String,
+}
+
+impl DurableIndex {
+    pub fn open(index_path: &str, dimension: usize, metric: DistanceMetric, policy: FsyncPolicy) -> DurableIndex {
+        /*
+
+            Load the last snapshot at index_path, or start a new index if there isn't one, and replay the log (index_path + ".wal") on top of it. The log is opened first, so that the snapshot can't be replaced by another process while it is loaded.
+
+        */
+        let (log, records) = WriteAheadLog::open(&log_path(index_path), dimension, policy);
+        let mut index = if Path::new(index_path).is_file() {
+            let index = SimpleSearch::load_mmap(index_path);
+            assert_eq!(index.dimension(), dimension, "{} has a different number of dimensions than the embeddings", index_path);
+            assert_eq!(index.metric(), metric, "{} was saved with a different distance metric", index_path);
+            index
+        } else {
+            SimpleSearch::new(dimension, metric)
+        };
+
+        if !records.is_empty() {
+            println!("Replaying {} changes from the vector index log", records.len());
+        }
+        for record in records.into_iter() {
+            match record {
+                WalRecord::Upsert(id, point) => index.upsert(id, point),
+                WalRecord::Delete(id) => {
+                    index.delete(id);
+                }
+            }
+        }
+
+        DurableIndex { index, log, index_path: index_path.to_string() }
+    }
+
+    pub fn checkpoint(&mut self) {
+        /*
+
+            Save the index as a new snapshot and empty the log. Replaced and deleted points leave tombstones, which aren't saved, so instead of compacting (which would copy a mapped index into memory), the index is mapped again from the new snapshot. That also lets go of the points that were kept in memory since the last checkpoint.
+
+        */
+        self.index.save(&self.index_path);
+        self.index = SimpleSearch::load_mmap(&self.index_path);
+        self.log.truncate();
+    }
+
+    pub fn unsaved_changes(&self) -> usize {
+        // how many changes are only in the log, and not in the snapshot
+        self.log.record_count()
+    }
+
+    pub fn index(&self) -> &SimpleSearch {
+        &self.index
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.index.dimension()
+    }
+}
+
+pub fn log_path(index_path: &str) -> String {
+    format!("{}.wal", index_path)
+}
+
+impl VectorSearchClient for DurableIndex {
+    fn search_filtered(&self, query: &QueryVector, top_k: usize, filter: Option<&PayloadFilter>) -> Vec<VectorSearchResult<'_>> {
+        self.index.search_filtered(query, top_k, filter)
+    }
+    fn point_count(&self) -> usize {
+        self.index.point_count()
+    }
+    fn upsert(&mut self, id: PointId, point: PointVector) {
+        self.log.append_upsert(id, &point);
+        self.index.upsert(id, point);
+    }
+    fn delete(&mut self, id: PointId) -> bool {
+        self.log.append_delete(id);
+        self.index.delete(id)
+    }
+    fn tombstone_count(&self) -> usize {
+        self.index.tombstone_count()
+    }
+    fn compact(&mut self) {
+        // compacting doesn't change which points are in the index, so it doesn't need to be logged
+        self.index.compact();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::XorShift64;
+    use crate::test_support::TempPath;
+    use crate::vector_search::VectorPayload;
+    use std::collections::HashSet;
+    use std::fs;
+
+    fn point(id: u64, vec: Vec<f32>) -> PointVector {
+        PointVector { vec, payload: VectorPayload { page_url: format!("https://wikipedia.org/wiki/{}", id), tags: vec![id.to_string()], ..Default::default() } }
+    }
+
+    fn ids_of(index: &DurableIndex, query: &[f32]) -> Vec<u64> {
+        index.search(&QueryVector { vec: query.to_vec() }, 20).into_iter().map(|result| result.id).collect()
+    }
+
+    #[test]
+    fn fsync_policies_are_parsed() {
+        assert_eq!(FsyncPolicy::parse("always"), Some(FsyncPolicy::Always));
+        assert_eq!(FsyncPolicy::parse("Never"), Some(FsyncPolicy::Never));
+        assert_eq!(FsyncPolicy::parse("100"), Some(FsyncPolicy::EveryN(100)));
+        assert_eq!(FsyncPolicy::parse("0"), None);
+    }
+
+    #[test]
+    fn logged_changes_are_replayed_after_a_crash() {
+        // on top of the last snapshot, including when the last record was cut off partway through
+        let mut random = XorShift64::new(19);
+        let dimension = 16;
+        let temp_path = TempPath::new("vector_index_wal.bin");
+        let index_path = temp_path.path();
+        let query = random.vector(dimension);
+
+        // changes that were never saved are replayed from the log
+        let mut index = DurableIndex::open(index_path, dimension, DistanceMetric::Cosine, FsyncPolicy::Always);
+        for id in 0..300 {
+            index.upsert(id, point(id, random.vector(dimension)));
+        }
+        for id in (0..300).step_by(5) {
+            index.delete(id);
+        }
+        index.upsert(7, point(7, query.clone()));
+        let expected = ids_of(&index, &query);
+        assert_eq!(expected[0], 7);
+        drop(index);
+
+        let mut index = DurableIndex::open(index_path, dimension, DistanceMetric::Cosine, FsyncPolicy::EveryN(10));
+        assert_eq!(index.point_count(), 240);
+        assert_eq!(index.unsaved_changes(), 361);
+        assert_eq!(ids_of(&index, &query), expected);
+        assert_eq!(index.search(&QueryVector { vec: query.clone() }, 1)[0].payload.tags, vec!["7".to_string()]);
+
+        // a checkpoint saves a snapshot and empties the log, and later changes are replayed on top of the snapshot
+        index.checkpoint();
+        assert_eq!(index.unsaved_changes(), 0);
+        assert_eq!(index.tombstone_count(), 0);
+        assert_eq!(ids_of(&index, &query), expected);
+        assert_eq!(fs::metadata(log_path(index_path)).unwrap().len(), HEADER_LEN as u64);
+        for id in 300..350 {
+            index.upsert(id, point(id, random.vector(dimension)));
+        }
+        index.delete(7);
+        let expected = ids_of(&index, &query);
+        drop(index);
+
+        let index = DurableIndex::open(index_path, dimension, DistanceMetric::Cosine, FsyncPolicy::Never);
+        assert_eq!(index.point_count(), 289);
+        assert_eq!(index.unsaved_changes(), 51);
+        assert_eq!(ids_of(&index, &query), expected);
+
+        // a crash between saving the snapshot and emptying the log replays changes that are already in the snapshot, which doesn't change anything
+        index.index().save(index_path);
+        drop(index);
+        let mut index = DurableIndex::open(index_path, dimension, DistanceMetric::Cosine, FsyncPolicy::Always);
+        assert_eq!(index.point_count(), 289);
+        assert_eq!(ids_of(&index, &query), expected);
+
+        // a record that was cut off partway through is dropped, and new records are written after the last complete one
+        index.upsert(1000, point(1000, query.clone()));
+        drop(index);
+        let log_length = fs::metadata(log_path(index_path)).unwrap().len();
+        OpenOptions::new().write(true).open(log_path(index_path)).unwrap().set_len(log_length - 5).unwrap();
+
+        let mut index = DurableIndex::open(index_path, dimension, DistanceMetric::Cosine, FsyncPolicy::Always);
+        assert_eq!(index.point_count(), 289);
+        assert_eq!(ids_of(&index, &query), expected);
+        index.upsert(1001, point(1001, query.clone()));
+        drop(index);
+
+        let index = DurableIndex::open(index_path, dimension, DistanceMetric::Cosine, FsyncPolicy::Always);
+        assert_eq!(index.point_count(), 290);
+        assert_eq!(index.search(&QueryVector { vec: query.clone() }, 1)[0].id, 1001);
+        let ids: HashSet<u64> = ids_of(&index, &query).into_iter().collect();
+        assert!(!ids.contains(&1000) && !ids.contains(&7));
+    }
+
+    #[test]
+    #[should_panic(expected = "was saved with a different distance metric")]
+    fn a_snapshot_with_another_metric_is_refused() {
+        let temp_path = TempPath::new("vector_index_wal_metric.bin");
+        let mut index = DurableIndex::open(temp_path.path(), 4, DistanceMetric::L2, FsyncPolicy::Never);
+        index.upsert(1, point(1, vec![1.0, 2.0, 3.0, 4.0]));
+        index.checkpoint();
+        drop(index);
+        DurableIndex::open(temp_path.path(), 4, DistanceMetric::Cosine, FsyncPolicy::Never);
+    }
+
+    #[test]
+    #[should_panic(expected = "is already open in another process")]
+    fn an_index_can_only_be_open_once() {
+        let temp_path = TempPath::new("vector_index_wal_lock.bin");
+        let index = DurableIndex::open(temp_path.path(), 4, DistanceMetric::L2, FsyncPolicy::Never);
+        drop(index);
+
+        // the lock is let go of when the index is closed, but not before
+        let _index = DurableIndex::open(temp_path.path(), 4, DistanceMetric::L2, FsyncPolicy::Never);
+        DurableIndex::open(temp_path.path(), 4, DistanceMetric::L2, FsyncPolicy::Never);
+    }
+}
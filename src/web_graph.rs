@@ -1,6 +1,10 @@
 
+use bincode::{config, Decode, Encode};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use crate::atomic_file::write_atomic;
 
 /*
 
@@ -10,20 +14,20 @@ use std::collections::HashMap;
 
 */
 
-#[derive(Serialize, Deserialize)]
-struct WebPageNode {
+#[derive(Serialize, Deserialize, Decode, Encode)]
+pub struct WebPageNode {
     /*
 
     An object representation of a web page
     
     */
 
-    url: String,
-    linked_urls: Vec<String>,
+    pub url: String,
+    pub linked_urls: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct WebPageGraph {
+#[derive(Serialize, Deserialize, Decode, Encode)]
+pub struct WebPageGraph {
 
     /*
     
@@ -33,16 +37,156 @@ struct WebPageGraph {
     
     */
 
-    node_hashmap: HashMap<String, WebPageNode>,
+    pub node_hashmap: HashMap<String, WebPageNode>,
 }
 
+impl WebPageGraph {
+    pub fn new() -> WebPageGraph {
+        WebPageGraph {
+            node_hashmap: HashMap::new()
+        }
+    }
 
-// The web page graph is not going to be part of the crawler for now, but this is what the code for inserting the page into the web page graph looks like, in case it is added back. 
-/*
-// insert the url into the graph
-let url_node = WebPageNode {
-    url: url.to_string(),
-    linked_urls: relevant_links.clone(),
-};
-graph.node_hashmap.insert(url.to_string(), url_node);
-*/
\ No newline at end of file
+    pub fn insert_page(&mut self, url: &str, linked_urls: &[String]) {
+        /*
+        
+            Insert a crawled page into the graph, along with the urls of the pages it links to. A page that links to the same url more than once only gets one edge to it. If the page is already in the graph, its links are replaced.
+        
+        */
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut unique_links: Vec<String> = Vec::new();
+        for link in linked_urls.iter() {
+            if seen.insert(link.as_str()) {
+                unique_links.push(link.clone());
+            }
+        }
+
+        let url_node = WebPageNode {
+            url: url.to_string(),
+            linked_urls: unique_links,
+        };
+        self.node_hashmap.insert(url.to_string(), url_node);
+    }
+
+    pub fn remove_page(&mut self, url: &str) {
+        /*
+        
+            Remove a page from the graph, along with every link to it
+        
+        */
+        self.node_hashmap.remove(url);
+        for node in self.node_hashmap.values_mut() {
+            node.linked_urls.retain(|linked_url| linked_url != url);
+        }
+    }
+
+    pub fn add_link(&mut self, from_url: &str, to_url: &str) {
+        // Add a link between two pages, adding the page it comes from if it isn't in the graph yet
+        let node = self.node_hashmap.entry(from_url.to_string()).or_insert_with(|| WebPageNode {
+            url: from_url.to_string(),
+            linked_urls: Vec::new(),
+        });
+        if !node.linked_urls.iter().any(|linked_url| linked_url == to_url) {
+            node.linked_urls.push(to_url.to_string());
+        }
+    }
+
+    pub fn remove_link(&mut self, from_url: &str, to_url: &str) {
+        if let Some(node) = self.node_hashmap.get_mut(from_url) {
+            node.linked_urls.retain(|linked_url| linked_url != to_url);
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_hashmap.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.node_hashmap.values().map(|node| node.linked_urls.len()).sum()
+    }
+
+    pub fn bincode_save(&self, graph_path: &str) {
+        let bincode_config = config::standard();
+
+        // the graph is saved every few pages during a crawl, so it is replaced atomically, and a crash while saving leaves the last one
+        let encoded_graph : Vec<u8> = bincode::encode_to_vec(self, bincode_config).unwrap();
+        write_atomic(graph_path, &encoded_graph);
+
+        println!("Web page graph written to disk.")
+    }
+
+    pub fn bincode_load(graph_path: &str) -> WebPageGraph {
+        /*
+        
+            Load a graph that was saved by a previous crawl, so that it can be crawled further, ranked, or analyzed
+        
+        */
+        let bincode_config = config::standard();
+
+        let graph_binary = fs::read(graph_path).expect("Unable to read web page graph binary from disk");
+        let (graph, _) : (WebPageGraph, usize) = bincode::decode_from_slice(&graph_binary[..], bincode_config).expect("Unable to decode web page graph binary");
+
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempPath;
+
+    fn links(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn pages_link_to_each_url_once() {
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("a", &links(&["b", "c", "b", "a", "c"]));
+        assert_eq!(graph.node_hashmap["a"].linked_urls, links(&["b", "c", "a"]));
+        assert_eq!(graph.edge_count(), 3);
+
+        // inserting a page again replaces its links
+        graph.insert_page("a", &links(&["d"]));
+        assert_eq!(graph.node_hashmap["a"].linked_urls, links(&["d"]));
+        assert_eq!(graph.node_count(), 1);
+
+        graph.add_link("a", "d");
+        graph.add_link("e", "a");
+        assert_eq!(graph.node_hashmap["a"].linked_urls, links(&["d"]));
+        assert_eq!(graph.node_hashmap["e"].linked_urls, links(&["a"]));
+    }
+
+    #[test]
+    fn removing_a_page_removes_the_links_to_it() {
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("a", &links(&["b", "c"]));
+        graph.insert_page("b", &links(&["a", "c"]));
+        graph.insert_page("c", &links(&["a"]));
+        graph.remove_page("a");
+
+        assert_eq!(graph.node_count(), 2);
+        assert!(!graph.node_hashmap.contains_key("a"));
+        assert_eq!(graph.node_hashmap["b"].linked_urls, links(&["c"]));
+        assert!(graph.node_hashmap["c"].linked_urls.is_empty());
+
+        graph.remove_link("b", "c");
+        assert_eq!(graph.edge_count(), 0);
+    }
+
+    #[test]
+    fn saved_graphs_load_the_same() {
+        let temp_path = TempPath::new("web_graph.bin");
+        let mut graph = WebPageGraph::new();
+        graph.insert_page("https://a.org", &links(&["https://b.org", "https://c.org"]));
+        graph.insert_page("https://b.org", &links(&["https://a.org"]));
+        graph.bincode_save(temp_path.path());
+
+        let loaded = WebPageGraph::bincode_load(temp_path.path());
+        assert_eq!(loaded.node_count(), 2);
+        for (url, node) in graph.node_hashmap.iter() {
+            assert_eq!(loaded.node_hashmap[url].url, node.url);
+            assert_eq!(loaded.node_hashmap[url].linked_urls, node.linked_urls);
+        }
+    }
+}